@@ -0,0 +1,341 @@
+//! Runs redstone test scripts without a server or any players.
+//!
+//! A test script is a TOML or JSON file which loads a build, toggles levers, advances
+//! the plot by some number of ticks and then checks the state of blocks:
+//!
+//! ```toml
+//! # Either a schematic or a plot file, relative to the script. Positions in the
+//! # script are relative to the corner of the schematic or absolute for plot files.
+//! schematic = "adder.schem"
+//! # Simulate using the redpiler instead of the world
+//! redpiler = false
+//!
+//! [levers]
+//! a = [0, 1, 0]
+//!
+//! [[steps]]
+//! toggle = ["a"]
+//! ticks = 10
+//! expect = [{ pos = [4, 1, 0], block = "redstone_lamp[lit=true]" }]
+//! ```
+//!
+//! The process exits with 0 if every expectation held, 1 if any failed and 2 if
+//! the script couldn't be run at all.
+
+use mchprs::blocks::{Block, BlockPos};
+use mchprs::plot::worldedit::WorldEditClipboard;
+use mchprs::plot::Plot;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+
+#[derive(Deserialize)]
+struct TestScript {
+    schematic: Option<String>,
+    plot: Option<String>,
+    #[serde(default)]
+    redpiler: bool,
+    #[serde(default)]
+    levers: HashMap<String, [i32; 3]>,
+    steps: Vec<TestStep>,
+}
+
+#[derive(Deserialize)]
+struct TestStep {
+    #[serde(default)]
+    toggle: Vec<String>,
+    #[serde(default)]
+    ticks: u32,
+    #[serde(default)]
+    expect: Vec<Expectation>,
+}
+
+#[derive(Deserialize)]
+struct Expectation {
+    pos: [i32; 3],
+    block: String,
+}
+
+fn to_block_pos(pos: [i32; 3]) -> BlockPos {
+    BlockPos::new(pos[0], pos[1] as u32, pos[2])
+}
+
+fn load_script(path: &Path) -> Result<TestScript, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+        _ => toml::from_str(&contents).map_err(|e| e.to_string()),
+    }
+}
+
+/// Parses the plot coordinates out of a plot file name such as `p-1,3`.
+fn parse_plot_file_name(path: &Path) -> Option<(i32, i32)> {
+    let name = path.file_name()?.to_str()?.strip_prefix('p')?;
+    let mut coords = name.split(',');
+    let x = coords.next()?.parse().ok()?;
+    let z = coords.next()?.parse().ok()?;
+    Some((x, z))
+}
+
+/// The y to paste a schematic at so that its bottom ends up at y=0, as long as the
+/// paste position and the schematic both fit in the plot.
+fn paste_y(offset_y: i32, size_y: u32) -> Result<u32, String> {
+    if (0..256).contains(&offset_y) && size_y <= 256 {
+        Ok(offset_y as u32)
+    } else {
+        Err(format!(
+            "The schematic can't be pasted with a y offset of {} and a height of {}",
+            offset_y, size_y
+        ))
+    }
+}
+
+fn load_plot(script: &TestScript, base_dir: &Path) -> Result<Plot, String> {
+    match (&script.schematic, &script.plot) {
+        (Some(schematic), None) => {
+            let path = base_dir.join(schematic);
+            let clipboard = WorldEditClipboard::load_from_schematic_file(&path)
+                .ok_or_else(|| format!("Unable to load schematic {}", path.display()))?;
+            let mut plot = Plot::load_headless(0, 0, None).unwrap();
+            // Paste the schematic so that its corner ends up at 0,0,0
+            let pos = BlockPos::new(
+                clipboard.offset_x,
+                paste_y(clipboard.offset_y, clipboard.size_y)?,
+                clipboard.offset_z,
+            );
+            plot.paste_clipboard(&clipboard, pos);
+            Ok(plot)
+        }
+        (None, Some(plot_file)) => {
+            let path = base_dir.join(plot_file);
+            let (x, z) = parse_plot_file_name(&path)
+                .ok_or_else(|| format!("{} is not a plot file", path.display()))?;
+            let data =
+                fs::read(&path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+            Plot::load_headless(x, z, Some(data))
+        }
+        _ => Err("The script needs exactly one of `schematic` or `plot`".to_owned()),
+    }
+}
+
+/// The properties in the block state string of `block`, such as `powered=true`
+fn state_properties(block: Block) -> HashMap<String, String> {
+    let state = block.get_state_string().unwrap_or_default();
+    let properties = match state.find('[') {
+        Some(idx) => state[idx + 1..].trim_end_matches(']'),
+        None => "",
+    };
+    properties
+        .split(',')
+        .filter_map(|property| {
+            let mut split = property.split('=');
+            Some((split.next()?.to_owned(), split.next()?.to_owned()))
+        })
+        .collect()
+}
+
+/// Checks a block against a description like `repeater[powered=true]`. Only the
+/// properties that are given are compared. Properties the block doesn't have and
+/// values it can't take are errors, so that a typo can't make a check pass.
+fn block_matches(actual: Block, description: &str) -> Result<bool, String> {
+    let (name, properties) = match description.find('[') {
+        Some(idx) => (
+            &description[..idx],
+            description[idx + 1..].trim_end_matches(']'),
+        ),
+        None => (description, ""),
+    };
+    let name = name.trim_start_matches("minecraft:");
+    let expected = Block::from_name(name).ok_or_else(|| format!("Unknown block {}", name))?;
+    let mut expected_properties = Vec::new();
+    for property in properties.split(',').filter(|p| !p.is_empty()) {
+        let mut split = property.split('=');
+        let (key, value) = match (split.next(), split.next(), split.next()) {
+            (Some(key), Some(value), None) => (key, value),
+            _ => return Err(format!("Invalid property {}", property)),
+        };
+        if !state_properties(expected).contains_key(key) {
+            return Err(format!("{} has no property {}", name, key));
+        }
+        let mut with_value = expected;
+        with_value.set_property(key, value);
+        if state_properties(with_value)[key] != value {
+            return Err(format!("{} is not a valid value for {}", value, key));
+        }
+        expected_properties.push((key, value));
+    }
+
+    if mem::discriminant(&expected) != mem::discriminant(&actual) {
+        return Ok(false);
+    }
+    // Blocks without a state of their own can only be told apart by their id
+    if let Block::Solid(_) | Block::Transparent(_) = expected {
+        return Ok(expected == actual);
+    }
+    // Some blocks share a variant, such as pistons and sticky pistons
+    if expected.get_name() != actual.get_name() {
+        return Ok(false);
+    }
+    let actual_properties = state_properties(actual);
+    Ok(expected_properties
+        .iter()
+        .all(|(key, value)| actual_properties[*key] == *value))
+}
+
+/// Runs the script and returns the number of expectations that failed.
+fn run_script(script: &TestScript, plot: &mut Plot) -> Result<usize, String> {
+    if script.redpiler {
+        plot.compile_redpiler();
+    }
+    let mut failures = 0;
+    for (step_idx, step) in script.steps.iter().enumerate() {
+        for name in &step.toggle {
+            let pos = script
+                .levers
+                .get(name)
+                .ok_or_else(|| format!("Step {}: unknown lever {}", step_idx + 1, name))?;
+            let pos = to_block_pos(*pos);
+            if let Block::Lever(_) = plot.get_block(pos) {
+                plot.use_block(pos);
+            } else {
+                return Err(format!(
+                    "Step {}: lever {} is not at {:?}",
+                    step_idx + 1,
+                    name,
+                    pos
+                ));
+            }
+        }
+        for _ in 0..step.ticks {
+            plot.tick();
+        }
+        plot.flush_redpiler();
+        for expectation in &step.expect {
+            let pos = to_block_pos(expectation.pos);
+            let actual = plot.get_block(pos);
+            if !block_matches(actual, &expectation.block)? {
+                println!(
+                    "Step {}: expected {} at {:?} but found {:?}",
+                    step_idx + 1,
+                    expectation.block,
+                    expectation.pos,
+                    actual
+                );
+                failures += 1;
+            }
+        }
+    }
+    Ok(failures)
+}
+
+fn main() {
+    let paths: Vec<PathBuf> = std::env::args().skip(1).map(PathBuf::from).collect();
+    if paths.is_empty() {
+        eprintln!("Usage: mchprs-sim <script>...");
+        process::exit(2);
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let result = load_script(path).and_then(|script| {
+            let mut plot = load_plot(&script, base_dir)?;
+            run_script(&script, &mut plot)
+        });
+        match result {
+            Ok(0) => println!("{}: passed", path.display()),
+            Ok(failures) => {
+                println!("{}: {} expectations failed", path.display(), failures);
+                failed = true;
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                process::exit(2);
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+#[test]
+fn block_matches_test() {
+    let lever = Block::from_name("lever").unwrap();
+    let mut powered_lever = lever;
+    powered_lever.set_property("powered", "true");
+    assert_eq!(
+        block_matches(powered_lever, "lever[powered=true]"),
+        Ok(true)
+    );
+    assert_eq!(
+        block_matches(lever, "minecraft:lever[powered=true]"),
+        Ok(false)
+    );
+    assert_eq!(block_matches(lever, "lever"), Ok(true));
+    // Typos are errors instead of checks that always pass
+    assert!(block_matches(lever, "lever[powerd=true]").is_err());
+    assert!(block_matches(lever, "lever[powered=yes]").is_err());
+    assert!(block_matches(lever, "stone[powered=true]").is_err());
+    assert!(block_matches(lever, "levr").is_err());
+
+    let piston = Block::from_name("piston").unwrap();
+    assert_eq!(block_matches(piston, "sticky_piston"), Ok(false));
+    assert_eq!(block_matches(piston, "piston[extended=false]"), Ok(true));
+}
+
+#[test]
+fn paste_y_test() {
+    assert_eq!(paste_y(0, 10), Ok(0));
+    assert_eq!(paste_y(3, 10), Ok(3));
+    // A positive `WEOffsetY` gives a negative offset, which can't be pasted at
+    assert!(paste_y(-1, 10).is_err());
+    assert!(paste_y(256, 10).is_err());
+    assert!(paste_y(0, 257).is_err());
+}
+
+#[test]
+fn run_script_test() {
+    let script: TestScript = toml::from_str(
+        r#"
+        [levers]
+        a = [5, 10, 5]
+
+        [[steps]]
+        expect = [{ pos = [6, 10, 5], block = "redstone_lamp[lit=false]" }]
+
+        [[steps]]
+        toggle = ["a"]
+        ticks = 2
+        expect = [
+            { pos = [5, 10, 5], block = "lever[powered=true]" },
+            { pos = [6, 10, 5], block = "redstone_lamp[lit=true]" },
+            { pos = [6, 10, 5], block = "redstone_lamp[lit=false]" },
+        ]
+        "#,
+    )
+    .unwrap();
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let mut lever = Block::from_name("lever").unwrap();
+    lever.set_property("face", "floor");
+    plot.set_block(BlockPos::new(5, 10, 5), lever);
+    plot.set_block(
+        BlockPos::new(6, 10, 5),
+        Block::from_name("redstone_lamp").unwrap(),
+    );
+    // Only the last expectation doesn't hold
+    assert_eq!(run_script(&script, &mut plot), Ok(1));
+
+    let script: TestScript = toml::from_str(
+        r#"
+        [[steps]]
+        toggle = ["b"]
+        "#,
+    )
+    .unwrap();
+    assert!(run_script(&script, &mut plot).is_err());
+}
@@ -0,0 +1,479 @@
+//! Containers keep their items in their block entity. Hoppers move items between
+//! containers and droppers push items into the container they face when powered.
+
+use crate::blocks::{Block, BlockEntity, BlockFace, BlockFacing, BlockPos};
+use crate::items::Item;
+use crate::plot::{Plot, TickPriority};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// How many redstone ticks a hopper waits after moving an item, which is 8 game ticks
+const HOPPER_COOLDOWN: u32 = 4;
+/// How many redstone ticks a dropper waits after being powered before it fires
+const DROPPER_DELAY: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContainerType {
+    Furnace,
+    Barrel,
+    Hopper,
+    Dropper,
+}
+
+impl ContainerType {
+    pub fn from_block(block: Block) -> Option<ContainerType> {
+        match block {
+            Block::Container(3372) => Some(ContainerType::Furnace),
+            Block::Container(_) => Some(ContainerType::Barrel),
+            Block::Hopper(_) => Some(ContainerType::Hopper),
+            Block::Dropper(_) => Some(ContainerType::Dropper),
+            _ => None,
+        }
+    }
+
+    pub fn num_slots(self) -> u8 {
+        match self {
+            ContainerType::Furnace => 3,
+            ContainerType::Barrel => 27,
+            ContainerType::Hopper => 5,
+            ContainerType::Dropper => 9,
+        }
+    }
+
+    /// The slots that items coming in from `side` can go into. Furnaces take items to
+    /// smelt from the top and fuel from the sides.
+    fn insert_slots(self, side: BlockFace) -> Range<u8> {
+        match (self, side) {
+            (ContainerType::Furnace, BlockFace::Top) => 0..1,
+            (ContainerType::Furnace, BlockFace::Bottom) => 0..0,
+            (ContainerType::Furnace, _) => 1..2,
+            _ => 0..self.num_slots(),
+        }
+    }
+
+    /// The slots that hoppers below the container can take items out of
+    fn extract_slots(self) -> Range<u8> {
+        match self {
+            ContainerType::Furnace => 2..3,
+            _ => 0..self.num_slots(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryEntry {
+    pub id: u32,
+    pub slot: u8,
+    pub count: u8,
+    /// The item's tag in the binary NBT format
+    pub nbt: Option<Vec<u8>>,
+}
+
+impl InventoryEntry {
+    pub fn max_stack_size(&self) -> u8 {
+        Item::from_id(self.id).max_stack_size() as u8
+    }
+
+    fn stacks_with(&self, other: &InventoryEntry) -> bool {
+        self.id == other.id && self.nbt == other.nbt
+    }
+}
+
+/// Returns the signal a comparator reads from a container holding `inventory`.
+pub fn comparator_override(ty: ContainerType, inventory: &[InventoryEntry]) -> u8 {
+    if inventory.is_empty() {
+        return 0;
+    }
+    let fullness: f32 = inventory
+        .iter()
+        .map(|entry| entry.count as f32 / entry.max_stack_size() as f32)
+        .sum();
+    (fullness / ty.num_slots() as f32 * 14.0).floor() as u8 + 1
+}
+
+/// Puts one of `item` into the first slot out of `slots` which is empty or has room
+/// for it. Returns false if there is no room.
+fn insert_one(
+    inventory: &mut Vec<InventoryEntry>,
+    slots: Range<u8>,
+    item: &InventoryEntry,
+) -> bool {
+    for slot in slots {
+        match inventory.iter_mut().find(|entry| entry.slot == slot) {
+            Some(entry) if entry.stacks_with(item) && entry.count < entry.max_stack_size() => {
+                entry.count += 1;
+                return true;
+            }
+            Some(_) => {}
+            None => {
+                inventory.push(InventoryEntry {
+                    slot,
+                    count: 1,
+                    ..item.clone()
+                });
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Takes one item out of `slot`.
+fn take_one(inventory: &mut Vec<InventoryEntry>, slot: u8) -> Option<InventoryEntry> {
+    let idx = inventory.iter().position(|entry| entry.slot == slot)?;
+    let entry = &mut inventory[idx];
+    let item = InventoryEntry {
+        count: 1,
+        ..entry.clone()
+    };
+    entry.count -= 1;
+    if entry.count == 0 {
+        inventory.remove(idx);
+    }
+    Some(item)
+}
+
+/// Moves one item out of the first slot of `from_slots` that has an item which fits
+/// into `to`. Returns false if nothing could be moved.
+fn transfer_one(
+    from: &mut Vec<InventoryEntry>,
+    from_slots: Range<u8>,
+    to: &mut Vec<InventoryEntry>,
+    to_slots: Range<u8>,
+) -> bool {
+    for slot in from_slots {
+        let item = match from.iter().find(|entry| entry.slot == slot) {
+            Some(entry) => entry.clone(),
+            None => continue,
+        };
+        if insert_one(to, to_slots.clone(), &item) {
+            take_one(from, slot);
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns the type and items of the container at `pos`.
+pub fn get_inventory(plot: &Plot, pos: BlockPos) -> Option<(ContainerType, Vec<InventoryEntry>)> {
+    let ty = ContainerType::from_block(plot.get_block(pos))?;
+    let inventory = match plot.get_block_entity(pos) {
+        Some(BlockEntity::Container { inventory, .. }) => inventory.clone(),
+        _ => Vec::new(),
+    };
+    Some((ty, inventory))
+}
+
+/// Replaces the items of the container at `pos`. Comparators reading the container
+/// are updated if its output changed, and hoppers which might be able to move items
+/// now are woken up.
+pub fn set_inventory(
+    plot: &mut Plot,
+    pos: BlockPos,
+    ty: ContainerType,
+    inventory: Vec<InventoryEntry>,
+) {
+    let old_override = plot.get_block(pos).get_comparator_override(plot, pos);
+    let block_entity = BlockEntity::Container {
+        comparator_override: comparator_override(ty, &inventory),
+        inventory,
+        ty,
+        inert: is_inert(plot, pos),
+    };
+    let changed = match &block_entity {
+        BlockEntity::Container {
+            comparator_override,
+            ..
+        } => *comparator_override != old_override,
+        _ => false,
+    };
+    if changed {
+        plot.reset_redpiler();
+    }
+    plot.set_block_entity(pos, block_entity);
+    if changed {
+        update_comparators(plot, pos);
+    }
+    wake_hoppers(plot, pos);
+    plot.update_container_windows(pos);
+}
+
+/// Whether the container at `pos` is a hopper which was kept from moving items
+/// because it comes from a plot saved before hoppers did anything.
+fn is_inert(plot: &Plot, pos: BlockPos) -> bool {
+    matches!(
+        plot.get_block_entity(pos),
+        Some(BlockEntity::Container { inert: true, .. })
+    )
+}
+
+/// Updates the comparators next to the container at `pos` and the ones reading it
+/// through a solid block.
+fn update_comparators(plot: &mut Plot, pos: BlockPos) {
+    for &face in &BlockFace::values() {
+        if !face.is_horizontal() {
+            continue;
+        }
+        let mut comparator_pos = pos.offset(face);
+        if plot.get_block(comparator_pos).is_solid() {
+            comparator_pos = comparator_pos.offset(face);
+        }
+        let block = plot.get_block(comparator_pos);
+        if let Block::RedstoneComparator(_) = block {
+            block.update(plot, comparator_pos);
+        }
+    }
+}
+
+/// Schedules a transfer for the hoppers that could take items from or put items into
+/// the container at `pos`, unless they are already waiting for one.
+fn wake_hoppers(plot: &mut Plot, pos: BlockPos) {
+    let mut hoppers = vec![pos, pos.offset(BlockFace::Bottom)];
+    for &face in &BlockFace::values() {
+        let neighbor_pos = pos.offset(face);
+        if let Block::Hopper(hopper) = plot.get_block(neighbor_pos) {
+            if neighbor_pos.offset(hopper.facing.block_face()) == pos {
+                hoppers.push(neighbor_pos);
+            }
+        }
+    }
+    for hopper_pos in hoppers {
+        if let Block::Hopper(hopper) = plot.get_block(hopper_pos) {
+            if hopper.enabled && !plot.pending_tick_at(hopper_pos) {
+                plot.schedule_tick(hopper_pos, 1, TickPriority::Normal);
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hopper {
+    /// The direction items are pushed in, which is never up
+    pub facing: BlockFacing,
+    /// Hoppers are disabled while they are powered
+    pub enabled: bool,
+}
+
+impl Hopper {
+    /// The facings in the order of their block states
+    pub(super) const FACINGS: [BlockFacing; 5] = [
+        BlockFacing::Down,
+        BlockFacing::North,
+        BlockFacing::South,
+        BlockFacing::West,
+        BlockFacing::East,
+    ];
+
+    pub(super) fn new(facing: BlockFacing, enabled: bool) -> Hopper {
+        Hopper { facing, enabled }
+    }
+
+    pub(super) fn facing_id(self) -> u32 {
+        Hopper::FACINGS
+            .iter()
+            .position(|&facing| facing == self.facing)
+            .unwrap_or(0) as u32
+    }
+
+    /// Locks or unlocks the hopper depending on whether it is powered and wakes it up
+    /// in case something changed around it.
+    pub fn update(self, plot: &mut Plot, pos: BlockPos) {
+        if is_inert(plot, pos) {
+            return;
+        }
+        let enabled = !Block::redstone_lamp_should_be_lit(plot, pos);
+        if enabled != self.enabled {
+            plot.set_block(pos, Block::Hopper(Hopper { enabled, ..self }));
+        }
+        if enabled && !plot.pending_tick_at(pos) {
+            plot.schedule_tick(pos, 1, TickPriority::Normal);
+        }
+    }
+
+    /// Pushes an item into the container the hopper faces and pulls one out of the
+    /// container above it. After moving an item the hopper waits for its cooldown,
+    /// otherwise it waits until it gets woken up.
+    pub fn tick(self, plot: &mut Plot, pos: BlockPos) {
+        if !self.enabled {
+            return;
+        }
+        let (ty, mut inventory) = match get_inventory(plot, pos) {
+            Some(container) => container,
+            None => return,
+        };
+        let slots = 0..ty.num_slots();
+
+        let target_pos = pos.offset(self.facing.block_face());
+        let mut target = get_inventory(plot, target_pos);
+        let pushed = match &mut target {
+            Some((target_ty, target_inventory)) => {
+                let target_was_empty = target_inventory.is_empty();
+                let to_slots = target_ty.insert_slots(self.facing.opposite().block_face());
+                let pushed =
+                    transfer_one(&mut inventory, slots.clone(), target_inventory, to_slots);
+                // Hoppers that just got their first item wait for a whole cooldown
+                if pushed
+                    && target_was_empty
+                    && *target_ty == ContainerType::Hopper
+                    && !plot.pending_tick_at(target_pos)
+                {
+                    plot.schedule_tick(target_pos, HOPPER_COOLDOWN, TickPriority::Normal);
+                }
+                pushed
+            }
+            None => false,
+        };
+
+        let source_pos = pos.offset(BlockFace::Top);
+        let mut source = get_inventory(plot, source_pos);
+        let pulled = match &mut source {
+            Some((source_ty, source_inventory)) => {
+                let from_slots = source_ty.extract_slots();
+                transfer_one(source_inventory, from_slots, &mut inventory, slots)
+            }
+            None => false,
+        };
+
+        if !pushed && !pulled {
+            return;
+        }
+        // The cooldown has to be scheduled first so the hopper isn't woken up by its
+        // own transfer
+        plot.schedule_tick(pos, HOPPER_COOLDOWN, TickPriority::Normal);
+        if let (true, Some((target_ty, target_inventory))) = (pushed, target) {
+            set_inventory(plot, target_pos, target_ty, target_inventory);
+        }
+        if let (true, Some((source_ty, source_inventory))) = (pulled, source) {
+            set_inventory(plot, source_pos, source_ty, source_inventory);
+        }
+        set_inventory(plot, pos, ty, inventory);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dropper {
+    pub facing: BlockFacing,
+    /// Set while the dropper is powered so it only fires once per pulse
+    pub triggered: bool,
+}
+
+impl Dropper {
+    pub(super) fn new(facing: BlockFacing, triggered: bool) -> Dropper {
+        Dropper { facing, triggered }
+    }
+
+    /// Droppers can be powered through the block above them, like pistons.
+    pub fn update(self, plot: &mut Plot, pos: BlockPos) {
+        let powered = Block::redstone_lamp_should_be_lit(plot, pos)
+            || Block::redstone_lamp_should_be_lit(plot, pos.offset(BlockFace::Top));
+        if powered && !self.triggered {
+            plot.schedule_tick(pos, DROPPER_DELAY, TickPriority::Normal);
+            plot.set_block(pos, Block::Dropper(Dropper::new(self.facing, true)));
+        } else if !powered && self.triggered {
+            plot.set_block(pos, Block::Dropper(Dropper::new(self.facing, false)));
+        }
+    }
+
+    /// Moves an item out of a random slot into the container the dropper faces.
+    /// Without a container in front of it, the dropper keeps its items since there
+    /// are no item entities to drop.
+    pub fn tick(self, plot: &mut Plot, pos: BlockPos) {
+        let (ty, mut inventory) = match get_inventory(plot, pos) {
+            Some(container) => container,
+            None => return,
+        };
+        let target_pos = pos.offset(self.facing.block_face());
+        let (target_ty, mut target) = match get_inventory(plot, target_pos) {
+            Some(container) => container,
+            None => return,
+        };
+        if inventory.is_empty() {
+            return;
+        }
+        let slot = inventory[rand::thread_rng().gen_range(0, inventory.len())].slot;
+        let to_slots = target_ty.insert_slots(self.facing.opposite().block_face());
+        if transfer_one(&mut inventory, slot..slot + 1, &mut target, to_slots) {
+            set_inventory(plot, target_pos, target_ty, target);
+            set_inventory(plot, pos, ty, inventory);
+        }
+    }
+}
+
+#[test]
+fn hopper_transfer_test() {
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let source_pos = BlockPos::new(5, 11, 5);
+    let hopper_pos = BlockPos::new(5, 10, 5);
+    let target_pos = BlockPos::new(6, 10, 5);
+    plot.set_block(source_pos, Block::Container(11136));
+    plot.set_block(target_pos, Block::Container(11136));
+    let hopper = Hopper::new(BlockFacing::East, true);
+    Block::Hopper(hopper).place_in_plot(&mut plot, hopper_pos, &None);
+    let redstone = InventoryEntry {
+        id: 600,
+        slot: 0,
+        count: 2,
+        nbt: None,
+    };
+    set_inventory(&mut plot, source_pos, ContainerType::Barrel, vec![redstone]);
+
+    // The first item gets pulled in, then pushed out while the second one is pulled
+    for _ in 0..HOPPER_COOLDOWN + 1 {
+        plot.tick();
+    }
+    let count = |plot: &Plot, pos| {
+        get_inventory(plot, pos)
+            .unwrap()
+            .1
+            .iter()
+            .map(|entry| entry.count)
+            .sum::<u8>()
+    };
+    assert_eq!(count(&plot, source_pos), 0);
+    assert_eq!(count(&plot, hopper_pos), 1);
+    assert_eq!(count(&plot, target_pos), 1);
+    assert_eq!(
+        plot.get_block(target_pos)
+            .get_comparator_override(&plot, target_pos),
+        1
+    );
+
+    // Powered hoppers are locked
+    plot.set_block(BlockPos::new(4, 10, 5), Block::RedstoneBlock);
+    Block::update_surrounding_blocks(&mut plot, BlockPos::new(4, 10, 5));
+    for _ in 0..HOPPER_COOLDOWN * 2 {
+        plot.tick();
+    }
+    assert_eq!(count(&plot, hopper_pos), 1);
+}
+
+#[test]
+fn comparator_override_stack_size_test() {
+    let entry = |id, slot, count| InventoryEntry {
+        id,
+        slot,
+        count,
+        nbt: None,
+    };
+    // A hopper full of swords is as full as it gets
+    let swords: Vec<_> = (0..5).map(|slot| entry(533, slot, 1)).collect();
+    assert_eq!(comparator_override(ContainerType::Hopper, &swords), 15);
+    // One sword fills a fifth of a hopper
+    assert_eq!(comparator_override(ContainerType::Hopper, &swords[..1]), 3);
+    // A barrel full of 16-stacks of ender pearls and signs
+    let stacks: Vec<_> = (0..27)
+        .map(|slot| entry(if slot % 2 == 0 { 682 } else { 589 }, slot, 16))
+        .collect();
+    assert_eq!(comparator_override(ContainerType::Barrel, &stacks), 15);
+    // Half a stack of buckets in a dropper
+    assert_eq!(
+        comparator_override(ContainerType::Dropper, &[entry(595, 0, 8)]),
+        1
+    );
+    // Music discs in a barrel
+    let discs: Vec<_> = (0..12)
+        .map(|slot| entry(841 + slot as u32, slot, 1))
+        .collect();
+    assert_eq!(comparator_override(ContainerType::Barrel, &discs), 7);
+}
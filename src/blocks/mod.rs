@@ -1,8 +1,13 @@
+mod container;
 mod redstone;
 
 use crate::items::{ActionResult, Item, UseOnBlockContext};
 use crate::plot::{Plot, TickPriority};
+pub use container::{get_inventory, set_inventory, ContainerType, InventoryEntry};
+use container::{Dropper, Hopper};
+use log::warn;
 use redstone::*;
+pub use redstone::{ButtonFace, ComparatorMode, LeverFace, RedstoneWire};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -13,9 +18,25 @@ pub struct SignBlockEntity {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BlockEntity {
-    Comparator { output_strength: u8 },
-    Container { comparator_override: u8 },
+    Comparator {
+        output_strength: u8,
+    },
+    /// Containers from plots saved before containers kept their items. These are
+    /// turned into `Container`s when the chunk loads.
+    LegacyContainer {
+        comparator_override: u8,
+    },
     Sign(Box<SignBlockEntity>),
+    MovingPiston(MovingPistonEntity),
+    Container {
+        /// Kept up to date with the inventory so comparators don't have to count items
+        comparator_override: u8,
+        inventory: Vec<InventoryEntry>,
+        ty: ContainerType,
+        /// Set on hoppers from plots saved before hoppers moved items. They stay plain
+        /// containers so that old builds keep working the way they did.
+        inert: bool,
+    },
 }
 
 macro_rules! nbt_unwrap_val {
@@ -30,22 +51,103 @@ macro_rules! nbt_unwrap_val {
 }
 
 impl BlockEntity {
-    fn load_container(slots_nbt: &[nbt::Value], num_slots: u8) -> Option<BlockEntity> {
+    /// Creates the block entity of a container, working out its comparator output.
+    pub fn container(ty: ContainerType, inventory: Vec<InventoryEntry>) -> BlockEntity {
+        BlockEntity::Container {
+            comparator_override: container::comparator_override(ty, &inventory),
+            inventory,
+            ty,
+            inert: false,
+        }
+    }
+
+    /// The block entity of a hopper from a plot saved before hoppers moved items
+    pub fn legacy_hopper(block_entity: Option<BlockEntity>) -> BlockEntity {
+        let inventory = match block_entity {
+            Some(BlockEntity::Container { inventory, .. }) => inventory,
+            _ => Vec::new(),
+        };
+        BlockEntity::Container {
+            comparator_override: container::comparator_override(ContainerType::Hopper, &inventory),
+            inventory,
+            ty: ContainerType::Hopper,
+            inert: true,
+        }
+    }
+
+    /// Fills a container with just enough redstone dust to give `comparator_override`.
+    fn container_with_redstone(ty: ContainerType, comparator_override: u8) -> BlockEntity {
+        let num_slots = ty.num_slots() as u32;
+        let mut items_left =
+            ((comparator_override.max(1) - 1) as u32 * num_slots * 64).div_ceil(14);
+        let mut inventory = Vec::new();
+        for slot in 0..num_slots {
+            if items_left == 0 {
+                break;
+            }
+            let count = items_left.min(64);
+            items_left -= count;
+            inventory.push(InventoryEntry {
+                id: 600,
+                slot: slot as u8,
+                count: count as u8,
+                nbt: None,
+            });
+        }
+        BlockEntity::container(ty, inventory)
+    }
+
+    /// Turns block entities from plots saved by older versions into their current form.
+    pub fn upgrade(self, block: Block) -> BlockEntity {
+        match self {
+            BlockEntity::LegacyContainer {
+                comparator_override,
+            } => {
+                let ty = ContainerType::from_block(block).unwrap_or(ContainerType::Barrel);
+                BlockEntity::container_with_redstone(ty, comparator_override)
+            }
+            block_entity => block_entity,
+        }
+    }
+
+    /// Items with names that aren't known are left out with a warning.
+    fn load_container(slots_nbt: &[nbt::Value], ty: ContainerType) -> Option<BlockEntity> {
         use nbt::Value;
-        let mut fullness_sum: f32 = 0.0;
+        let mut inventory = Vec::new();
         for item in slots_nbt {
             let item_compound = nbt_unwrap_val!(item, Value::Compound);
             let count = nbt_unwrap_val!(item_compound["Count"], Value::Byte);
+            let slot = nbt_unwrap_val!(item_compound["Slot"], Value::Byte);
             let namespaced_name = nbt_unwrap_val!(
                 item_compound.get("Id").or(item_compound.get("id"))?,
                 Value::String
             );
-            let item_type = Item::from_name(namespaced_name.split(':').last()?);
-            fullness_sum += count as f32 / item_type.map(Item::max_stack_size).unwrap_or(64) as f32;
+            if count <= 0 || slot < 0 || slot as u8 >= ty.num_slots() {
+                continue;
+            }
+            let item_type = match Item::from_name(namespaced_name.split(':').last()?) {
+                Some(item_type) => item_type,
+                None => {
+                    warn!("Skipping unknown item {} in a container", namespaced_name);
+                    continue;
+                }
+            };
+            let nbt = match item_compound.get("tag") {
+                Some(tag @ Value::Compound(_)) => {
+                    let mut data = vec![0x0a, 0, 0];
+                    tag.to_writer(&mut data).ok()?;
+                    Some(data)
+                }
+                _ => None,
+            };
+            inventory.push(InventoryEntry {
+                id: item_type.get_id(),
+                slot: slot as u8,
+                count: count as u8,
+                nbt,
+            });
         }
-        Some(BlockEntity::Container {
-            comparator_override: (1.0 + (fullness_sum / num_slots as f32) * 14.0).floor() as u8,
-        })
+        Some(BlockEntity::container(ty, inventory))
     }
 
     pub fn from_nbt(nbt: &HashMap<String, nbt::Value>) -> Option<BlockEntity> {
@@ -55,15 +157,22 @@ impl BlockEntity {
             "minecraft:comparator" => Some(BlockEntity::Comparator {
                 output_strength: *nbt_unwrap_val!(&nbt["OutputSignal"], Value::Int) as u8,
             }),
-            "minecraft:furnace" => {
-                BlockEntity::load_container(nbt_unwrap_val!(&nbt["Items"], Value::List), 3)
-            }
-            "minecraft:barrel" => {
-                BlockEntity::load_container(nbt_unwrap_val!(&nbt["Items"], Value::List), 27)
-            }
-            "minecraft:hopper" => {
-                BlockEntity::load_container(nbt_unwrap_val!(&nbt["Items"], Value::List), 5)
-            }
+            "minecraft:furnace" => BlockEntity::load_container(
+                nbt_unwrap_val!(&nbt["Items"], Value::List),
+                ContainerType::Furnace,
+            ),
+            "minecraft:barrel" => BlockEntity::load_container(
+                nbt_unwrap_val!(&nbt["Items"], Value::List),
+                ContainerType::Barrel,
+            ),
+            "minecraft:hopper" => BlockEntity::load_container(
+                nbt_unwrap_val!(&nbt["Items"], Value::List),
+                ContainerType::Hopper,
+            ),
+            "minecraft:dropper" => BlockEntity::load_container(
+                nbt_unwrap_val!(&nbt["Items"], Value::List),
+                ContainerType::Dropper,
+            ),
             "minecraft:sign" => Some({
                 BlockEntity::Sign(Box::new(SignBlockEntity {
                     rows: [
@@ -79,6 +188,70 @@ impl BlockEntity {
         }
     }
 
+    /// Creates the compound used in the `BlockEntities` list of Sponge schematics,
+    /// without the position. Items without a known name are left out with a warning.
+    pub fn to_schematic_nbt(&self, block: Block) -> Option<HashMap<String, nbt::Value>> {
+        use nbt::Value;
+        let mut nbt = HashMap::new();
+        match self {
+            BlockEntity::Comparator { output_strength } => {
+                nbt.insert(
+                    "Id".to_owned(),
+                    Value::String("minecraft:comparator".to_owned()),
+                );
+                nbt.insert(
+                    "OutputSignal".to_owned(),
+                    Value::Int(*output_strength as i32),
+                );
+            }
+            BlockEntity::Container { inventory, .. } => {
+                let mut items = Vec::new();
+                for entry in inventory {
+                    let name = match Item::from_id(entry.id).get_name() {
+                        Some(name) => name,
+                        None => {
+                            warn!("Skipping item with unknown name (id {})", entry.id);
+                            continue;
+                        }
+                    };
+                    let mut item = HashMap::new();
+                    item.insert("Slot".to_owned(), Value::Byte(entry.slot as i8));
+                    item.insert(
+                        "id".to_owned(),
+                        Value::String(format!("minecraft:{}", name)),
+                    );
+                    item.insert("Count".to_owned(), Value::Byte(entry.count as i8));
+                    if let Some(data) = &entry.nbt {
+                        // Skip the root compound's tag id and name
+                        let name_len = data
+                            .get(1..3)
+                            .map(|len| u16::from_be_bytes([len[0], len[1]]));
+                        let tag = name_len
+                            .and_then(|len| data.get(3 + len as usize..))
+                            .and_then(|mut payload| Value::from_reader(0x0a, &mut payload).ok());
+                        if let Some(tag) = tag {
+                            item.insert("tag".to_owned(), tag);
+                        }
+                    }
+                    items.push(Value::Compound(item));
+                }
+                nbt.insert(
+                    "Id".to_owned(),
+                    Value::String(format!("minecraft:{}", block.get_name()?)),
+                );
+                nbt.insert("Items".to_owned(), Value::List(items));
+            }
+            BlockEntity::Sign(sign) => {
+                for (i, row) in sign.rows.iter().enumerate() {
+                    nbt.insert(format!("Text{}", i + 1), Value::String(row.clone()));
+                }
+                nbt.insert("Id".to_owned(), Value::String("minecraft:sign".to_owned()));
+            }
+            BlockEntity::LegacyContainer { .. } | BlockEntity::MovingPiston(_) => return None,
+        }
+        Some(nbt)
+    }
+
     pub fn to_nbt(&self, pos: BlockPos) -> Option<nbt::Blob> {
         use nbt::Value;
         let blob = match self {
@@ -164,7 +337,7 @@ pub enum BlockDirection {
 }
 
 impl BlockDirection {
-    fn opposite(self) -> BlockDirection {
+    pub fn opposite(self) -> BlockDirection {
         use BlockDirection::*;
         match self {
             North => South,
@@ -218,6 +391,15 @@ impl BlockDirection {
         }
     }
 
+    fn to_str(self) -> &'static str {
+        match self {
+            BlockDirection::North => "north",
+            BlockDirection::South => "south",
+            BlockDirection::East => "east",
+            BlockDirection::West => "west",
+        }
+    }
+
     fn get_id(self) -> u32 {
         match self {
             BlockDirection::North => 0,
@@ -227,7 +409,7 @@ impl BlockDirection {
         }
     }
 
-    fn rotate(self) -> BlockDirection {
+    pub fn rotate(self) -> BlockDirection {
         use BlockDirection::*;
         match self {
             North => East,
@@ -237,7 +419,7 @@ impl BlockDirection {
         }
     }
 
-    fn rotate_ccw(self) -> BlockDirection {
+    pub fn rotate_ccw(self) -> BlockDirection {
         use BlockDirection::*;
         match self {
             North => West,
@@ -278,7 +460,7 @@ impl BlockFace {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BlockFacing {
     North,
     East,
@@ -322,6 +504,40 @@ impl BlockFacing {
             _ => BlockFacing::Down,
         }
     }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            BlockFacing::North => "north",
+            BlockFacing::South => "south",
+            BlockFacing::East => "east",
+            BlockFacing::West => "west",
+            BlockFacing::Up => "up",
+            BlockFacing::Down => "down",
+        }
+    }
+
+    pub fn opposite(self) -> BlockFacing {
+        use BlockFacing::*;
+        match self {
+            North => South,
+            South => North,
+            East => West,
+            West => East,
+            Up => Down,
+            Down => Up,
+        }
+    }
+
+    pub fn block_face(self) -> BlockFace {
+        match self {
+            BlockFacing::North => BlockFace::North,
+            BlockFacing::South => BlockFace::South,
+            BlockFacing::East => BlockFace::East,
+            BlockFacing::West => BlockFace::West,
+            BlockFacing::Up => BlockFace::Top,
+            BlockFacing::Down => BlockFace::Bottom,
+        }
+    }
 }
 
 impl Default for BlockFacing {
@@ -336,7 +552,19 @@ impl BlockFace {
         [Top, Bottom, North, South, East, West]
     }
 
-    fn is_horizontal(self) -> bool {
+    pub fn opposite(self) -> BlockFace {
+        use BlockFace::*;
+        match self {
+            Top => Bottom,
+            Bottom => Top,
+            North => South,
+            South => North,
+            East => West,
+            West => East,
+        }
+    }
+
+    pub fn is_horizontal(self) -> bool {
         use BlockFace::*;
         match self {
             North | South | East | West => true,
@@ -344,7 +572,7 @@ impl BlockFace {
         }
     }
 
-    fn to_direction(self) -> BlockDirection {
+    pub fn to_direction(self) -> BlockDirection {
         match self {
             BlockFace::North => BlockDirection::North,
             BlockFace::South => BlockDirection::South,
@@ -367,9 +595,15 @@ pub enum Block {
     Lever(Lever),
     RedstoneBlock,
     Container(u32),
-    PressurePlate(u32),
-    TripwireHook(BlockDirection),
-    Observer(BlockFacing),
+    Hopper(Hopper),
+    Dropper(Dropper),
+    PressurePlate(PressurePlate),
+    TripwireHook(TripwireHook),
+    Tripwire(Tripwire),
+    Observer(BlockFacing, bool),
+    Piston(Piston),
+    PistonHead(PistonHead),
+    MovingPiston(BlockFacing, bool),
     SeaPickle(u8),
     Sign(u32, u32),
     WallSign(u32, BlockDirection),
@@ -378,26 +612,113 @@ pub enum Block {
     StoneButton(StoneButton),
 }
 
+/// Bedrock panics when it is ticked in tests, so that they can crash a plot.
+#[cfg(test)]
+pub const CRASHING_BLOCK_ID: u32 = 33;
+
+/// Blocks which are fully described by their name, along with their block state id.
+const SIMPLE_BLOCKS: &[(&str, u32)] = &[
+    ("stone", 1),
+    ("air", 0),
+    ("glass", 230),
+    ("quartz_slab", 7855),
+    ("smooth_stone_slab", 7807),
+    ("quartz_block", 6202),
+    ("sandstone", 245),
+    ("white_wool", 1383),
+    ("orange_wool", 1384),
+    ("magenta_wool", 1385),
+    ("light_blue_wool", 1386),
+    ("yellow_wool", 1387),
+    ("lime_wool", 1388),
+    ("pink_wool", 1389),
+    ("gray_wool", 1390),
+    ("light_gray_wool", 1391),
+    ("cyan_wool", 1392),
+    ("purple_wool", 1393),
+    ("blue_wool", 1394),
+    ("brown_wool", 1395),
+    ("green_wool", 1396),
+    ("red_wool", 1397),
+    ("black_wool", 1398),
+    ("iron_block", 1427),
+    ("stone_bricks", 4481),
+    ("white_terracotta", 6311),
+    ("orange_terracotta", 6312),
+    ("magenta_terracotta", 6313),
+    ("light_blue_terracotta", 6314),
+    ("yellow_terracotta", 6315),
+    ("lime_terracotta", 6316),
+    ("pink_terracotta", 6317),
+    ("gray_terracotta", 6318),
+    ("light_gray_terracotta", 6319),
+    ("cyan_terracotta", 6320),
+    ("purple_terracotta", 6321),
+    ("blue_terracotta", 6322),
+    ("brown_terracotta", 6323),
+    ("green_terracotta", 6324),
+    ("red_terracotta", 6325),
+    ("black_terracotta", 6326),
+    ("white_concrete", 8902),
+    ("orange_concrete", 8903),
+    ("magenta_concrete", 8904),
+    ("light_blue_concrete", 8905),
+    ("yellow_concrete", 8906),
+    ("lime_concrete", 8907),
+    ("pink_concrete", 8908),
+    ("gray_concrete", 8909),
+    ("light_gray_concrete", 8910),
+    ("cyan_concrete", 8911),
+    ("purple_concrete", 8912),
+    ("blue_concrete", 8913),
+    ("brown_concrete", 8914),
+    ("green_concrete", 8915),
+    ("red_concrete", 8916),
+    ("black_concrete", 8917),
+    ("redstone_block", 6190),
+    ("furnace", 3372),
+    ("barrel", 11136),
+    ("slime_block", 6999),
+    ("honey_block", 11335),
+    ("obsidian", 1433),
+    ("bedrock", 33),
+];
+
+/// The names of the wooden pressure plates, indexed by their wood type
+const WOODEN_PRESSURE_PLATES: [&str; 6] = [
+    "oak_pressure_plate",
+    "spruce_pressure_plate",
+    "birch_pressure_plate",
+    "jungle_pressure_plate",
+    "acacia_pressure_plate",
+    "dark_oak_pressure_plate",
+];
+
 impl Block {
     fn has_block_entity(self) -> bool {
         match self {
-            Block::RedstoneComparator(_) | Block::Container(_) => true,
+            Block::RedstoneComparator(_)
+            | Block::Container(_)
+            | Block::Hopper(_)
+            | Block::Dropper(_)
+            | Block::MovingPiston(_, _) => true,
             _ => false,
         }
     }
 
-    fn has_comparator_override(self) -> bool {
+    pub fn has_comparator_override(self) -> bool {
         match self {
-            Block::Container(_) => true,
+            Block::Container(_) | Block::Hopper(_) | Block::Dropper(_) => true,
             _ => false,
         }
     }
 
-    fn get_comparator_override(self, plot: &Plot, pos: BlockPos) -> u8 {
+    pub fn get_comparator_override(self, plot: &Plot, pos: BlockPos) -> u8 {
         match self {
-            Block::Container(_) => {
+            Block::Container(_) | Block::Hopper(_) | Block::Dropper(_) => {
                 if let Some(BlockEntity::Container {
                     comparator_override,
+                    ..
                 }) = plot.get_block_entity(pos)
                 {
                     *comparator_override
@@ -409,20 +730,18 @@ impl Block {
         }
     }
 
-    fn is_transparent(self) -> bool {
-        match self {
-            Block::Transparent(_) | Block::RedstoneBlock | Block::Container(6198) => true,
-            _ => false,
-        }
+    pub fn is_transparent(self) -> bool {
+        matches!(
+            self,
+            Block::Transparent(_) | Block::RedstoneBlock | Block::Hopper(_)
+        )
     }
 
-    fn is_solid(self) -> bool {
-        match self {
-            Block::RedstoneLamp(_) | Block::Solid(_) => true,
-            // Hoppers are transparent
-            Block::Container(id) if id != 6198 => true,
-            _ => false,
-        }
+    pub fn is_solid(self) -> bool {
+        matches!(
+            self,
+            Block::RedstoneLamp(_) | Block::Solid(_) | Block::Container(_) | Block::Dropper(_)
+        )
     }
 
     pub fn is_cube(self) -> bool {
@@ -431,13 +750,16 @@ impl Block {
             | Block::Transparent(_)
             | Block::RedstoneBlock
             | Block::Container(_)
-            | Block::Observer(_)
+            | Block::Hopper(_)
+            | Block::Dropper(_)
+            | Block::Observer(_, _)
+            | Block::Piston(_)
             | Block::RedstoneLamp(_) => true,
             _ => false,
         }
     }
 
-    fn is_diode(self) -> bool {
+    pub fn is_diode(self) -> bool {
         match self {
             Block::RedstoneRepeater(_) | Block::RedstoneComparator(_) => true,
             _ => false,
@@ -466,6 +788,33 @@ impl Block {
             0 => Block::Air,
             // Glass
             230 => Block::Transparent(id),
+            // Sticky Piston
+            1328..=1339 => {
+                let id = id - 1328;
+                let extended = id < 6;
+                let facing = BlockFacing::from_id(id % 6);
+                Block::Piston(Piston::new(true, facing, extended))
+            }
+            // Piston
+            1347..=1358 => {
+                let id = id - 1347;
+                let extended = id < 6;
+                let facing = BlockFacing::from_id(id % 6);
+                Block::Piston(Piston::new(false, facing, extended))
+            }
+            // Piston Head
+            1359..=1382 => {
+                let id = id - 1359;
+                let sticky = (id & 1) == 1;
+                let short = ((id >> 1) & 1) == 0;
+                let facing = BlockFacing::from_id(id >> 2);
+                Block::PistonHead(PistonHead::new(sticky, facing, short))
+            }
+            // Moving Piston
+            1399..=1410 => {
+                let id = id - 1399;
+                Block::MovingPiston(BlockFacing::from_id(id >> 1), (id & 1) == 1)
+            }
             // Redstone Wire
             2056..=3351 => {
                 let id = id - 2056;
@@ -505,7 +854,16 @@ impl Block {
                 Block::StoneButton(StoneButton::new(face, facing, powered))
             }
             // Stone Pressure Plate
-            3806 => Block::PressurePlate(id),
+            3805..=3806 => {
+                let power = if id == 3805 { 15 } else { 0 };
+                Block::PressurePlate(PressurePlate::new(PressurePlateKind::Stone, power))
+            }
+            // Wooden Pressure Plates
+            3871..=3882 => {
+                let id = id - 3871;
+                let power = if (id & 1) == 0 { 15 } else { 0 };
+                Block::PressurePlate(PressurePlate::new(PressurePlateKind::Wood(id >> 1), power))
+            }
             // Redstone Torch
             3885 => Block::RedstoneTorch(true),
             3886 => Block::RedstoneTorch(false),
@@ -528,11 +886,27 @@ impl Block {
             // Redstone Lamp
             5140 => Block::RedstoneLamp(true),
             5141 => Block::RedstoneLamp(false),
-            // Tripwire Hooks
-            5252 => Block::TripwireHook(BlockDirection::North),
-            5254 => Block::TripwireHook(BlockDirection::South),
-            5256 => Block::TripwireHook(BlockDirection::West),
-            5258 => Block::TripwireHook(BlockDirection::East),
+            // Tripwire Hook
+            5243..=5258 => {
+                let id = id - 5243;
+                let attached = (id >> 3) == 0;
+                let facing = BlockDirection::from_id((id >> 1) & 0b11);
+                let powered = (id & 1) == 0;
+                Block::TripwireHook(TripwireHook::new(facing, attached, powered))
+            }
+            // Tripwire
+            5259..=5386 => {
+                let id = id - 5259;
+                Block::Tripwire(Tripwire {
+                    attached: (id >> 6) & 1 == 0,
+                    disarmed: (id >> 5) & 1 == 0,
+                    east: (id >> 4) & 1 == 0,
+                    north: (id >> 3) & 1 == 0,
+                    powered: (id >> 2) & 1 == 0,
+                    south: (id >> 1) & 1 == 0,
+                    west: id & 1 == 0,
+                })
+            }
             // Redstone Comparator
             6142..=6157 => {
                 let id = id - 6142;
@@ -541,18 +915,36 @@ impl Block {
                 let facing = BlockDirection::from_id(id >> 2);
                 Block::RedstoneComparator(RedstoneComparator::new(facing, mode, powered))
             }
+            // Weighted Pressure Plates
+            6110..=6125 => Block::PressurePlate(PressurePlate::new(
+                PressurePlateKind::LightWeighted,
+                (id - 6110) as u8,
+            )),
+            6126..=6141 => Block::PressurePlate(PressurePlate::new(
+                PressurePlateKind::HeavyWeighted,
+                (id - 6126) as u8,
+            )),
             6190 => Block::RedstoneBlock,
             // Hopper
-            6198 => Block::Container(id),
+            6192..=6201 => {
+                let id = id - 6192;
+                Block::Hopper(Hopper::new(Hopper::FACINGS[(id % 5) as usize], id < 5))
+            }
+            // Dropper
+            6299..=6310 => {
+                let id = id - 6299;
+                Block::Dropper(Dropper::new(BlockFacing::from_id(id >> 1), (id & 1) == 0))
+            }
             // Smooth Stone Slab
             7807 => Block::Transparent(id),
             // Quartz Slab
             7855 => Block::Transparent(id),
             // Observer
-            8725..=8735 => {
-                let id = id - 8725;
+            8724..=8735 => {
+                let id = id - 8724;
+                let powered = (id & 1) == 0;
                 let facing = BlockFacing::from_id(id >> 1);
-                Block::Observer(facing)
+                Block::Observer(facing, powered)
             }
             // Sea Pickles
             9105..=9111 => Block::SeaPickle(((id - 9105) >> 1) as u8 + 1),
@@ -598,12 +990,22 @@ impl Block {
             }
             Block::RedstoneLamp(true) => 5140,
             Block::RedstoneLamp(false) => 5141,
-            // I might make tripwire calculate id at some point,
-            // This is easier though
-            Block::TripwireHook(BlockDirection::North) => 5252,
-            Block::TripwireHook(BlockDirection::South) => 5254,
-            Block::TripwireHook(BlockDirection::West) => 5256,
-            Block::TripwireHook(BlockDirection::East) => 5258,
+            Block::TripwireHook(hook) => {
+                (!hook.attached as u32 * 8)
+                    + (hook.facing.get_id() << 1)
+                    + !hook.powered as u32
+                    + 5243
+            }
+            Block::Tripwire(tripwire) => {
+                (!tripwire.attached as u32 * 64)
+                    + (!tripwire.disarmed as u32 * 32)
+                    + (!tripwire.east as u32 * 16)
+                    + (!tripwire.north as u32 * 8)
+                    + (!tripwire.powered as u32 * 4)
+                    + (!tripwire.south as u32 * 2)
+                    + !tripwire.west as u32
+                    + 5259
+            }
             Block::RedstoneComparator(comparator) => {
                 comparator.facing.get_id() * 4
                     + comparator.mode.get_id() * 2
@@ -611,10 +1013,27 @@ impl Block {
                     + 6142
             }
             Block::RedstoneBlock => 6190,
-            Block::Observer(facing) => (facing.get_id() << 1) + 8725,
+            Block::Hopper(hopper) => !hopper.enabled as u32 * 5 + hopper.facing_id() + 6192,
+            Block::Dropper(dropper) => {
+                (dropper.facing.get_id() << 1) + !dropper.triggered as u32 + 6299
+            }
+            Block::Observer(facing, powered) => (facing.get_id() << 1) + !powered as u32 + 8724,
+            Block::Piston(piston) => {
+                let base = if piston.sticky { 1328 } else { 1347 };
+                !piston.extended as u32 * 6 + piston.facing.get_id() + base
+            }
+            Block::PistonHead(head) => {
+                (head.facing.get_id() << 2) + (!head.short as u32 * 2) + head.sticky as u32 + 1359
+            }
+            Block::MovingPiston(facing, sticky) => (facing.get_id() << 1) + sticky as u32 + 1399,
             Block::WallSign(sign_type, facing) => (sign_type << 3) + (facing.get_id() << 1) + 3734,
             Block::SeaPickle(pickles) => ((pickles - 1) << 1) as u32 + 9105,
-            Block::PressurePlate(id) => id,
+            Block::PressurePlate(plate) => match plate.kind {
+                PressurePlateKind::Stone => (plate.power == 0) as u32 + 3805,
+                PressurePlateKind::Wood(wood) => (wood << 1) + (plate.power == 0) as u32 + 3871,
+                PressurePlateKind::LightWeighted => plate.power as u32 + 6110,
+                PressurePlateKind::HeavyWeighted => plate.power as u32 + 6126,
+            },
             Block::Solid(id) => id,
             Block::Transparent(id) => id,
             Block::Container(id) => id,
@@ -622,76 +1041,48 @@ impl Block {
     }
 
     pub fn from_name(name: &str) -> Option<Block> {
+        // Older versions saved schematics with these misspelled names
+        let name = match name {
+            "megenta_wool" => "magenta_wool",
+            "megenta_terracotta" => "magenta_terracotta",
+            "megenta_concrete" => "magenta_concrete",
+            name => name,
+        };
+        if let Some((_, id)) = SIMPLE_BLOCKS
+            .iter()
+            .find(|(block_name, _)| *block_name == name)
+        {
+            return Some(Block::from_block_state(*id));
+        }
         match name {
-            "air" => Some(Block::Air),
-            "glass" => Some(Block::Transparent(230)),
-            "quartz_slab" => Some(Block::Transparent(7855)),
-            "smooth_stone_slab" => Some(Block::Transparent(7807)),
-            "quartz_block" => Some(Block::Solid(6202)),
-            "sandstone" => Some(Block::Solid(245)),
-            "stone_pressure_plate" => Some(Block::PressurePlate(3806)),
-            "white_wool" => Some(Block::Solid(1383)),
-            "orange_wool" => Some(Block::Solid(1384)),
-            "megenta_wool" => Some(Block::Solid(1385)),
-            "light_blue_wool" => Some(Block::Solid(1386)),
-            "yellow_wool" => Some(Block::Solid(1387)),
-            "lime_wool" => Some(Block::Solid(1388)),
-            "pink_wool" => Some(Block::Solid(1389)),
-            "gray_wool" => Some(Block::Solid(1390)),
-            "light_gray_wool" => Some(Block::Solid(1391)),
-            "cyan_wool" => Some(Block::Solid(1392)),
-            "purple_wool" => Some(Block::Solid(1393)),
-            "blue_wool" => Some(Block::Solid(1394)),
-            "brown_wool" => Some(Block::Solid(1395)),
-            "green_wool" => Some(Block::Solid(1396)),
-            "red_wool" => Some(Block::Solid(1397)),
-            "black_wool" => Some(Block::Solid(1398)),
-            "iron_block" => Some(Block::Solid(1427)),
-            "stone_bricks" => Some(Block::Solid(4481)),
-            "white_terracotta" => Some(Block::Solid(6311)),
-            "orange_terracotta" => Some(Block::Solid(6312)),
-            "megenta_terracotta" => Some(Block::Solid(6313)),
-            "light_blue_terracotta" => Some(Block::Solid(6314)),
-            "yellow_terracotta" => Some(Block::Solid(6315)),
-            "lime_terracotta" => Some(Block::Solid(6316)),
-            "pink_terracotta" => Some(Block::Solid(6317)),
-            "gray_terracotta" => Some(Block::Solid(6318)),
-            "light_gray_terracotta" => Some(Block::Solid(6319)),
-            "cyan_terracotta" => Some(Block::Solid(6320)),
-            "purple_terracotta" => Some(Block::Solid(6321)),
-            "blue_terracotta" => Some(Block::Solid(6322)),
-            "brown_terracotta" => Some(Block::Solid(6323)),
-            "green_terracotta" => Some(Block::Solid(6324)),
-            "red_terracotta" => Some(Block::Solid(6325)),
-            "black_terracotta" => Some(Block::Solid(6326)),
-            "white_concrete" => Some(Block::Solid(8902)),
-            "orange_concrete" => Some(Block::Solid(8903)),
-            "megenta_concrete" => Some(Block::Solid(8904)),
-            "light_blue_concrete" => Some(Block::Solid(8905)),
-            "yellow_concrete" => Some(Block::Solid(8906)),
-            "lime_concrete" => Some(Block::Solid(8907)),
-            "pink_concrete" => Some(Block::Solid(8908)),
-            "gray_concrete" => Some(Block::Solid(8909)),
-            "light_gray_concrete" => Some(Block::Solid(8910)),
-            "cyan_concrete" => Some(Block::Solid(8911)),
-            "purple_concrete" => Some(Block::Solid(8912)),
-            "blue_concrete" => Some(Block::Solid(8913)),
-            "brown_concrete" => Some(Block::Solid(8914)),
-            "green_concrete" => Some(Block::Solid(8915)),
-            "red_concrete" => Some(Block::Solid(8916)),
-            "black_concrete" => Some(Block::Solid(8917)),
             "redstone_wire" => Some(Block::RedstoneWire(RedstoneWire::default())),
             "redstone_torch" => Some(Block::RedstoneTorch(true)),
             "redstone_wall_torch" => Some(Block::RedstoneWallTorch(true, BlockDirection::West)),
-            "redstone_block" => Some(Block::RedstoneBlock),
             "redstone_lamp" => Some(Block::RedstoneLamp(false)),
             "repeater" => Some(Block::RedstoneRepeater(RedstoneRepeater::default())),
             "comparator" => Some(Block::RedstoneComparator(RedstoneComparator::default())),
-            "furnace" => Some(Block::Container(3372)),
-            "barrel" => Some(Block::Container(11136)),
             "lever" => Some(Block::Lever(Lever::default())),
-            "tripwire_hook" => Some(Block::TripwireHook(BlockDirection::default())),
-            "observer" => Some(Block::Observer(BlockFacing::default())),
+            "tripwire_hook" => Some(Block::TripwireHook(TripwireHook::default())),
+            "tripwire" => Some(Block::Tripwire(Tripwire::default())),
+            "observer" => Some(Block::Observer(BlockFacing::default(), false)),
+            "hopper" => Some(Block::Hopper(Hopper::new(BlockFacing::Down, true))),
+            "dropper" => Some(Block::Dropper(Dropper::new(BlockFacing::default(), false))),
+            "piston" => Some(Block::Piston(Piston::new(
+                false,
+                BlockFacing::default(),
+                false,
+            ))),
+            "sticky_piston" => Some(Block::Piston(Piston::new(
+                true,
+                BlockFacing::default(),
+                false,
+            ))),
+            "piston_head" => Some(Block::PistonHead(PistonHead::new(
+                false,
+                BlockFacing::default(),
+                false,
+            ))),
+            "moving_piston" => Some(Block::MovingPiston(BlockFacing::default(), false)),
             "oak_sign" => Some(Block::Sign(0, 0)),
             "spruce_sign" => Some(Block::Sign(1, 0)),
             "birch_sign" => Some(Block::Sign(2, 0)),
@@ -705,11 +1096,196 @@ impl Block {
             "acacia_wall_sign" => Some(Block::WallSign(4, BlockDirection::default())),
             "dark_oak_wall_sign" => Some(Block::WallSign(5, BlockDirection::default())),
             "stone_button" => Some(Block::StoneButton(StoneButton::default())),
-            "hopper" => Some(Block::Container(6198)),
+            "stone_pressure_plate" => Some(Block::PressurePlate(PressurePlate::new(
+                PressurePlateKind::Stone,
+                0,
+            ))),
+            "light_weighted_pressure_plate" => Some(Block::PressurePlate(PressurePlate::new(
+                PressurePlateKind::LightWeighted,
+                0,
+            ))),
+            "heavy_weighted_pressure_plate" => Some(Block::PressurePlate(PressurePlate::new(
+                PressurePlateKind::HeavyWeighted,
+                0,
+            ))),
+            name if name.ends_with("_pressure_plate") => WOODEN_PRESSURE_PLATES
+                .iter()
+                .position(|plate| *plate == name)
+                .map(|wood| {
+                    Block::PressurePlate(PressurePlate::new(
+                        PressurePlateKind::Wood(wood as u32),
+                        0,
+                    ))
+                }),
+            "sea_pickle" => Some(Block::SeaPickle(1)),
             _ => None,
         }
     }
 
+    /// The name of the block without the `minecraft:` namespace, if it is known.
+    pub fn get_name(self) -> Option<&'static str> {
+        const WALL_SIGNS: [&str; 6] = [
+            "oak_wall_sign",
+            "spruce_wall_sign",
+            "birch_wall_sign",
+            "jungle_wall_sign",
+            "acacia_wall_sign",
+            "dark_oak_wall_sign",
+        ];
+        const SIGNS: [&str; 6] = [
+            "oak_sign",
+            "spruce_sign",
+            "birch_sign",
+            "jungle_sign",
+            "acacia_sign",
+            "dark_oak_sign",
+        ];
+        let name = match self {
+            Block::RedstoneWire(_) => "redstone_wire",
+            Block::RedstoneRepeater(_) => "repeater",
+            Block::RedstoneComparator(_) => "comparator",
+            Block::RedstoneTorch(_) => "redstone_torch",
+            Block::RedstoneWallTorch(_, _) => "redstone_wall_torch",
+            Block::RedstoneLamp(_) => "redstone_lamp",
+            Block::Lever(_) => "lever",
+            Block::StoneButton(_) => "stone_button",
+            Block::PressurePlate(plate) => match plate.kind {
+                PressurePlateKind::Stone => "stone_pressure_plate",
+                PressurePlateKind::Wood(wood) => WOODEN_PRESSURE_PLATES
+                    .get(wood as usize)
+                    .unwrap_or(&"oak_pressure_plate"),
+                PressurePlateKind::LightWeighted => "light_weighted_pressure_plate",
+                PressurePlateKind::HeavyWeighted => "heavy_weighted_pressure_plate",
+            },
+            Block::TripwireHook(_) => "tripwire_hook",
+            Block::Tripwire(_) => "tripwire",
+            Block::Observer(_, _) => "observer",
+            Block::Hopper(_) => "hopper",
+            Block::Dropper(_) => "dropper",
+            Block::Piston(piston) if piston.sticky => "sticky_piston",
+            Block::Piston(_) => "piston",
+            Block::PistonHead(_) => "piston_head",
+            Block::MovingPiston(_, _) => "moving_piston",
+            Block::SeaPickle(_) => "sea_pickle",
+            Block::Sign(sign_type, _) => SIGNS.get(sign_type as usize).unwrap_or(&"oak_sign"),
+            Block::WallSign(sign_type, _) => WALL_SIGNS
+                .get(sign_type as usize)
+                .unwrap_or(&"oak_wall_sign"),
+            _ => {
+                let id = self.get_id();
+                return SIMPLE_BLOCKS
+                    .iter()
+                    .find(|(_, block_id)| *block_id == id)
+                    .map(|(name, _)| *name);
+            }
+        };
+        Some(name)
+    }
+
+    /// The full block state string, such as `minecraft:lever[face=wall,facing=north,powered=false]`.
+    /// This is the format used by the palette in Sponge schematics. Returns `None` if
+    /// the block's name isn't known.
+    pub fn get_state_string(self) -> Option<String> {
+        let properties: Vec<(&str, String)> = match self {
+            Block::RedstoneWire(wire) => vec![
+                ("east", wire.east.to_str().to_owned()),
+                ("north", wire.north.to_str().to_owned()),
+                ("power", wire.power.to_string()),
+                ("south", wire.south.to_str().to_owned()),
+                ("west", wire.west.to_str().to_owned()),
+            ],
+            Block::RedstoneRepeater(repeater) => vec![
+                ("delay", repeater.delay.to_string()),
+                ("facing", repeater.facing.to_str().to_owned()),
+                ("locked", repeater.locked.to_string()),
+                ("powered", repeater.powered.to_string()),
+            ],
+            Block::RedstoneComparator(comparator) => vec![
+                ("facing", comparator.facing.to_str().to_owned()),
+                ("mode", comparator.mode.to_str().to_owned()),
+                ("powered", comparator.powered.to_string()),
+            ],
+            Block::RedstoneTorch(lit) | Block::RedstoneLamp(lit) => {
+                vec![("lit", lit.to_string())]
+            }
+            Block::RedstoneWallTorch(lit, facing) => vec![
+                ("facing", facing.to_str().to_owned()),
+                ("lit", lit.to_string()),
+            ],
+            Block::Lever(lever) => vec![
+                ("face", lever.face.to_str().to_owned()),
+                ("facing", lever.facing.to_str().to_owned()),
+                ("powered", lever.powered.to_string()),
+            ],
+            Block::StoneButton(button) => vec![
+                ("face", button.face.to_str().to_owned()),
+                ("facing", button.facing.to_str().to_owned()),
+                ("powered", button.powered.to_string()),
+            ],
+            Block::PressurePlate(plate) if plate.kind.is_weighted() => {
+                vec![("power", plate.power.to_string())]
+            }
+            Block::PressurePlate(plate) => vec![("powered", (plate.power > 0).to_string())],
+            Block::TripwireHook(hook) => vec![
+                ("attached", hook.attached.to_string()),
+                ("facing", hook.facing.to_str().to_owned()),
+                ("powered", hook.powered.to_string()),
+            ],
+            Block::Tripwire(tripwire) => vec![
+                ("attached", tripwire.attached.to_string()),
+                ("disarmed", tripwire.disarmed.to_string()),
+                ("east", tripwire.east.to_string()),
+                ("north", tripwire.north.to_string()),
+                ("powered", tripwire.powered.to_string()),
+                ("south", tripwire.south.to_string()),
+                ("west", tripwire.west.to_string()),
+            ],
+            Block::Observer(facing, powered) => vec![
+                ("facing", facing.to_str().to_owned()),
+                ("powered", powered.to_string()),
+            ],
+            Block::Hopper(hopper) => vec![
+                ("enabled", hopper.enabled.to_string()),
+                ("facing", hopper.facing.to_str().to_owned()),
+            ],
+            Block::Dropper(dropper) => vec![
+                ("facing", dropper.facing.to_str().to_owned()),
+                ("triggered", dropper.triggered.to_string()),
+            ],
+            Block::Piston(piston) => vec![
+                ("extended", piston.extended.to_string()),
+                ("facing", piston.facing.to_str().to_owned()),
+            ],
+            Block::PistonHead(head) => vec![
+                ("facing", head.facing.to_str().to_owned()),
+                ("short", head.short.to_string()),
+                (
+                    "type",
+                    if head.sticky { "sticky" } else { "normal" }.to_owned(),
+                ),
+            ],
+            Block::MovingPiston(facing, sticky) => vec![
+                ("facing", facing.to_str().to_owned()),
+                ("type", if sticky { "sticky" } else { "normal" }.to_owned()),
+            ],
+            Block::SeaPickle(pickles) => vec![("pickles", pickles.to_string())],
+            Block::Sign(_, rotation) => vec![("rotation", rotation.to_string())],
+            Block::WallSign(_, facing) => vec![("facing", facing.to_str().to_owned())],
+            _ => Vec::new(),
+        };
+        let mut state = format!("minecraft:{}", self.get_name()?);
+        if !properties.is_empty() {
+            let properties: Vec<String> = properties
+                .iter()
+                .map(|(key, val)| format!("{}={}", key, val))
+                .collect();
+            state.push('[');
+            state.push_str(&properties.join(","));
+            state.push(']');
+        }
+        Some(state)
+    }
+
     pub fn on_use(
         self,
         plot: &mut Plot,
@@ -795,8 +1371,12 @@ impl Block {
             64 => Block::Transparent(230),
             // Sandstone
             68 => Block::Solid(245),
+            // Sticky Piston
+            74 => Block::Piston(Piston::new(true, context.player_facing.opposite(), false)),
             // Sea Pickle
             80 => Block::SeaPickle(1),
+            // Piston
+            81 => Block::Piston(Piston::new(false, context.player_facing.opposite(), false)),
             // Wool
             82..=97 => Block::Solid(item_id + 1301),
             // Furnace
@@ -815,6 +1395,13 @@ impl Block {
                 };
                 Block::Lever(Lever::new(lever_face, facing, false))
             }
+            // Stone Pressure Plate
+            165 => Block::PressurePlate(PressurePlate::new(PressurePlateKind::Stone, 0)),
+            // Wooden Pressure Plates
+            166..=171 => Block::PressurePlate(PressurePlate::new(
+                PressurePlateKind::Wood(item_id - 166),
+                0,
+            )),
             // Redstone Torch
             173 => match context.block_face {
                 BlockFace::Top => Block::RedstoneTorch(true),
@@ -835,14 +1422,32 @@ impl Block {
                 };
                 Block::StoneButton(StoneButton::new(button_face, facing, false))
             }
+            // Tripwire Hook
+            238 => match context.block_face {
+                BlockFace::Top | BlockFace::Bottom => Block::Air,
+                face => Block::TripwireHook(TripwireHook::new(face.to_direction(), false, false)),
+            },
             // Redstone Lamp
             234 => Block::RedstoneLamp(Block::redstone_lamp_should_be_lit(plot, pos)),
+            // Weighted Pressure Plates
+            269 => Block::PressurePlate(PressurePlate::new(PressurePlateKind::LightWeighted, 0)),
+            270 => Block::PressurePlate(PressurePlate::new(PressurePlateKind::HeavyWeighted, 0)),
             // Redstone Block
             272 => Block::RedstoneBlock,
             // Hopper
-            274 => Block::Container(6198),
+            274 => {
+                let facing = match context.block_face {
+                    BlockFace::Top | BlockFace::Bottom => BlockFacing::Down,
+                    face => face.to_direction().opposite().block_facing(),
+                };
+                Block::Hopper(Hopper::new(facing, true))
+            }
+            // Dropper
+            280 => Block::Dropper(Dropper::new(context.player_facing.opposite(), false)),
             // Terracotta
             281..=296 => Block::Solid(item_id + 6030),
+            // Observer
+            379 => Block::Observer(context.player_facing, false),
             // Concrete
             413..=428 => Block::Solid(item_id + 8489),
             // Redstone Repeater
@@ -857,6 +1462,8 @@ impl Block {
                 ComparatorMode::Compare,
                 false,
             )),
+            // String
+            552 => Block::Tripwire(Tripwire::default().update_sides(plot, pos)),
             // Redstone Wire
             600 => Block::RedstoneWire(RedstoneWire::get_state_for_placement(plot, pos)),
             // Barrel
@@ -892,6 +1499,30 @@ impl Block {
                 Block::change_surrounding_blocks(plot, pos);
                 Block::update_wire_neighbors(plot, pos);
             }
+            Block::Piston(piston) => {
+                plot.set_block(pos, self);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+                piston.on_neighbor_updated(plot, pos);
+            }
+            Block::TripwireHook(hook) => {
+                plot.set_block(pos, self);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+                hook.update_line(plot, pos, false, None);
+            }
+            Block::Tripwire(tripwire) => {
+                plot.set_block(pos, self);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+                tripwire.notify_hooks(plot, pos);
+            }
+            Block::Hopper(hopper) => {
+                plot.set_block(pos, self);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+                hopper.update(plot, pos);
+            }
             _ => {
                 plot.set_block(pos, self);
                 Block::change_surrounding_blocks(plot, pos);
@@ -904,7 +1535,6 @@ impl Block {
         if self.has_block_entity() {
             plot.delete_block_entity(pos);
         }
-
         match self {
             Block::RedstoneWire(_) => {
                 plot.set_block(pos, Block::Air);
@@ -936,15 +1566,65 @@ impl Block {
                     }
                 }
             }
+            Block::Observer(facing, powered) => {
+                plot.set_block(pos, Block::Air);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+                if powered {
+                    Block::observer_on_state_change(plot, pos, facing);
+                }
+            }
+            Block::Piston(piston) => {
+                if piston.extended {
+                    piston.destroy_head(plot, pos);
+                }
+                plot.set_block(pos, Block::Air);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+            }
+            Block::PistonHead(head) => {
+                head.destroy_base(plot, pos);
+                plot.set_block(pos, Block::Air);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+            }
+            Block::TripwireHook(hook) => {
+                plot.set_block(pos, Block::Air);
+                hook.update_line(plot, pos, true, None);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+                if hook.powered {
+                    Block::update_surrounding_blocks(
+                        plot,
+                        pos.offset(hook.facing.opposite().block_face()),
+                    );
+                }
+            }
+            Block::Tripwire(tripwire) => {
+                plot.set_block(pos, Block::Air);
+                Block::change_surrounding_blocks(plot, pos);
+                Block::update_surrounding_blocks(plot, pos);
+                // Breaking tripwire sets off the hooks it was strung between
+                let broken = Tripwire {
+                    powered: true,
+                    ..tripwire
+                };
+                broken.notify_hooks(plot, pos);
+            }
             _ => {
                 plot.set_block(pos, Block::Air);
                 Block::change_surrounding_blocks(plot, pos);
                 Block::update_surrounding_blocks(plot, pos);
             }
         }
+        if ContainerType::from_block(self).is_some() {
+            // Closes the windows of players looking into the container
+            plot.update_container_windows(pos);
+        }
     }
 
     fn update(self, plot: &mut Plot, pos: BlockPos) {
+        plot.profile_block_update(self);
         match self {
             Block::RedstoneWire(wire) => {
                 wire.on_neighbor_updated(plot, pos);
@@ -967,6 +1647,15 @@ impl Block {
             Block::RedstoneComparator(comparator) => {
                 comparator.update(plot, pos);
             }
+            Block::Piston(piston) => {
+                piston.on_neighbor_updated(plot, pos);
+            }
+            Block::Hopper(hopper) => {
+                hopper.update(plot, pos);
+            }
+            Block::Dropper(dropper) => {
+                dropper.update(plot, pos);
+            }
             Block::RedstoneLamp(lit) => {
                 let should_be_lit = Block::redstone_lamp_should_be_lit(plot, pos);
                 if lit && !should_be_lit {
@@ -1013,6 +1702,38 @@ impl Block {
                     plot.set_block(pos, Block::RedstoneLamp(false));
                 }
             }
+            #[cfg(test)]
+            Block::Solid(CRASHING_BLOCK_ID) => panic!("The crashing block was ticked"),
+            Block::Observer(facing, powered) => {
+                if powered {
+                    plot.set_block(pos, Block::Observer(facing, false));
+                } else {
+                    plot.set_block(pos, Block::Observer(facing, true));
+                    plot.schedule_tick(pos, 1, TickPriority::Normal);
+                }
+                Block::observer_on_state_change(plot, pos, facing);
+            }
+            Block::Piston(piston) => {
+                piston.tick(plot, pos);
+            }
+            Block::MovingPiston(_, _) => {
+                finish_moving_piston(plot, pos);
+            }
+            Block::Hopper(hopper) => {
+                hopper.tick(plot, pos);
+            }
+            Block::Dropper(dropper) => {
+                dropper.tick(plot, pos);
+            }
+            Block::PressurePlate(plate) if plate.power > 0 => {
+                plate.check_pressed(plot, pos);
+            }
+            Block::TripwireHook(hook) => {
+                hook.update_line(plot, pos, false, None);
+            }
+            Block::Tripwire(tripwire) if tripwire.powered => {
+                tripwire.check_powered(plot, pos);
+            }
             Block::StoneButton(mut button) => {
                 if button.powered {
                     button.powered = false;
@@ -1041,7 +1762,8 @@ impl Block {
             Block::RedstoneWire(_)
             | Block::RedstoneComparator(_)
             | Block::RedstoneRepeater(_)
-            | Block::RedstoneTorch(_) => {
+            | Block::RedstoneTorch(_)
+            | Block::PressurePlate(_) => {
                 let bottom_block = plot.get_block(pos.offset(BlockFace::Bottom));
                 bottom_block.is_cube()
             }
@@ -1079,6 +1801,11 @@ impl Block {
                     parent_block.is_cube()
                 }
             },
+            Block::TripwireHook(hook) => {
+                let parent_block = plot.get_block(pos.offset(hook.facing.opposite().block_face()));
+                parent_block.is_cube()
+            }
+            Block::PistonHead(head) => head.is_attached(plot, pos),
             _ => true,
         }
     }
@@ -1095,6 +1822,9 @@ impl Block {
                     Block::update_wire_neighbors(plot, pos);
                 }
             }
+            Block::Tripwire(tripwire) => {
+                plot.set_block(pos, Block::Tripwire(tripwire.update_sides(plot, pos)));
+            }
             _ => {}
         }
     }
@@ -1214,18 +1944,91 @@ impl Block {
             Block::StoneButton(button) if key == "powered" => {
                 button.powered = val.parse::<bool>().unwrap_or_default();
             }
-            Block::TripwireHook(facing) if key == "facing" => {
-                *facing = BlockDirection::from_str(val);
+            Block::PressurePlate(plate) if key == "powered" => {
+                let powered = val.parse::<bool>().unwrap_or_default();
+                plate.power = if powered { 15 } else { 0 };
+            }
+            Block::PressurePlate(plate) if key == "power" => {
+                plate.power = val.parse::<u8>().unwrap_or_default().min(15);
+            }
+            Block::TripwireHook(hook) if key == "facing" => {
+                hook.facing = BlockDirection::from_str(val);
+            }
+            Block::TripwireHook(hook) if key == "attached" => {
+                hook.attached = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::TripwireHook(hook) if key == "powered" => {
+                hook.powered = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Tripwire(tripwire) if key == "attached" => {
+                tripwire.attached = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Tripwire(tripwire) if key == "disarmed" => {
+                tripwire.disarmed = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Tripwire(tripwire) if key == "powered" => {
+                tripwire.powered = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Tripwire(tripwire) if key == "north" => {
+                tripwire.north = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Tripwire(tripwire) if key == "south" => {
+                tripwire.south = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Tripwire(tripwire) if key == "east" => {
+                tripwire.east = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Tripwire(tripwire) if key == "west" => {
+                tripwire.west = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Observer(facing, _) if key == "facing" => {
+                *facing = BlockFacing::from_str(val);
+            }
+            Block::Observer(_, powered) if key == "powered" => {
+                *powered = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Hopper(hopper) if key == "facing" => {
+                hopper.facing = BlockFacing::from_str(val);
+            }
+            Block::Hopper(hopper) if key == "enabled" => {
+                hopper.enabled = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Dropper(dropper) if key == "facing" => {
+                dropper.facing = BlockFacing::from_str(val);
+            }
+            Block::Dropper(dropper) if key == "triggered" => {
+                dropper.triggered = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::Piston(piston) if key == "facing" => {
+                piston.facing = BlockFacing::from_str(val);
+            }
+            Block::Piston(piston) if key == "extended" => {
+                piston.extended = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::PistonHead(head) if key == "facing" => {
+                head.facing = BlockFacing::from_str(val);
+            }
+            Block::PistonHead(head) if key == "short" => {
+                head.short = val.parse::<bool>().unwrap_or_default();
+            }
+            Block::PistonHead(head) if key == "type" => {
+                head.sticky = val == "sticky";
             }
-            Block::Observer(facing) if key == "facing" => {
+            Block::MovingPiston(facing, _) if key == "facing" => {
                 *facing = BlockFacing::from_str(val);
             }
+            Block::MovingPiston(_, sticky) if key == "type" => {
+                *sticky = val == "sticky";
+            }
             Block::WallSign(_, facing) if key == "facing" => {
                 *facing = BlockDirection::from_str(val);
             }
             Block::Sign(_, rotation) if key == "rotation" => {
                 *rotation = val.parse::<u32>().unwrap_or_default();
             }
+            Block::SeaPickle(pickles) if key == "pickles" => {
+                *pickles = val.parse::<u8>().unwrap_or(1).clamp(1, 4);
+            }
             _ => {}
         }
     }
@@ -1253,3 +2056,54 @@ fn comparator_id_test() {
     let new = Block::from_block_state(id);
     assert_eq!(new, original);
 }
+
+#[test]
+fn observer_id_test() {
+    let original = Block::Observer(BlockFacing::Up, true);
+    let id = original.get_id();
+    assert_eq!(id, 8732);
+    let new = Block::from_block_state(id);
+    assert_eq!(new, original);
+}
+
+#[test]
+fn piston_id_test() {
+    let original = Block::Piston(Piston::new(true, BlockFacing::Up, true));
+    let id = original.get_id();
+    assert_eq!(id, 1332);
+    let new = Block::from_block_state(id);
+    assert_eq!(new, original);
+
+    let original = Block::PistonHead(PistonHead::new(true, BlockFacing::West, false));
+    let id = original.get_id();
+    assert_eq!(id, 1374);
+    let new = Block::from_block_state(id);
+    assert_eq!(new, original);
+}
+
+#[test]
+fn simple_block_names_test() {
+    for &(name, id) in SIMPLE_BLOCKS {
+        let block = Block::from_name(name).unwrap();
+        assert_eq!(block.get_id(), id);
+        assert_eq!(block.get_name(), Some(name));
+    }
+}
+
+#[test]
+fn pressure_plate_id_test() {
+    let original = Block::PressurePlate(PressurePlate::new(PressurePlateKind::Wood(2), 15));
+    let id = original.get_id();
+    assert_eq!(id, 3875);
+    assert_eq!(Block::from_block_state(id), original);
+    assert_eq!(original.get_name(), Some("birch_pressure_plate"));
+
+    let original = Block::PressurePlate(PressurePlate::new(PressurePlateKind::HeavyWeighted, 3));
+    let id = original.get_id();
+    assert_eq!(id, 6129);
+    assert_eq!(Block::from_block_state(id), original);
+    assert_eq!(
+        Block::from_name("stone_pressure_plate").unwrap().get_id(),
+        3806
+    );
+}
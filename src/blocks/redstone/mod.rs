@@ -1,8 +1,12 @@
+mod piston;
 mod redstone_wire;
+mod tripwire;
 
+pub use piston::{finish_moving_piston, MovingPistonEntity, Piston, PistonHead};
 pub use redstone_wire::{RedstoneWire, RedstoneWireSide};
+pub use tripwire::{Tripwire, TripwireHook};
 
-use crate::blocks::{Block, BlockDirection, BlockEntity, BlockFace, BlockPos};
+use crate::blocks::{Block, BlockDirection, BlockEntity, BlockFace, BlockFacing, BlockPos};
 use crate::plot::{Plot, TickPriority};
 use std::cmp;
 
@@ -14,11 +18,14 @@ impl Block {
             Block::RedstoneBlock => 15,
             Block::Lever(lever) if lever.powered => 15,
             Block::StoneButton(button) if button.powered => 15,
+            Block::PressurePlate(plate) => plate.power,
+            Block::TripwireHook(hook) if hook.powered => 15,
             Block::RedstoneRepeater(repeater)
                 if repeater.facing.block_face() == side && repeater.powered =>
             {
                 15
             }
+            Block::Observer(facing, true) if facing.block_face() == side => 15,
             Block::RedstoneComparator(comparator) if comparator.facing.block_face() == side => {
                 if let Some(BlockEntity::Comparator { output_strength }) =
                     plot.get_block_entity(pos)
@@ -64,9 +71,12 @@ impl Block {
                 _ if button.facing == side.to_direction() && button.powered => 15,
                 _ => 0,
             },
+            Block::PressurePlate(plate) if side == BlockFace::Top => plate.power,
+            Block::TripwireHook(hook) if hook.powered && hook.facing.block_face() == side => 15,
             Block::RedstoneWire(_) => self.get_weak_power(plot, pos, side, dust_power),
             Block::RedstoneRepeater(_) => self.get_weak_power(plot, pos, side, dust_power),
             Block::RedstoneComparator(_) => self.get_weak_power(plot, pos, side, dust_power),
+            Block::Observer(_, _) => self.get_weak_power(plot, pos, side, dust_power),
             _ => 0,
         }
     }
@@ -109,6 +119,33 @@ impl Block {
         wall_block.get_redstone_power(plot, wall_pos, direction.opposite().block_face()) > 0
     }
 
+    /// Observers only output through their back face, so when one changes state
+    /// only the block behind it and that block's neighbors need to be updated.
+    pub fn observer_on_state_change(plot: &mut Plot, pos: BlockPos, facing: BlockFacing) {
+        let back_pos = pos.offset(facing.block_face().opposite());
+        let back_block = plot.get_block(back_pos);
+        back_block.update(plot, back_pos);
+        for direction in &BlockFace::values() {
+            let neighbor_pos = back_pos.offset(*direction);
+            let block = plot.get_block(neighbor_pos);
+            block.update(plot, neighbor_pos);
+        }
+    }
+
+    /// Starts the pulse of every observer watching the block at `pos`. This gets
+    /// called every time a block in the plot changes its state.
+    pub fn notify_observers(plot: &mut Plot, pos: BlockPos) {
+        for face in &BlockFace::values() {
+            let observer_pos = pos.offset(*face);
+            if let Block::Observer(facing, false) = plot.get_block(observer_pos) {
+                if facing.block_face() == face.opposite() && !plot.pending_tick_at(observer_pos) {
+                    // Observers pulse for 2 game ticks, which is one redstone tick
+                    plot.schedule_tick(observer_pos, 1, TickPriority::Normal);
+                }
+            }
+        }
+    }
+
     pub fn redstone_lamp_should_be_lit(plot: &Plot, pos: BlockPos) -> bool {
         for face in &BlockFace::values() {
             let neighbor_pos = pos.offset(*face);
@@ -138,10 +175,10 @@ fn diode_get_input_strength(plot: &Plot, pos: BlockPos, facing: BlockDirection)
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct RedstoneRepeater {
-    pub(super) delay: u8,
-    pub(super) facing: BlockDirection,
-    pub(super) locked: bool,
-    pub(super) powered: bool,
+    pub delay: u8,
+    pub facing: BlockDirection,
+    pub locked: bool,
+    pub powered: bool,
 }
 
 impl Default for RedstoneRepeater {
@@ -298,6 +335,13 @@ impl ComparatorMode {
             _ => ComparatorMode::Compare,
         }
     }
+
+    pub(super) fn to_str(self) -> &'static str {
+        match self {
+            ComparatorMode::Compare => "compare",
+            ComparatorMode::Subtract => "subtract",
+        }
+    }
 }
 
 impl Default for ComparatorMode {
@@ -308,9 +352,9 @@ impl Default for ComparatorMode {
 
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
 pub struct RedstoneComparator {
-    pub(super) facing: BlockDirection,
-    pub(super) mode: ComparatorMode,
-    pub(super) powered: bool,
+    pub facing: BlockDirection,
+    pub mode: ComparatorMode,
+    pub powered: bool,
 }
 
 impl RedstoneComparator {
@@ -494,6 +538,14 @@ impl LeverFace {
             _ => LeverFace::Wall,
         }
     }
+
+    pub(super) fn to_str(self) -> &'static str {
+        match self {
+            LeverFace::Floor => "floor",
+            LeverFace::Wall => "wall",
+            LeverFace::Ceiling => "ceiling",
+        }
+    }
 }
 
 impl Default for LeverFace {
@@ -551,6 +603,14 @@ impl ButtonFace {
             _ => ButtonFace::Wall,
         }
     }
+
+    pub(super) fn to_str(self) -> &'static str {
+        match self {
+            ButtonFace::Floor => "floor",
+            ButtonFace::Wall => "wall",
+            ButtonFace::Ceiling => "ceiling",
+        }
+    }
 }
 
 impl Default for ButtonFace {
@@ -575,3 +635,203 @@ impl StoneButton {
         }
     }
 }
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PressurePlateKind {
+    Stone,
+    /// The wood type, in the same order as signs
+    Wood(u32),
+    LightWeighted,
+    HeavyWeighted,
+}
+
+impl PressurePlateKind {
+    pub(super) fn is_weighted(self) -> bool {
+        matches!(
+            self,
+            PressurePlateKind::LightWeighted | PressurePlateKind::HeavyWeighted
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PressurePlate {
+    pub kind: PressurePlateKind,
+    /// Stone and wooden plates are either off or at full power
+    pub power: u8,
+}
+
+impl PressurePlate {
+    pub(super) fn new(kind: PressurePlateKind, power: u8) -> PressurePlate {
+        PressurePlate { kind, power }
+    }
+
+    /// The number of redstone ticks the plate stays pressed after it was last checked.
+    /// Stone and wooden plates stay down for 20 game ticks, weighted plates for 10.
+    fn release_delay(self) -> u32 {
+        if self.kind.is_weighted() {
+            5
+        } else {
+            10
+        }
+    }
+
+    fn power_for(self, players: usize) -> u8 {
+        match self.kind {
+            PressurePlateKind::LightWeighted => cmp::min(players, 15) as u8,
+            PressurePlateKind::HeavyWeighted => cmp::min(players.div_ceil(10), 15) as u8,
+            _ if players > 0 => 15,
+            _ => 0,
+        }
+    }
+
+    /// Updates the power of the plate from the players standing on it. While anyone
+    /// is still on the plate, it gets checked again once the release delay is over.
+    pub fn check_pressed(mut self, plot: &mut Plot, pos: BlockPos) {
+        // Only the middle of the plate detects players
+        let (x, y, z) = (pos.x as f64, pos.y as f64, pos.z as f64);
+        let players =
+            plot.players_in_box([x + 0.125, y, z + 0.125], [x + 0.875, y + 0.25, z + 0.875]);
+        let power = self.power_for(players);
+        if power != self.power {
+            plot.reset_redpiler();
+            self.power = power;
+            plot.set_block(pos, Block::PressurePlate(self));
+            Block::update_surrounding_blocks(plot, pos);
+            Block::update_surrounding_blocks(plot, pos.offset(BlockFace::Bottom));
+        }
+        if power > 0 {
+            plot.schedule_tick(pos, self.release_delay(), TickPriority::Normal);
+        }
+    }
+}
+
+/// Adds `count` players standing in the middle of the block at `pos`.
+#[cfg(test)]
+fn add_test_players(plot: &mut Plot, pos: BlockPos, count: usize) {
+    use crate::network::NetworkClient;
+    use crate::player::Player;
+
+    for _ in 0..count {
+        let id = plot.players.len() as u32;
+        let (client, _) = NetworkClient::connect_local(id);
+        let mut player = Player::load_player(id as u128, format!("player{}", id), client);
+        player.x = pos.x as f64 + 0.5;
+        player.y = pos.y as f64;
+        player.z = pos.z as f64 + 0.5;
+        plot.players.push(player);
+    }
+}
+
+/// Ticks the plot until the plate at `pos` is released and returns how many ticks it
+/// took.
+#[cfg(test)]
+fn ticks_until_released(plot: &mut Plot, pos: BlockPos) -> u32 {
+    (1..=100)
+        .find(|_| {
+            plot.tick();
+            let block = plot.get_block(pos);
+            block.get_redstone_power(plot, pos, BlockFace::Top) == 0
+        })
+        .unwrap()
+}
+
+#[test]
+fn pressure_plate_test() {
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let plate_pos = BlockPos::new(5, 10, 5);
+    let lamp_pos = BlockPos::new(6, 10, 5);
+    plot.set_block(plate_pos, Block::from_name("stone_pressure_plate").unwrap());
+    plot.set_block(lamp_pos, Block::RedstoneLamp(false));
+    add_test_players(&mut plot, plate_pos, 1);
+    plot.tick();
+    let pressed = PressurePlate::new(PressurePlateKind::Stone, 15);
+    assert_eq!(plot.get_block(plate_pos), Block::PressurePlate(pressed));
+    assert_eq!(plot.get_block(lamp_pos), Block::RedstoneLamp(true));
+
+    // The plate stays down as long as someone is standing on it
+    for _ in 0..30 {
+        plot.tick();
+    }
+    assert_eq!(plot.get_block(plate_pos), Block::PressurePlate(pressed));
+
+    // Once they step off, it comes up at the next check
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    plot.set_block(plate_pos, Block::from_name("oak_pressure_plate").unwrap());
+    add_test_players(&mut plot, plate_pos, 1);
+    plot.tick();
+    plot.players[0].x += 2.0;
+    assert_eq!(ticks_until_released(&mut plot, plate_pos), 10);
+}
+
+#[test]
+fn weighted_pressure_plate_test() {
+    let plate_pos = BlockPos::new(5, 10, 5);
+    let power_with = |name: &str, players: usize| {
+        let mut plot = Plot::load_headless(0, 0, None).unwrap();
+        plot.set_block(plate_pos, Block::from_name(name).unwrap());
+        add_test_players(&mut plot, plate_pos, players);
+        plot.tick();
+        plot.get_block(plate_pos)
+            .get_redstone_power(&plot, plate_pos, BlockFace::Top)
+    };
+    assert_eq!(power_with("light_weighted_pressure_plate", 3), 3);
+    assert_eq!(power_with("light_weighted_pressure_plate", 20), 15);
+    assert_eq!(power_with("heavy_weighted_pressure_plate", 3), 1);
+    assert_eq!(power_with("heavy_weighted_pressure_plate", 11), 2);
+
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    plot.set_block(
+        plate_pos,
+        Block::from_name("light_weighted_pressure_plate").unwrap(),
+    );
+    add_test_players(&mut plot, plate_pos, 2);
+    plot.tick();
+    // The plate gets lighter at the next check, and releases at the one after
+    plot.players[0].x += 2.0;
+    for _ in 0..5 {
+        plot.tick();
+    }
+    let lighter = PressurePlate::new(PressurePlateKind::LightWeighted, 1);
+    assert_eq!(plot.get_block(plate_pos), Block::PressurePlate(lighter));
+    plot.players[0].x += 2.0;
+    plot.players[1].x += 2.0;
+    assert_eq!(ticks_until_released(&mut plot, plate_pos), 5);
+}
+
+#[test]
+fn observer_pulse_test() {
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let observer_pos = BlockPos::new(5, 10, 5);
+    let watched_pos = BlockPos::new(6, 10, 5);
+    let wire_pos = BlockPos::new(4, 10, 5);
+    plot.set_block(wire_pos.offset(BlockFace::Bottom), Block::Solid(1));
+    plot.set_block(wire_pos, Block::from_name("redstone_wire").unwrap());
+    plot.set_block(observer_pos, Block::Observer(BlockFacing::East, false));
+    let wire_power = |plot: &Plot| match plot.get_block(wire_pos) {
+        Block::RedstoneWire(wire) => wire.power,
+        block => panic!("The wire was replaced by {:?}", block),
+    };
+
+    plot.set_block(watched_pos, Block::Solid(1));
+    assert_eq!(wire_power(&plot), 0);
+    plot.tick();
+    assert_eq!(wire_power(&plot), 15);
+    // Only the back of the observer outputs power
+    let observer = plot.get_block(observer_pos);
+    for face in &BlockFace::values() {
+        let power = observer.get_redstone_power(&plot, observer_pos, *face);
+        let expected = if *face == BlockFace::East { 15 } else { 0 };
+        assert_eq!(power, expected, "Power towards {:?}", face);
+    }
+
+    // The pulse lasts for exactly one redstone tick
+    plot.tick();
+    assert_eq!(wire_power(&plot), 0);
+    assert_eq!(
+        plot.get_block(observer_pos),
+        Block::Observer(BlockFacing::East, false)
+    );
+    plot.tick();
+    assert_eq!(wire_power(&plot), 0);
+}
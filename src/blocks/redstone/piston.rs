@@ -0,0 +1,684 @@
+use crate::blocks::{Block, BlockEntity, BlockFace, BlockFacing, BlockPos};
+use crate::plot::{Plot, TickPriority};
+use serde::{Deserialize, Serialize};
+
+// Pistons can push at most this many blocks at once
+const PUSH_LIMIT: usize = 12;
+
+const SLIME_BLOCK: u32 = 6999;
+const HONEY_BLOCK: u32 = 11335;
+const OBSIDIAN: u32 = 1433;
+const BEDROCK: u32 = 33;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Piston {
+    pub sticky: bool,
+    pub facing: BlockFacing,
+    pub extended: bool,
+}
+
+impl Piston {
+    pub(in crate::blocks) fn new(sticky: bool, facing: BlockFacing, extended: bool) -> Piston {
+        Piston {
+            sticky,
+            facing,
+            extended,
+        }
+    }
+
+    /// Pistons can be powered from any side except their front. They can also be
+    /// powered by anything that would power the block above them, which is known
+    /// as quasi-connectivity.
+    fn should_extend(self, plot: &Plot, pos: BlockPos) -> bool {
+        for face in &BlockFace::values() {
+            if *face == self.facing.block_face() {
+                continue;
+            }
+            let neighbor_pos = pos.offset(*face);
+            let neighbor = plot.get_block(neighbor_pos);
+            if neighbor.get_redstone_power(plot, neighbor_pos, *face) > 0 {
+                return true;
+            }
+        }
+        let up_pos = pos.offset(BlockFace::Top);
+        for face in &BlockFace::values() {
+            if *face == BlockFace::Bottom {
+                continue;
+            }
+            let neighbor_pos = up_pos.offset(*face);
+            let neighbor = plot.get_block(neighbor_pos);
+            if neighbor.get_redstone_power(plot, neighbor_pos, *face) > 0 {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn on_neighbor_updated(self, plot: &mut Plot, pos: BlockPos) {
+        if self.should_extend(plot, pos) != self.extended && !plot.pending_tick_at(pos) {
+            // In vanilla, pistons queue a block event which gets processed at the
+            // end of the tick. Scheduling a tick with no delay has the same effect.
+            plot.schedule_tick(pos, 0, TickPriority::Normal);
+        }
+    }
+
+    pub fn tick(mut self, plot: &mut Plot, pos: BlockPos) {
+        let should_extend = self.should_extend(plot, pos);
+        if should_extend && !self.extended {
+            if move_blocks(plot, pos, self.facing, self.sticky, true) {
+                self.extended = true;
+                plot.set_block(pos, Block::Piston(self));
+                Block::update_surrounding_blocks(plot, pos);
+            }
+        } else if !should_extend && self.extended {
+            self.retract(plot, pos);
+        }
+    }
+
+    fn retract(self, plot: &mut Plot, pos: BlockPos) {
+        let head_pos = pos.offset(self.facing.block_face());
+        if let Block::MovingPiston(_, _) = plot.get_block(head_pos) {
+            finish_moving_piston(plot, head_pos);
+        }
+
+        plot.set_block(pos, Block::MovingPiston(self.facing, self.sticky));
+        plot.set_block_entity(
+            pos,
+            BlockEntity::MovingPiston(MovingPistonEntity {
+                block_state: Block::Piston(Piston::new(self.sticky, self.facing, false)).get_id(),
+                facing: self.facing,
+                extending: false,
+                source: true,
+            }),
+        );
+        plot.schedule_tick(pos, 1, TickPriority::Normal);
+        Block::update_surrounding_blocks(plot, pos);
+
+        let mut pulled = false;
+        if self.sticky {
+            let pull_pos = head_pos.offset(self.facing.block_face());
+            let pull_block = plot.get_block(pull_pos);
+            let mut dropped = false;
+            if let Block::MovingPiston(_, _) = pull_block {
+                if let Some(BlockEntity::MovingPiston(entity)) = plot.get_block_entity(pull_pos) {
+                    // A sticky piston which gets a pulse shorter than its extension
+                    // time will drop the block it was pushing.
+                    if entity.facing == self.facing && entity.extending {
+                        finish_moving_piston(plot, pull_pos);
+                        dropped = true;
+                    }
+                }
+            }
+            let can_pull = match pull_block {
+                Block::Air => false,
+                Block::Piston(_) => true,
+                _ => pull_block.push_reaction() == PushReaction::Normal,
+            } && pull_block.is_pushable(
+                plot,
+                pull_pos,
+                self.facing.opposite(),
+                false,
+            );
+            if !dropped && can_pull {
+                pulled = move_blocks(plot, pos, self.facing, self.sticky, false);
+            }
+        }
+        if !pulled {
+            plot.set_block(head_pos, Block::Air);
+            Block::change_surrounding_blocks(plot, head_pos);
+            Block::update_surrounding_blocks(plot, head_pos);
+        }
+    }
+
+    /// Breaks the head of an extended piston along with the piston.
+    pub fn destroy_head(self, plot: &mut Plot, pos: BlockPos) {
+        let head_pos = pos.offset(self.facing.block_face());
+        if let Block::PistonHead(head) = plot.get_block(head_pos) {
+            if head.facing == self.facing {
+                plot.set_block(head_pos, Block::Air);
+                Block::change_surrounding_blocks(plot, head_pos);
+                Block::update_surrounding_blocks(plot, head_pos);
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PistonHead {
+    pub sticky: bool,
+    pub facing: BlockFacing,
+    pub short: bool,
+}
+
+impl PistonHead {
+    pub(in crate::blocks) fn new(sticky: bool, facing: BlockFacing, short: bool) -> PistonHead {
+        PistonHead {
+            sticky,
+            facing,
+            short,
+        }
+    }
+
+    /// A piston head can only exist in front of an extended piston.
+    pub fn is_attached(self, plot: &Plot, pos: BlockPos) -> bool {
+        let base_pos = pos.offset(self.facing.opposite().block_face());
+        match plot.get_block(base_pos) {
+            Block::Piston(piston) => piston.extended && piston.facing == self.facing,
+            _ => false,
+        }
+    }
+
+    /// Breaks the piston that this head belongs to.
+    pub fn destroy_base(self, plot: &mut Plot, pos: BlockPos) {
+        if self.is_attached(plot, pos) {
+            let base_pos = pos.offset(self.facing.opposite().block_face());
+            plot.set_block(base_pos, Block::Air);
+            Block::change_surrounding_blocks(plot, base_pos);
+            Block::update_surrounding_blocks(plot, base_pos);
+        }
+    }
+}
+
+/// The block entity of a moving piston. It holds the block which is being moved
+/// until the movement is finished.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovingPistonEntity {
+    pub block_state: u32,
+    pub facing: BlockFacing,
+    pub extending: bool,
+    /// Whether this is the piston base or head instead of a block being pushed
+    pub source: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PushReaction {
+    Normal,
+    Destroy,
+    Block,
+}
+
+impl Block {
+    fn is_sticky(self) -> bool {
+        matches!(self, Block::Solid(SLIME_BLOCK) | Block::Solid(HONEY_BLOCK))
+    }
+
+    fn can_stick_to(self, other: Block) -> bool {
+        match (self, other) {
+            (Block::Solid(SLIME_BLOCK), Block::Solid(HONEY_BLOCK))
+            | (Block::Solid(HONEY_BLOCK), Block::Solid(SLIME_BLOCK)) => false,
+            _ => self.is_sticky() || other.is_sticky(),
+        }
+    }
+
+    pub fn push_reaction(self) -> PushReaction {
+        match self {
+            Block::Air => PushReaction::Normal,
+            Block::RedstoneWire(_)
+            | Block::RedstoneRepeater(_)
+            | Block::RedstoneComparator(_)
+            | Block::RedstoneTorch(_)
+            | Block::RedstoneWallTorch(_, _)
+            | Block::Lever(_)
+            | Block::StoneButton(_)
+            | Block::PressurePlate(_)
+            | Block::TripwireHook(_)
+            | Block::Tripwire(_)
+            | Block::SeaPickle(_) => PushReaction::Destroy,
+            Block::Container(_)
+            | Block::Hopper(_)
+            | Block::Dropper(_)
+            | Block::Sign(_, _)
+            | Block::WallSign(_, _)
+            | Block::PistonHead(_)
+            | Block::MovingPiston(_, _)
+            | Block::Solid(OBSIDIAN)
+            | Block::Solid(BEDROCK) => PushReaction::Block,
+            _ if self.can_place_block_in() => PushReaction::Destroy,
+            _ => PushReaction::Normal,
+        }
+    }
+
+    fn is_pushable(
+        self,
+        plot: &Plot,
+        pos: BlockPos,
+        direction: BlockFacing,
+        destroy_blocks: bool,
+    ) -> bool {
+        if !plot.pos_in_plot(pos)
+            || (direction == BlockFacing::Down && pos.y == 0)
+            || (direction == BlockFacing::Up && pos.y == 255)
+        {
+            return false;
+        }
+        match self {
+            Block::Piston(piston) => !piston.extended,
+            _ => match self.push_reaction() {
+                PushReaction::Block => false,
+                PushReaction::Destroy => destroy_blocks,
+                PushReaction::Normal => !self.has_block_entity(),
+            },
+        }
+    }
+}
+
+/// Offsets a position `n` blocks in a direction, returning `None` if it would
+/// leave the world vertically.
+fn offset_n(pos: BlockPos, face: BlockFace, n: u32) -> Option<BlockPos> {
+    match face {
+        BlockFace::Bottom => pos.y.checked_sub(n).map(|y| BlockPos::new(pos.x, y, pos.z)),
+        BlockFace::Top if pos.y + n > 255 => None,
+        _ => {
+            let mut pos = pos;
+            for _ in 0..n {
+                pos = pos.offset(face);
+            }
+            Some(pos)
+        }
+    }
+}
+
+fn same_axis(a: BlockFace, b: BlockFace) -> bool {
+    a == b || a == b.opposite()
+}
+
+/// Finds all the blocks that will be moved or destroyed by a piston. This is
+/// a port of vanilla's `PistonStructureResolver`.
+struct PistonStructure<'a> {
+    plot: &'a Plot,
+    piston_pos: BlockPos,
+    start_pos: Option<BlockPos>,
+    direction: BlockFacing,
+    extending: bool,
+    to_push: Vec<BlockPos>,
+    to_destroy: Vec<BlockPos>,
+}
+
+impl<'a> PistonStructure<'a> {
+    fn new(plot: &'a Plot, piston_pos: BlockPos, facing: BlockFacing, extending: bool) -> Self {
+        let (direction, start_pos) = if extending {
+            (facing, offset_n(piston_pos, facing.block_face(), 1))
+        } else {
+            (facing.opposite(), offset_n(piston_pos, facing.block_face(), 2))
+        };
+        PistonStructure {
+            plot,
+            piston_pos,
+            start_pos,
+            direction,
+            extending,
+            to_push: Vec::new(),
+            to_destroy: Vec::new(),
+        }
+    }
+
+    fn resolve(&mut self) -> bool {
+        let start_pos = match self.start_pos {
+            Some(pos) => pos,
+            None => return false,
+        };
+        let block = self.plot.get_block(start_pos);
+        if !block.is_pushable(self.plot, start_pos, self.direction, false) {
+            if self.extending && block.push_reaction() == PushReaction::Destroy {
+                self.to_destroy.push(start_pos);
+                return true;
+            }
+            return false;
+        }
+        if !self.add_block_line(start_pos) {
+            return false;
+        }
+        let mut i = 0;
+        while i < self.to_push.len() {
+            let pos = self.to_push[i];
+            if self.plot.get_block(pos).is_sticky() && !self.add_branching_blocks(pos) {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    fn add_block_line(&mut self, origin: BlockPos) -> bool {
+        let move_face = self.direction.block_face();
+        let mut block = self.plot.get_block(origin);
+        if block == Block::Air
+            || !block.is_pushable(self.plot, origin, self.direction, false)
+            || origin == self.piston_pos
+            || self.to_push.contains(&origin)
+        {
+            return true;
+        }
+
+        let mut line_len = 1;
+        if line_len + self.to_push.len() > PUSH_LIMIT {
+            return false;
+        }
+        // Find the sticky blocks behind this one that will get dragged along
+        while block.is_sticky() {
+            let pos = match offset_n(origin, move_face.opposite(), line_len as u32) {
+                Some(pos) => pos,
+                None => break,
+            };
+            let prev_block = block;
+            block = self.plot.get_block(pos);
+            if block == Block::Air
+                || !prev_block.can_stick_to(block)
+                || !block.is_pushable(self.plot, pos, self.direction, false)
+                || pos == self.piston_pos
+            {
+                break;
+            }
+            line_len += 1;
+            if line_len + self.to_push.len() > PUSH_LIMIT {
+                return false;
+            }
+        }
+
+        let mut added = 0;
+        for i in (0..line_len).rev() {
+            // These offsets have all been checked above
+            self.to_push
+                .push(offset_n(origin, move_face.opposite(), i as u32).unwrap());
+            added += 1;
+        }
+
+        let mut i = 1;
+        loop {
+            let pos = match offset_n(origin, move_face, i) {
+                Some(pos) => pos,
+                None => return false,
+            };
+            if let Some(collision_idx) = self.to_push.iter().position(|p| *p == pos) {
+                self.reorder_list_at_collision(added, collision_idx);
+                for k in 0..=collision_idx + added {
+                    let pos = self.to_push[k];
+                    if self.plot.get_block(pos).is_sticky() && !self.add_branching_blocks(pos) {
+                        return false;
+                    }
+                }
+                return true;
+            }
+
+            let block = self.plot.get_block(pos);
+            if block == Block::Air {
+                return true;
+            }
+            if !block.is_pushable(self.plot, pos, self.direction, true) || pos == self.piston_pos
+            {
+                return false;
+            }
+            if block.push_reaction() == PushReaction::Destroy {
+                self.to_destroy.push(pos);
+                return true;
+            }
+            if self.to_push.len() >= PUSH_LIMIT {
+                return false;
+            }
+            self.to_push.push(pos);
+            added += 1;
+            i += 1;
+        }
+    }
+
+    fn reorder_list_at_collision(&mut self, added: usize, collision_idx: usize) {
+        let len = self.to_push.len();
+        let mut reordered = self.to_push[..collision_idx].to_vec();
+        reordered.extend_from_slice(&self.to_push[len - added..]);
+        reordered.extend_from_slice(&self.to_push[collision_idx..len - added]);
+        self.to_push = reordered;
+    }
+
+    fn add_branching_blocks(&mut self, from_pos: BlockPos) -> bool {
+        let block = self.plot.get_block(from_pos);
+        for id in 0..6 {
+            let face = BlockFace::from_id(id);
+            if same_axis(face, self.direction.block_face()) {
+                continue;
+            }
+            if let Some(pos) = offset_n(from_pos, face, 1) {
+                if self.plot.get_block(pos).can_stick_to(block) && !self.add_block_line(pos) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Starts moving the blocks in front of a piston. Returns false if the blocks
+/// could not be moved.
+fn move_blocks(
+    plot: &mut Plot,
+    pos: BlockPos,
+    facing: BlockFacing,
+    sticky: bool,
+    extending: bool,
+) -> bool {
+    let head_pos = pos.offset(facing.block_face());
+    if !extending {
+        if let Block::PistonHead(_) = plot.get_block(head_pos) {
+            plot.set_block(head_pos, Block::Air);
+        }
+    }
+
+    let mut structure = PistonStructure::new(plot, pos, facing, extending);
+    if !structure.resolve() {
+        return false;
+    }
+    let direction = structure.direction;
+    let to_push = structure.to_push;
+    let to_destroy = structure.to_destroy;
+
+    let blocks: Vec<Block> = to_push.iter().map(|pos| plot.get_block(*pos)).collect();
+    let mut vacated = to_push.clone();
+
+    for destroy_pos in to_destroy.iter().rev() {
+        let block = plot.get_block(*destroy_pos);
+        if block.has_block_entity() {
+            plot.delete_block_entity(*destroy_pos);
+        }
+        plot.set_block(*destroy_pos, Block::Air);
+    }
+
+    for (block_pos, block) in to_push.iter().zip(blocks.iter()).rev() {
+        let new_pos = block_pos.offset(direction.block_face());
+        vacated.retain(|pos| *pos != new_pos);
+        start_moving(
+            plot,
+            new_pos,
+            Block::MovingPiston(direction, false),
+            MovingPistonEntity {
+                block_state: block.get_id(),
+                facing: direction,
+                extending,
+                source: false,
+            },
+        );
+    }
+
+    if extending {
+        vacated.retain(|pos| *pos != head_pos);
+        start_moving(
+            plot,
+            head_pos,
+            Block::MovingPiston(direction, sticky),
+            MovingPistonEntity {
+                block_state: Block::PistonHead(PistonHead::new(sticky, direction, false)).get_id(),
+                facing: direction,
+                extending,
+                source: true,
+            },
+        );
+    }
+
+    for vacated_pos in &vacated {
+        plot.set_block(*vacated_pos, Block::Air);
+    }
+    for vacated_pos in &vacated {
+        Block::change_surrounding_blocks(plot, *vacated_pos);
+        Block::update_surrounding_blocks(plot, *vacated_pos);
+    }
+    for destroy_pos in to_destroy.iter().rev() {
+        Block::change_surrounding_blocks(plot, *destroy_pos);
+        Block::update_surrounding_blocks(plot, *destroy_pos);
+    }
+    for block_pos in to_push.iter().rev() {
+        Block::update_surrounding_blocks(plot, *block_pos);
+    }
+    if extending {
+        Block::update_surrounding_blocks(plot, head_pos);
+    }
+    true
+}
+
+fn start_moving(plot: &mut Plot, pos: BlockPos, block: Block, entity: MovingPistonEntity) {
+    // Ticks scheduled for the block that was here are no longer valid
+    plot.remove_pending_ticks_at(pos);
+    plot.set_block(pos, block);
+    plot.set_block_entity(pos, BlockEntity::MovingPiston(entity));
+    // Blocks take 2 game ticks to move, which is one redstone tick
+    plot.schedule_tick(pos, 1, TickPriority::Normal);
+}
+
+/// Places the block that a moving piston was carrying.
+pub fn finish_moving_piston(plot: &mut Plot, pos: BlockPos) {
+    let entity = match plot.get_block_entity(pos) {
+        Some(BlockEntity::MovingPiston(entity)) => entity.clone(),
+        _ => {
+            plot.set_block(pos, Block::Air);
+            return;
+        }
+    };
+    plot.delete_block_entity(pos);
+    plot.remove_pending_ticks_at(pos);
+
+    let block = match Block::from_block_state(entity.block_state) {
+        // Powered observers turn off when they are moved
+        Block::Observer(facing, true) => {
+            plot.set_block(pos, Block::Observer(facing, false));
+            Block::observer_on_state_change(plot, pos, facing);
+            Block::Observer(facing, false)
+        }
+        block => {
+            plot.set_block(pos, block);
+            block
+        }
+    };
+    Block::change_surrounding_blocks(plot, pos);
+    Block::update_surrounding_blocks(plot, pos);
+    block.update(plot, pos);
+}
+
+#[cfg(test)]
+const TEST_PISTON_POS: BlockPos = BlockPos { x: 5, y: 10, z: 5 };
+
+/// Returns the position `n` blocks in front of the piston in the piston tests.
+#[cfg(test)]
+fn in_front(n: usize) -> BlockPos {
+    BlockPos::new(6 + n as i32, 10, 5)
+}
+
+/// Builds a piston facing east with `blocks` in a line in front of it, and a lever
+/// next to it which powers it.
+#[cfg(test)]
+fn build_piston(sticky: bool, blocks: &[Block]) -> Plot {
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let piston = Piston::new(sticky, BlockFacing::East, false);
+    plot.set_block(TEST_PISTON_POS, Block::Piston(piston));
+    let mut lever = Block::from_name("lever").unwrap();
+    lever.set_property("face", "floor");
+    plot.set_block(BlockPos::new(5, 10, 4), lever);
+    for (n, block) in blocks.iter().enumerate() {
+        plot.set_block(in_front(n), *block);
+    }
+    plot
+}
+
+/// Flicks the lever of a piston built by `build_piston` and waits for the piston to
+/// finish moving.
+#[cfg(test)]
+fn flick_piston_lever(plot: &mut Plot) {
+    plot.use_block(BlockPos::new(5, 10, 4));
+    for _ in 0..3 {
+        plot.tick();
+    }
+}
+
+#[cfg(test)]
+fn is_extended(plot: &Plot) -> bool {
+    matches!(plot.get_block(TEST_PISTON_POS), Block::Piston(piston) if piston.extended)
+}
+
+#[test]
+fn piston_push_test() {
+    let stone = Block::Solid(1);
+    let mut plot = build_piston(false, &[stone; 3]);
+    flick_piston_lever(&mut plot);
+    assert!(is_extended(&plot));
+    assert_eq!(
+        plot.get_block(in_front(0)),
+        Block::PistonHead(PistonHead::new(false, BlockFacing::East, false))
+    );
+    for n in 1..4 {
+        assert_eq!(plot.get_block(in_front(n)), stone);
+    }
+
+    // Normal pistons leave the blocks they pushed behind
+    flick_piston_lever(&mut plot);
+    assert!(!is_extended(&plot));
+    assert_eq!(plot.get_block(in_front(0)), Block::Air);
+    assert_eq!(plot.get_block(in_front(1)), stone);
+}
+
+#[test]
+fn piston_push_limit_test() {
+    let stone = Block::Solid(1);
+    let mut plot = build_piston(false, &[stone; PUSH_LIMIT]);
+    flick_piston_lever(&mut plot);
+    assert!(is_extended(&plot));
+    assert_eq!(plot.get_block(in_front(PUSH_LIMIT)), stone);
+
+    let mut plot = build_piston(false, &[stone; PUSH_LIMIT + 1]);
+    flick_piston_lever(&mut plot);
+    assert!(!is_extended(&plot));
+    assert_eq!(plot.get_block(in_front(0)), stone);
+    assert_eq!(plot.get_block(in_front(PUSH_LIMIT + 1)), Block::Air);
+}
+
+#[test]
+fn piston_immovable_test() {
+    let stone = Block::Solid(1);
+    for &immovable in &[OBSIDIAN, BEDROCK] {
+        let immovable = Block::from_block_state(immovable);
+        let mut plot = build_piston(false, &[stone, immovable]);
+        flick_piston_lever(&mut plot);
+        assert!(!is_extended(&plot));
+        assert_eq!(plot.get_block(in_front(0)), stone);
+        assert_eq!(plot.get_block(in_front(1)), immovable);
+    }
+}
+
+#[test]
+fn sticky_piston_retract_test() {
+    let stone = Block::Solid(1);
+    let mut plot = build_piston(true, &[stone]);
+    flick_piston_lever(&mut plot);
+    assert!(is_extended(&plot));
+    assert_eq!(plot.get_block(in_front(1)), stone);
+
+    flick_piston_lever(&mut plot);
+    assert!(!is_extended(&plot));
+    assert_eq!(plot.get_block(in_front(0)), stone);
+    assert_eq!(plot.get_block(in_front(1)), Block::Air);
+
+    // Blocks that can't be pushed aren't pulled either
+    let obsidian = Block::from_block_state(OBSIDIAN);
+    let mut plot = build_piston(true, &[Block::Air, obsidian]);
+    flick_piston_lever(&mut plot);
+    assert!(is_extended(&plot));
+    flick_piston_lever(&mut plot);
+    assert!(!is_extended(&plot));
+    assert_eq!(plot.get_block(in_front(0)), Block::Air);
+    assert_eq!(plot.get_block(in_front(1)), obsidian);
+}
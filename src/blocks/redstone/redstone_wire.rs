@@ -32,6 +32,14 @@ impl RedstoneWireSide {
             _ => RedstoneWireSide::None,
         }
     }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            RedstoneWireSide::Up => "up",
+            RedstoneWireSide::Side => "side",
+            RedstoneWireSide::None => "none",
+        }
+    }
 }
 
 impl Default for RedstoneWireSide {
@@ -147,7 +155,7 @@ impl RedstoneWire {
             Block::RedstoneRepeater(repeater) => {
                 repeater.facing == side || repeater.facing == side.opposite()
             }
-            Block::Observer(facing) => facing == side.block_facing(),
+            Block::Observer(facing, _) => facing == side.block_facing(),
             _ => false,
         }
     }
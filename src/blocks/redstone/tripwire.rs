@@ -0,0 +1,221 @@
+use crate::blocks::{Block, BlockDirection, BlockPos};
+use crate::plot::{Plot, TickPriority};
+
+/// The most tripwire that can be strung between two hooks
+const MAX_LENGTH: u32 = 40;
+
+/// How many redstone ticks tripwire stays powered after a player was last seen
+/// touching it. This is also how long hooks wait before checking tripwire that was
+/// changed.
+const CHECK_DELAY: u32 = 5;
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct TripwireHook {
+    /// The direction the tripwire goes off in, away from the block the hook is on
+    pub facing: BlockDirection,
+    pub attached: bool,
+    pub powered: bool,
+}
+
+impl TripwireHook {
+    pub(in crate::blocks) fn new(
+        facing: BlockDirection,
+        attached: bool,
+        powered: bool,
+    ) -> TripwireHook {
+        TripwireHook {
+            facing,
+            attached,
+            powered,
+        }
+    }
+
+    fn set(self, plot: &mut Plot, pos: BlockPos) {
+        if plot.get_block(pos) == Block::TripwireHook(self) {
+            return;
+        }
+        plot.reset_redpiler();
+        plot.set_block(pos, Block::TripwireHook(self));
+        Block::update_surrounding_blocks(plot, pos);
+        Block::update_surrounding_blocks(plot, pos.offset(self.facing.opposite().block_face()));
+    }
+
+    /// Looks for a hook facing this one at the other end of the tripwire, then updates
+    /// both hooks and the tripwire between them. `removing` is set when this hook is
+    /// being broken. `changed` is the distance to tripwire which is being placed or
+    /// broken along with its state, which the world might not have yet.
+    pub fn update_line(
+        self,
+        plot: &mut Plot,
+        pos: BlockPos,
+        removing: bool,
+        changed: Option<(u32, Tripwire)>,
+    ) {
+        let face = self.facing.block_face();
+        let mut attached = !removing;
+        let mut powered = false;
+        let mut other_distance = None;
+        let mut line_pos = pos;
+        for distance in 1..=MAX_LENGTH + 1 {
+            line_pos = line_pos.offset(face);
+            let block = plot.get_block(line_pos);
+            if let Block::TripwireHook(hook) = block {
+                if hook.facing == self.facing.opposite() {
+                    other_distance = Some(distance);
+                }
+                break;
+            }
+            let tripwire = match (changed, block) {
+                (Some((changed_distance, tripwire)), _) if changed_distance == distance => {
+                    plot.schedule_tick(pos, CHECK_DELAY, TickPriority::Normal);
+                    attached &= !tripwire.disarmed;
+                    Some(tripwire)
+                }
+                (_, Block::Tripwire(tripwire)) => Some(tripwire),
+                _ => None,
+            };
+            match tripwire {
+                Some(tripwire) => powered |= tripwire.powered && !tripwire.disarmed,
+                None => attached = false,
+            }
+        }
+        // Hooks right next to each other have nothing to attach to
+        attached &= other_distance.is_some_and(|distance| distance > 1);
+        powered &= attached;
+
+        if let Some(distance) = other_distance {
+            let other_pos = (0..distance).fold(pos, |pos, _| pos.offset(face));
+            TripwireHook::new(self.facing.opposite(), attached, powered).set(plot, other_pos);
+        }
+        if !removing {
+            TripwireHook::new(self.facing, attached, powered).set(plot, pos);
+        }
+        if attached != self.attached {
+            let mut line_pos = pos;
+            for _ in 1..other_distance.unwrap_or(0) {
+                line_pos = line_pos.offset(face);
+                if let Block::Tripwire(mut tripwire) = plot.get_block(line_pos) {
+                    tripwire.attached = attached;
+                    plot.set_block(line_pos, Block::Tripwire(tripwire));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Tripwire {
+    pub north: bool,
+    pub south: bool,
+    pub east: bool,
+    pub west: bool,
+    /// Set when there are hooks on both ends of the tripwire
+    pub attached: bool,
+    /// Disarmed tripwire doesn't power the hooks when it gets broken or touched
+    pub disarmed: bool,
+    pub powered: bool,
+}
+
+impl Tripwire {
+    fn connects(plot: &Plot, pos: BlockPos, direction: BlockDirection) -> bool {
+        match plot.get_block(pos.offset(direction.block_face())) {
+            Block::Tripwire(_) => true,
+            Block::TripwireHook(hook) => hook.facing == direction.opposite(),
+            _ => false,
+        }
+    }
+
+    /// Returns the tripwire with its sides connected to the tripwire and hooks next to it.
+    pub fn update_sides(mut self, plot: &Plot, pos: BlockPos) -> Tripwire {
+        self.north = Tripwire::connects(plot, pos, BlockDirection::North);
+        self.south = Tripwire::connects(plot, pos, BlockDirection::South);
+        self.east = Tripwire::connects(plot, pos, BlockDirection::East);
+        self.west = Tripwire::connects(plot, pos, BlockDirection::West);
+        self
+    }
+
+    /// Powers the tripwire if a player is touching it and lets the hooks know when that
+    /// changes. While a player is still touching it, it gets checked again later.
+    pub fn check_powered(mut self, plot: &mut Plot, pos: BlockPos) {
+        let (x, y, z) = (pos.x as f64, pos.y as f64, pos.z as f64);
+        // Attached tripwire hangs a little above the ground
+        let (min_y, max_y) = if self.attached {
+            (y + 0.0625, y + 0.15625)
+        } else {
+            (y, y + 0.5)
+        };
+        let powered = plot.players_in_box([x, min_y, z], [x + 1.0, max_y, z + 1.0]) > 0;
+        if powered != self.powered {
+            self.powered = powered;
+            plot.set_block(pos, Block::Tripwire(self));
+            self.notify_hooks(plot, pos);
+        }
+        if powered {
+            plot.schedule_tick(pos, CHECK_DELAY, TickPriority::Normal);
+        }
+    }
+
+    /// Lets the hooks at the ends of the tripwire know that the tripwire at `pos`
+    /// changed to `self`. Only the hooks to the south and west are updated because
+    /// they update the hooks across from them.
+    pub fn notify_hooks(self, plot: &mut Plot, pos: BlockPos) {
+        for &direction in &[BlockDirection::South, BlockDirection::West] {
+            let mut hook_pos = pos;
+            for distance in 1..=MAX_LENGTH + 1 {
+                hook_pos = hook_pos.offset(direction.block_face());
+                match plot.get_block(hook_pos) {
+                    Block::TripwireHook(hook) => {
+                        if hook.facing == direction.opposite() {
+                            hook.update_line(plot, hook_pos, false, Some((distance, self)));
+                        }
+                        break;
+                    }
+                    Block::Tripwire(_) => {}
+                    _ => break,
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn tripwire_hook_test() {
+    use crate::blocks::BlockFace;
+
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let wall = BlockPos::new(5, 10, 5);
+    let hook_pos = BlockPos::new(6, 10, 5);
+    let other_pos = BlockPos::new(10, 10, 5);
+    plot.set_block(wall, Block::Solid(1));
+    plot.set_block(BlockPos::new(11, 10, 5), Block::Solid(1));
+    let hook = TripwireHook::new(BlockDirection::East, false, false);
+    Block::TripwireHook(hook).place_in_plot(&mut plot, hook_pos, &None);
+    let other = TripwireHook::new(BlockDirection::West, false, false);
+    Block::TripwireHook(other).place_in_plot(&mut plot, other_pos, &None);
+    for x in 7..10 {
+        let pos = BlockPos::new(x, 10, 5);
+        Block::Tripwire(Tripwire::default()).place_in_plot(&mut plot, pos, &None);
+    }
+    let attached = Block::TripwireHook(TripwireHook::new(BlockDirection::West, true, false));
+    assert_eq!(plot.get_block(other_pos), attached);
+    assert!(matches!(
+        plot.get_block(BlockPos::new(8, 10, 5)),
+        Block::Tripwire(Tripwire {
+            attached: true,
+            east: true,
+            west: true,
+            ..
+        })
+    ));
+
+    // Breaking the tripwire sets off the hooks until they notice it is gone
+    let middle = BlockPos::new(8, 10, 5);
+    plot.get_block(middle).destroy(&mut plot, middle);
+    let powered = Block::TripwireHook(TripwireHook::new(BlockDirection::East, true, true));
+    assert_eq!(plot.get_block(hook_pos), powered);
+    assert_eq!(
+        plot.get_block(wall)
+            .get_redstone_power(&plot, wall, BlockFace::East),
+        15
+    );
+}
@@ -0,0 +1,196 @@
+use crate::blocks::Block;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Who may build on plots that nobody has claimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnclaimedPlotPolicy {
+    /// Anyone can build
+    Allow,
+    /// Only players with the `protection.bypass` permission can build
+    Deny,
+}
+
+/// How new plots are generated. Changing the plot size only works for new worlds
+/// since plot files store a fixed number of chunks.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneratorConfig {
+    /// The width of a plot in blocks including its road and border. This has to be a
+    /// multiple of 16.
+    pub plot_size: i32,
+    /// How many blocks of road there are on each side of a plot
+    pub road_width: i32,
+    pub road_block: String,
+    /// The block used for the ring between the road and the inside of a plot
+    pub border_block: String,
+    /// A block placed on top of the border, such as a slab
+    pub wall_block: Option<String>,
+    /// How many blocks tall the floor is. Set this to 0 for void plots.
+    pub floor_height: u32,
+    /// The blocks of the floor from the top down. The last block fills the rest of
+    /// the floor.
+    pub floor_layers: Vec<String>,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> GeneratorConfig {
+        GeneratorConfig {
+            plot_size: 256,
+            road_width: 0,
+            road_block: "stone".to_string(),
+            border_block: "stone_bricks".to_string(),
+            wall_block: None,
+            floor_height: 8,
+            floor_layers: vec!["sandstone".to_string()],
+        }
+    }
+}
+
+impl GeneratorConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.plot_size <= 0 || self.plot_size % 16 != 0 {
+            return Err("plot_size has to be a positive multiple of 16".to_string());
+        }
+        if self.road_width < 0 || self.road_width * 2 >= self.plot_size {
+            return Err("road_width has to fit inside of the plot".to_string());
+        }
+        if self.floor_height > 0 && self.floor_layers.is_empty() {
+            return Err("floor_layers can't be empty".to_string());
+        }
+        let blocks = [&self.road_block, &self.border_block];
+        let blocks = blocks
+            .iter()
+            .copied()
+            .chain(&self.wall_block)
+            .chain(&self.floor_layers);
+        for name in blocks {
+            if Block::from_name(name).is_none() {
+                return Err(format!("Unknown block: {}", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const CONFIG_PATH: &str = "Config.toml";
+
+lazy_static! {
+    /// The server config, loaded from `Config.toml` the first time it is used.
+    pub static ref CONFIG: ServerConfig = ServerConfig::load(CONFIG_PATH);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub motd: String,
+    pub chat_format: String,
+    pub max_players: i64,
+    /// The amount of memory in megabytes each player's worldedit history may use.
+    pub worldedit_history_mb: u64,
+    /// Whether players are authenticated with the session server. Connections are
+    /// encrypted in online mode.
+    pub online_mode: bool,
+    /// The session server used in online mode. This can point at a local stand-in for
+    /// testing.
+    pub session_server: String,
+    /// Whether plots that keep ticking are loaded again from their last save after
+    /// they crash. Other plots are loaded again when someone enters them.
+    pub restart_crashed_plots: bool,
+    pub unclaimed_plot_policy: UnclaimedPlotPolicy,
+    pub generator: GeneratorConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind_address: "0.0.0.0:25565".to_string(),
+            motd: "Minecraft High Performace Redstone Server".to_string(),
+            chat_format: "<{username}> {message}".to_string(),
+            max_players: 99999,
+            worldedit_history_mb: 64,
+            online_mode: false,
+            session_server: "https://sessionserver.mojang.com".to_string(),
+            restart_crashed_plots: true,
+            unclaimed_plot_policy: UnclaimedPlotPolicy::Allow,
+            generator: Default::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the config, using the defaults for anything that's missing. If the file
+    /// can't be parsed, all of the defaults are used so that it can be fixed. Loading
+    /// never writes to disk.
+    fn load(path: &str) -> ServerConfig {
+        let mut config: ServerConfig = match fs::read_to_string(path) {
+            Ok(config_string) => match toml::from_str(&config_string) {
+                Ok(config) => config,
+                Err(err) => {
+                    error!("Unable to parse {}, using the defaults: {}", path, err);
+                    return ServerConfig::default();
+                }
+            },
+            Err(_) => ServerConfig::default(),
+        };
+        if let Err(err) = config.generator.validate() {
+            error!(
+                "Invalid generator in {}, using the default generator: {}",
+                path, err
+            );
+            config.generator = Default::default();
+        }
+        config
+    }
+
+    /// Writes the config to `path` if there is no file there yet, so that the options
+    /// of a new server can be found and changed.
+    pub fn save_if_missing(&self, path: &str) {
+        if Path::new(path).exists() {
+            return;
+        }
+        let config_string = toml::to_string(self).unwrap();
+        if let Err(err) = fs::write(path, &config_string) {
+            warn!("Unable to write {}: {}", path, err);
+        }
+    }
+}
+
+#[test]
+fn invalid_config_test() {
+    let dir = std::env::temp_dir().join(format!("mchprs_config_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("Config.toml");
+    let path = path.to_str().unwrap();
+
+    // Mistyped options fall back to the defaults without touching the file
+    fs::write(path, "max_players = \"lots\"\n").unwrap();
+    let config = ServerConfig::load(path);
+    assert_eq!(config.max_players, 99999);
+    assert_eq!(
+        fs::read_to_string(path).unwrap(),
+        "max_players = \"lots\"\n"
+    );
+
+    // An invalid generator is replaced by the default one
+    fs::write(path, "motd = \"hi\"\n[generator]\nplot_size = 100\n").unwrap();
+    let config = ServerConfig::load(path);
+    assert_eq!(config.motd, "hi");
+    assert_eq!(config.generator.plot_size, 256);
+
+    // Missing configs are only written when asked to
+    fs::remove_file(path).unwrap();
+    let config = ServerConfig::load(path);
+    assert!(!Path::new(path).exists());
+    config.save_if_missing(path);
+    assert_eq!(ServerConfig::load(path).motd, config.motd);
+    fs::write(path, "motd = \"hi\"\n").unwrap();
+    config.save_if_missing(path);
+    assert_eq!(fs::read_to_string(path).unwrap(), "motd = \"hi\"\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
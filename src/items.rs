@@ -1,4 +1,4 @@
-use crate::blocks::{Block, BlockDirection, BlockFace, BlockPos};
+use crate::blocks::{Block, BlockDirection, BlockFace, BlockFacing, BlockPos};
 use crate::plot::Plot;
 
 #[derive(PartialEq, Copy, Clone)]
@@ -18,6 +18,7 @@ pub struct UseOnBlockContext {
     pub block_face: BlockFace,
     pub player_crouching: bool,
     pub player_direction: BlockDirection,
+    pub player_facing: BlockFacing,
     pub player_idx: usize,
 }
 
@@ -82,6 +83,8 @@ pub enum Item {
     /// it is NOT a block state id.
     BlockItem(u32),
     WEWand,
+    /// Right clicking a block with this adds or removes a redstone probe
+    RedstoneProbe,
     Snowball,
     TotemOfUndying,
     Unknown(u32),
@@ -92,19 +95,26 @@ impl Item {
         match id {
             64 => Item::BlockItem(id),
             68 => Item::BlockItem(id),
-            80 => Item::BlockItem(id),
+            74 => Item::BlockItem(id),
+            80..=81 => Item::BlockItem(id),
             82..=97 => Item::BlockItem(id),
             160 => Item::BlockItem(id),
-            164 => Item::BlockItem(id),
+            164..=171 => Item::BlockItem(id),
             173 => Item::BlockItem(id),
             174 => Item::BlockItem(id),
             234 => Item::BlockItem(id),
+            238 => Item::BlockItem(id),
+            269..=270 => Item::BlockItem(id),
             272 => Item::BlockItem(id),
             274 => Item::BlockItem(id),
+            280 => Item::BlockItem(id),
             281..=296 => Item::BlockItem(id),
+            379 => Item::BlockItem(id),
             413..=428 => Item::BlockItem(id),
             513..=514 => Item::BlockItem(id),
             536 => Item::WEWand,
+            545 => Item::RedstoneProbe,
+            552 => Item::BlockItem(id),
             600 => Item::BlockItem(id),
             601 => Item::Snowball,
             836 => Item::TotemOfUndying,
@@ -116,6 +126,7 @@ impl Item {
     pub fn get_id(self) -> u32 {
         match self {
             Item::WEWand => 536,
+            Item::RedstoneProbe => 545,
             Item::Snowball => 601,
             Item::TotemOfUndying => 836,
             Item::BlockItem(id) => id,
@@ -127,14 +138,34 @@ impl Item {
         match name {
             "snowball" => Some(Item::Snowball),
             "totem_of_undying" => Some(Item::TotemOfUndying),
+            "redstone" => Some(Item::BlockItem(600)),
             _ => None,
         }
     }
 
-    pub fn max_stack_size(self) -> u32 {
+    /// The name of the item without the `minecraft:` namespace, if it is known.
+    pub fn get_name(self) -> Option<&'static str> {
         match self {
-            Item::Snowball => 16,
-            Item::TotemOfUndying => 1,
+            Item::Snowball => Some("snowball"),
+            Item::TotemOfUndying => Some("totem_of_undying"),
+            Item::BlockItem(600) => Some("redstone"),
+            _ => None,
+        }
+    }
+
+    pub fn max_stack_size(self) -> u32 {
+        match self.get_id() {
+            // Shulker boxes
+            380..=396 => 1,
+            // Tools, weapons, armor, buckets of things, boats, minecarts, beds,
+            // potions, music discs and other unstackable items
+            518 | 520..=523 | 525 | 532..=544 | 547..=551 | 555..=559 | 563..=582 => 1,
+            596..=599 | 602 | 604..=608 | 618..=619 | 622 | 653..=669 | 672 | 687 => 1,
+            758 | 775 | 780 | 783..=784 | 789 | 793..=796 | 799 | 823 | 825 => 1,
+            828..=836 | 839..=853 | 857..=858 | 860..=864 => 1,
+            // Signs, buckets, snowballs, eggs, ender pearls, written books, armor
+            // stands, banners and honey bottles
+            589..=595 | 601 | 620 | 682 | 759 | 792 | 802..=817 | 881 => 16,
             _ => 64,
         }
     }
@@ -0,0 +1,16 @@
+mod config;
+mod network;
+mod permissions;
+#[macro_use]
+pub mod blocks;
+mod items;
+mod player;
+pub mod plot;
+mod redpiler;
+pub mod server;
+
+#[macro_use]
+extern crate bitflags;
+
+#[macro_use]
+extern crate lazy_static;
@@ -1,11 +1,18 @@
 pub mod packets;
+pub mod session;
 
+use aes::Aes128;
+use cfb8::cipher::inout::InOutBuf;
+use cfb8::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
 use packets::{DecodeResult, PacketDecoder, PacketEncoder};
 use std::io::{self, BufRead, BufReader, Write};
 use std::net::{Shutdown, TcpListener, TcpStream};
 use std::sync::mpsc;
 use std::thread;
 
+type Encryptor = cfb8::Encryptor<Aes128>;
+type Decryptor = cfb8::Decryptor<Aes128>;
+
 #[derive(PartialEq, Clone)]
 pub enum NetworkState {
     Handshake,
@@ -25,15 +32,50 @@ pub struct NetworkClient {
     pub username: Option<String>,
     pub alive: bool,
     pub compressed: bool,
+    /// The token sent in the encryption request, which the client has to send back
+    pub verify_token: Option<[u8; 4]>,
+    /// The ciphers for incoming and outgoing data once encryption has been enabled
+    encryption: Option<(Decryptor, Encryptor)>,
+    /// Data that was received and decrypted, but doesn't make up a whole packet yet
+    incoming_data: Vec<u8>,
+    /// The number of packets sent since `take_packets_sent` was last called
+    packets_sent: u64,
 }
 
 impl NetworkClient {
+    fn new(id: u32, stream: TcpStream) -> NetworkClient {
+        NetworkClient {
+            id,
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+            state: NetworkState::Handshake,
+            packets: Vec::new(),
+            username: None,
+            alive: true,
+            compressed: false,
+            verify_token: None,
+            encryption: None,
+            incoming_data: Vec::new(),
+            packets_sent: 0,
+        }
+    }
+
+    /// Connects a client to a local socket, returning the other end of the connection.
+    #[cfg(test)]
+    pub fn connect_local(id: u32) -> (NetworkClient, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        (NetworkClient::new(id, stream), remote)
+    }
+
     pub fn update(&mut self) -> DecodeResult<()> {
         if !self.alive {
             return Ok(());
         };
         let mut would_block = false;
-        let incoming_data = Vec::from(match self.reader.fill_buf() {
+        let mut data = Vec::from(match self.reader.fill_buf() {
             Ok(data) => data,
             Err(e) => match e.kind() {
                 io::ErrorKind::WouldBlock => {
@@ -46,12 +88,19 @@ impl NetworkClient {
                 }
             },
         });
-        let data_length = incoming_data.len();
-        let mut incoming_packets = PacketDecoder::decode(self.compressed, incoming_data)?;
-        if !incoming_packets.is_empty() {
-            self.packets.append(&mut incoming_packets);
-        }
+        let data_length = data.len();
         self.reader.consume(data_length);
+        // Each byte is decrypted exactly once, right after it was taken out of the
+        // reader, because the decryptor's state depends on every byte before it
+        if let Some((decryptor, _)) = &mut self.encryption {
+            let (blocks, _) = InOutBuf::from(data.as_mut_slice()).into_chunks();
+            decryptor.decrypt_blocks_inout_mut(blocks);
+        }
+        self.incoming_data.append(&mut data);
+        let (mut incoming_packets, used) =
+            PacketDecoder::decode(self.compressed, &self.incoming_data)?;
+        self.incoming_data.drain(..used);
+        self.packets.append(&mut incoming_packets);
         if !would_block && data_length == 0 {
             self.alive = false;
         }
@@ -59,11 +108,30 @@ impl NetworkClient {
     }
 
     pub fn send_packet(&mut self, data: &PacketEncoder) {
-        if self.compressed {
-            self.stream.write_all(&data.compressed());
+        let mut data = if self.compressed {
+            data.compressed()
         } else {
-            self.stream.write_all(&data.uncompressed());
+            data.uncompressed()
+        };
+        if let Some((_, encryptor)) = &mut self.encryption {
+            let (blocks, _) = InOutBuf::from(data.as_mut_slice()).into_chunks();
+            encryptor.encrypt_blocks_inout_mut(blocks);
         }
+        self.stream.write_all(&data);
+        self.packets_sent += 1;
+    }
+
+    /// Returns the number of packets sent since this was last called.
+    pub fn take_packets_sent(&mut self) -> u64 {
+        std::mem::take(&mut self.packets_sent)
+    }
+
+    /// Encrypts all data sent and received from now on using the shared secret.
+    pub fn enable_encryption(&mut self, shared_secret: &[u8; 16]) {
+        // The shared secret is used as both the key and the initial vector
+        let decryptor = Decryptor::new(shared_secret.into(), shared_secret.into());
+        let encryptor = Encryptor::new(shared_secret.into(), shared_secret.into());
+        self.encryption = Some((decryptor, encryptor));
     }
 
     pub fn close_connection(&mut self) {
@@ -86,18 +154,9 @@ impl NetworkServer {
         for (index, stream) in listener.incoming().enumerate() {
             let stream = stream.unwrap();
             stream.set_nonblocking(true).unwrap();
+            // The index will increment after each client making it unique. We'll just use this as the id.
             sender
-                .send(NetworkClient {
-                    // The index will increment after each client making it unique. We'll just use this as the id.
-                    id: index as u32,
-                    reader: BufReader::new(stream.try_clone().unwrap()),
-                    stream,
-                    state: NetworkState::Handshake,
-                    packets: Vec::new(),
-                    username: None,
-                    alive: true,
-                    compressed: false,
-                })
+                .send(NetworkClient::new(index as u32, stream))
                 .unwrap();
         }
     }
@@ -127,3 +186,38 @@ impl NetworkServer {
         }
     }
 }
+
+#[test]
+fn split_encrypted_packet_test() {
+    use packets::serverbound::{S03ChatMessage, ServerBoundPacket};
+    use std::time::{Duration, Instant};
+
+    let (mut client, mut remote) = NetworkClient::connect_local(0);
+    let shared_secret = [7; 16];
+    client.enable_encryption(&shared_secret);
+
+    // A chat message saying "hello", encrypted the way the client would
+    let mut data = vec![7, 0x03, 5, b'h', b'e', b'l', b'l', b'o'];
+    let mut encryptor = Encryptor::new(&shared_secret.into(), &shared_secret.into());
+    let (blocks, _) = InOutBuf::from(data.as_mut_slice()).into_chunks();
+    encryptor.encrypt_blocks_inout_mut(blocks);
+
+    fn update_until(client: &mut NetworkClient, done: impl Fn(&NetworkClient) -> bool) {
+        let start = Instant::now();
+        while !done(client) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            client.update().unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    remote.write_all(&data[..3]).unwrap();
+    update_until(&mut client, |client| client.incoming_data.len() == 3);
+    assert!(client.packets.is_empty());
+    remote.write_all(&data[3..]).unwrap();
+    update_until(&mut client, |client| !client.packets.is_empty());
+    assert!(client.incoming_data.is_empty());
+
+    let packet = client.packets.remove(0);
+    assert_eq!(packet.packet_id, 0x03);
+    assert_eq!(S03ChatMessage::decode(packet).unwrap().message, "hello");
+}
@@ -45,6 +45,24 @@ impl ClientBoundPacket for C01Pong {
     }
 }
 
+pub struct C01EncryptionRequest {
+    pub server_id: String,
+    pub public_key: Vec<u8>,
+    pub verify_token: Vec<u8>,
+}
+
+impl ClientBoundPacket for C01EncryptionRequest {
+    fn encode(self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_string(20, &self.server_id);
+        buf.write_varint(self.public_key.len() as i32);
+        buf.write_bytes(self.public_key);
+        buf.write_varint(self.verify_token.len() as i32);
+        buf.write_bytes(self.verify_token);
+        PacketEncoder::new(buf, 0x01)
+    }
+}
+
 pub struct C02LoginSuccess {
     pub uuid: u128,
     pub username: String,
@@ -197,6 +215,8 @@ pub enum C12DeclareCommandsNodeParser {
     Integer(i32, i32),
     BlockPos,
     BlockState,
+    Word,
+    GreedyString,
 }
 
 impl C12DeclareCommandsNodeParser {
@@ -210,6 +230,14 @@ impl C12DeclareCommandsNodeParser {
             Vec3 => buf.write_string(32767, "minecraft:vec3"),
             BlockPos => buf.write_string(32767, "minecraft:block_pos"),
             BlockState => buf.write_string(32767, "minecraft:block_state"),
+            Word => {
+                buf.write_string(32767, "brigadier:string");
+                buf.write_varint(0); // SINGLE_WORD
+            }
+            GreedyString => {
+                buf.write_string(32767, "brigadier:string");
+                buf.write_varint(2); // GREEDY_PHRASE
+            }
             Integer(min, max) => {
                 buf.write_string(32767, "brigadier:integer");
                 buf.write_byte(3); // Supply min and max value
@@ -258,6 +286,34 @@ impl ClientBoundPacket for C12DeclareCommands {
     }
 }
 
+pub struct C13WindowConfirmation {
+    pub window_id: i8,
+    pub action_number: i16,
+    pub accepted: bool,
+}
+
+impl ClientBoundPacket for C13WindowConfirmation {
+    fn encode(self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_byte(self.window_id);
+        buf.write_short(self.action_number);
+        buf.write_bool(self.accepted);
+        PacketEncoder::new(buf, 0x13)
+    }
+}
+
+pub struct C14CloseWindow {
+    pub window_id: u8,
+}
+
+impl ClientBoundPacket for C14CloseWindow {
+    fn encode(self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_unsigned_byte(self.window_id);
+        PacketEncoder::new(buf, 0x14)
+    }
+}
+
 pub struct C15WindowItems {
     pub window_id: u8,
     pub slot_data: Vec<Option<SlotData>>,
@@ -286,6 +342,33 @@ impl ClientBoundPacket for C15WindowItems {
     }
 }
 
+pub struct C17SetSlot {
+    pub window_id: i8,
+    pub slot: i16,
+    pub slot_data: Option<SlotData>,
+}
+
+impl ClientBoundPacket for C17SetSlot {
+    fn encode(self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_byte(self.window_id);
+        buf.write_short(self.slot);
+        if let Some(slot) = self.slot_data {
+            buf.write_bool(true);
+            buf.write_varint(slot.item_id);
+            buf.write_byte(slot.item_count);
+            if let Some(nbt) = slot.nbt {
+                buf.write_nbt_blob(nbt);
+            } else {
+                buf.write_byte(0); // End tag
+            }
+        } else {
+            buf.write_bool(false);
+        }
+        PacketEncoder::new(buf, 0x17)
+    }
+}
+
 pub struct C19PluginMessageBrand {
     pub brand: String,
 }
@@ -519,6 +602,22 @@ impl ClientBoundPacket for C2CEntityMovement {
     }
 }
 
+pub struct C2FOpenWindow {
+    pub window_id: i32,
+    pub window_type: i32,
+    pub window_title: String,
+}
+
+impl ClientBoundPacket for C2FOpenWindow {
+    fn encode(self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_varint(self.window_id);
+        buf.write_varint(self.window_type);
+        buf.write_string(32767, &self.window_title);
+        PacketEncoder::new(buf, 0x2F)
+    }
+}
+
 pub struct C32PlayerAbilities {
     pub flags: u8,
     pub fly_speed: f32,
@@ -536,9 +635,9 @@ impl ClientBoundPacket for C32PlayerAbilities {
 }
 
 pub struct C34PlayerInfoAddPlayerProperty {
-    name: String,
-    value: String,
-    signature: Option<String>,
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
 }
 
 pub struct C34PlayerInfoAddPlayer {
@@ -51,54 +51,52 @@ pub struct PacketDecoder {
 }
 
 impl PacketDecoder {
-    pub fn decode(compression: bool, buf: Vec<u8>) -> DecodeResult<Vec<PacketDecoder>> {
+    /// Decodes the whole packets at the start of `buf`. The number of bytes they took
+    /// up is returned along with them, so that a packet which hasn't been fully
+    /// received yet can be decoded once the rest of it arrives.
+    pub fn decode(compression: bool, buf: &[u8]) -> DecodeResult<(Vec<PacketDecoder>, usize)> {
         let mut decoders = Vec::new();
         let mut i = 0;
         while i < buf.len() {
-            let length = PacketDecoder::read_varint_from_buffer(i, &buf)?;
-            i += length.1 as usize;
-            if compression {
-                // Compression is enabled
-                let data_length = PacketDecoder::read_varint_from_buffer(i, &buf)?;
-                i += data_length.1 as usize;
-                if data_length.0 > 0 {
-                    let mut data = Vec::new();
-                    // Decompress data
-                    ZlibDecoder::new(&buf[i..i + (length.0 - data_length.1) as usize])
-                        .read_to_end(&mut data)
-                        .unwrap();
-                    i += (length.0 - data_length.1) as usize;
-                    let packet_id = PacketDecoder::read_varint_from_buffer(0, &data)?;
-                    decoders.push(PacketDecoder {
-                        buffer: Cursor::new(Vec::from(
-                            &data[packet_id.1 as usize..data_length.0 as usize],
-                        )),
-                        packet_id: packet_id.0 as u32,
-                    });
-                } else {
-                    // Even though compression is enabled, packet is not compressed because the compression 
-                    // threshold has not been reached
-                    let packet_id = PacketDecoder::read_varint_from_buffer(i, &buf)?;
-                    i += packet_id.1 as usize;
-                    let data = &buf
-                        [i..i + length.0 as usize - data_length.1 as usize - packet_id.1 as usize];
-                    decoders.push(PacketDecoder {
-                        buffer: Cursor::new(Vec::from(data)),
-                        packet_id: packet_id.0 as u32,
-                    });
-                    i += length.0 as usize - data_length.1 as usize - packet_id.1 as usize;
-                }
-            } else {
-                // Compression is disabled
-                let packet_id = PacketDecoder::read_varint_from_buffer(i, &buf)?;
-                decoders.push(PacketDecoder {
-                    buffer: Cursor::new(Vec::from(&buf[i + 1..i + length.0 as usize])),
-                    packet_id: packet_id.0 as u32,
-                });
-                i += length.0 as usize;
+            // The length itself might not have been fully received yet
+            let length_received = buf[i..].iter().take(5).any(|b| b & 0b1000_0000 == 0);
+            if !length_received && buf.len() - i < 5 {
+                break;
+            }
+            let length = PacketDecoder::read_varint_from_buffer(i, buf)?;
+            let start = i + length.1 as usize;
+            let end = start + length.0 as usize;
+            if end > buf.len() {
+                break;
             }
+            decoders.push(PacketDecoder::decode_packet(compression, &buf[start..end])?);
+            i = end;
         }
-        Ok(decoders)
+        Ok((decoders, i))
+    }
+
+    /// Decodes a single packet without its length.
+    fn decode_packet(compression: bool, buf: &[u8]) -> DecodeResult<PacketDecoder> {
+        let data = if compression {
+            let data_length = PacketDecoder::read_varint_from_buffer(0, buf)?;
+            let rest = &buf[data_length.1 as usize..];
+            if data_length.0 > 0 {
+                let mut data = Vec::new();
+                ZlibDecoder::new(rest).read_to_end(&mut data)?;
+                data
+            } else {
+                // Even though compression is enabled, packet is not compressed because the compression
+                // threshold has not been reached
+                rest.to_vec()
+            }
+        } else {
+            buf.to_vec()
+        };
+        let packet_id = PacketDecoder::read_varint_from_buffer(0, &data)?;
+        Ok(PacketDecoder {
+            buffer: Cursor::new(Vec::from(&data[packet_id.1 as usize..])),
+            packet_id: packet_id.0 as u32,
+        })
     }
 
     fn read_unsigned_byte(&mut self) -> DecodeResult<u8> {
@@ -56,6 +56,24 @@ impl ServerBoundPacket for S00LoginStart {
     }
 }
 
+pub struct S01EncryptionResponse {
+    pub shared_secret: Vec<u8>,
+    pub verify_token: Vec<u8>,
+}
+
+impl ServerBoundPacket for S01EncryptionResponse {
+    fn decode(mut decoder: PacketDecoder) -> DecodeResult<Self> {
+        let shared_secret_length = decoder.read_varint()?;
+        let shared_secret = decoder.read_bytes(shared_secret_length as usize)?;
+        let verify_token_length = decoder.read_varint()?;
+        let verify_token = decoder.read_bytes(verify_token_length as usize)?;
+        Ok(S01EncryptionResponse {
+            shared_secret,
+            verify_token,
+        })
+    }
+}
+
 pub struct S03ChatMessage {
     pub message: String,
 }
@@ -90,6 +108,44 @@ impl ServerBoundPacket for S05ClientSettings {
     }
 }
 
+pub struct S09ClickWindow {
+    pub window_id: u8,
+    pub slot: i16,
+    pub button: i8,
+    pub action_number: i16,
+    pub mode: i32,
+    // The clicked item is left out because the whole window gets sent back anyway
+}
+
+impl ServerBoundPacket for S09ClickWindow {
+    fn decode(mut decoder: PacketDecoder) -> DecodeResult<Self> {
+        let window_id = decoder.read_unsigned_byte()?;
+        let slot = decoder.read_short()?;
+        let button = decoder.read_byte()?;
+        let action_number = decoder.read_short()?;
+        let mode = decoder.read_varint()?;
+        Ok(S09ClickWindow {
+            window_id,
+            slot,
+            button,
+            action_number,
+            mode,
+        })
+    }
+}
+
+pub struct S0ACloseWindow {
+    pub window_id: u8,
+}
+
+impl ServerBoundPacket for S0ACloseWindow {
+    fn decode(mut decoder: PacketDecoder) -> DecodeResult<Self> {
+        Ok(S0ACloseWindow {
+            window_id: decoder.read_unsigned_byte()?,
+        })
+    }
+}
+
 pub struct S0BPluginMessage {
     pub channel: String,
     pub data: Vec<u8>,
@@ -0,0 +1,102 @@
+//! A client for the session server which is used to authenticate players in online
+//! mode.
+
+use super::packets::clientbound::C34PlayerInfoAddPlayerProperty;
+use num_bigint::BigInt;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GameProfile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+impl GameProfile {
+    /// Parses the uuid, which the session server sends without hyphens.
+    pub fn uuid(&self) -> Option<u128> {
+        u128::from_str_radix(&self.id.replace('-', ""), 16).ok()
+    }
+}
+
+fn url_encode(val: &str) -> String {
+    val.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Hashes the shared secret and the server's public key into the server id the client
+/// sends to the session server. Minecraft formats the digest as a signed hexadecimal
+/// number.
+pub fn server_hash(shared_secret: &[u8], public_key_der: &[u8]) -> String {
+    let digest = Sha1::new()
+        .chain_update(b"")
+        .chain_update(shared_secret)
+        .chain_update(public_key_der)
+        .finalize();
+    BigInt::from_signed_bytes_be(&digest).to_str_radix(16)
+}
+
+/// Asks the session server whether the player has joined the server with the given
+/// hash. Returns `None` if the player could not be authenticated.
+pub fn has_joined(
+    session_server: &str,
+    username: &str,
+    server_hash: &str,
+) -> Result<Option<GameProfile>, String> {
+    let url = format!(
+        "{}/session/minecraft/hasJoined?username={}&serverId={}",
+        session_server.trim_end_matches('/'),
+        url_encode(username),
+        url_encode(server_hash)
+    );
+    let response = ureq::get(&url)
+        .timeout(Duration::from_secs(10))
+        .call()
+        .map_err(|e| e.to_string())?;
+    match response.status() {
+        200 => {
+            let body = response.into_string().map_err(|e| e.to_string())?;
+            serde_json::from_str(&body)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        }
+        204 => Ok(None),
+        status => Err(format!("Session server responded with {}", status)),
+    }
+}
+
+impl From<ProfileProperty> for C34PlayerInfoAddPlayerProperty {
+    fn from(property: ProfileProperty) -> C34PlayerInfoAddPlayerProperty {
+        C34PlayerInfoAddPlayerProperty {
+            name: property.name,
+            value: property.value,
+            signature: property.signature,
+        }
+    }
+}
+
+#[test]
+fn server_hash_test() {
+    fn hash(name: &str) -> String {
+        let digest = Sha1::digest(name.as_bytes());
+        BigInt::from_signed_bytes_be(&digest).to_str_radix(16)
+    }
+    assert_eq!(hash("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+    assert_eq!(hash("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+    assert_eq!(hash("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    assert_eq!(server_hash(b"je", b"b_"), hash("jeb_"));
+}
@@ -1,9 +1,249 @@
-use config::Config;
+//! Permission nodes are dot separated names like `worldedit.set`. Groups of nodes are
+//! loaded from `Permissions.toml` and every player is in the default group. Nodes can
+//! also be granted or revoked for a single player, which is stored in the database
+//! and takes precedence over their groups. Granting `group.<name>` to a player puts
+//! them in that group.
 
-pub struct Permissions {}
+use crate::player::Player;
+use crate::plot::database;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const PERMISSIONS_PATH: &str = "Permissions.toml";
+
+lazy_static! {
+    /// The permission groups, loaded from `Permissions.toml` the first time they are used.
+    pub static ref PERMISSIONS: Permissions = Permissions::load(PERMISSIONS_PATH);
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Group {
+    /// Groups whose nodes this group also has
+    pub inherits: Vec<String>,
+    /// `*` matches every node and `worldedit.*` matches every node starting with
+    /// `worldedit.`. Nodes starting with `-` are denied instead.
+    pub nodes: Vec<String>,
+    /// The usernames or uuids of the players in this group
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    pub default_group: String,
+    pub groups: BTreeMap<String, Group>,
+}
+
+impl Default for Permissions {
+    fn default() -> Permissions {
+        let mut groups = BTreeMap::new();
+        groups.insert(
+            "default".to_string(),
+            Group {
+                nodes: [
+                    "plot.*",
+                    // Plots that keep ticking use up server time even while empty
+                    "-plot.keepticking",
+                    "worldedit.*",
+                    "redstone.*",
+                    "teleport",
+                    "speed",
+                ]
+                .iter()
+                .map(|node| node.to_string())
+                .collect(),
+                ..Default::default()
+            },
+        );
+        groups.insert(
+            "admin".to_string(),
+            Group {
+                inherits: vec!["default".to_string()],
+                nodes: vec!["*".to_string()],
+                members: Vec::new(),
+            },
+        );
+        Permissions {
+            default_group: "default".to_string(),
+            groups,
+        }
+    }
+}
+
+/// Returns how specific the pattern is if it matches the node.
+fn match_specificity(pattern: &str, node: &str) -> Option<usize> {
+    if pattern == "*" {
+        Some(0)
+    } else if pattern == node {
+        Some(pattern.len())
+    } else if pattern.ends_with(".*") && node.starts_with(&pattern[..pattern.len() - 1]) {
+        Some(pattern.len() - 1)
+    } else {
+        None
+    }
+}
+
+/// Finds the value of the most specific pattern that matches the node. Denying wins
+/// if two patterns are equally specific.
+fn lookup<'a>(entries: impl Iterator<Item = (&'a str, bool)>, node: &str) -> Option<bool> {
+    entries
+        .filter_map(|(pattern, value)| {
+            match_specificity(pattern, node).map(|specificity| (specificity, !value))
+        })
+        .max()
+        .map(|(_, denied)| !denied)
+}
 
 impl Permissions {
-    pub fn new(config: &Config) -> Permissions {
-        Permissions {}
+    /// Loads the permissions, using the defaults if the file is missing or invalid.
+    fn load(path: &str) -> Permissions {
+        match fs::read_to_string(path) {
+            Ok(permissions_string) => toml::from_str(&permissions_string).unwrap_or_else(|err| {
+                error!("Unable to parse {}, using the defaults: {}", path, err);
+                Permissions::default()
+            }),
+            Err(_) => Permissions::default(),
+        }
+    }
+
+    /// Writes the permissions to `path` if there is no file there yet, so that the
+    /// default groups can be found and changed.
+    pub fn save_if_missing(&self, path: &str) {
+        if Path::new(path).exists() {
+            return;
+        }
+        let permissions_string = toml::to_string(self).unwrap();
+        if let Err(err) = fs::write(path, &permissions_string) {
+            warn!("Unable to write {}: {}", path, err);
+        }
+    }
+
+    fn group_value(&self, name: &str, node: &str, depth: u32) -> Option<bool> {
+        // Stop following inheritance if there is a cycle
+        if depth > 16 {
+            return None;
+        }
+        let group = self.groups.get(name)?;
+        let nodes = group
+            .nodes
+            .iter()
+            .map(|pattern| match pattern.strip_prefix('-') {
+                Some(pattern) => (pattern, false),
+                None => (pattern.as_str(), true),
+            });
+        lookup(nodes, node).or_else(|| {
+            group
+                .inherits
+                .iter()
+                .find_map(|parent| self.group_value(parent, node, depth + 1))
+        })
+    }
+
+    pub fn has_permission(&self, uuid: u128, username: &str, node: &str) -> bool {
+        let overrides = database::get_permission_overrides(uuid);
+        let player_nodes = overrides
+            .iter()
+            .map(|(node, value)| (node.as_str(), *value));
+        if let Some(value) = lookup(player_nodes, node) {
+            return value;
+        }
+
+        let hyphenated_uuid = Player::uuid_with_hyphens(uuid);
+        let granted_groups = overrides
+            .iter()
+            .filter(|(_, value)| *value)
+            .filter_map(|(node, _)| node.strip_prefix("group."));
+        let member_groups = self
+            .groups
+            .iter()
+            .filter(|(_, group)| {
+                group.members.iter().any(|member| {
+                    member.eq_ignore_ascii_case(username)
+                        || member.eq_ignore_ascii_case(&hyphenated_uuid)
+                })
+            })
+            .map(|(name, _)| name.as_str());
+        granted_groups
+            .chain(member_groups)
+            .chain(std::iter::once(self.default_group.as_str()))
+            .find_map(|group| self.group_value(group, node, 0))
+            .unwrap_or(false)
     }
 }
+
+#[test]
+fn permission_lookup_test() {
+    let wildcard = [("*", true)];
+    assert_eq!(lookup(wildcard.iter().copied(), "server.stop"), Some(true));
+    let worldedit = [("worldedit.*", true), ("worldedit.set", false)];
+    assert_eq!(
+        lookup(worldedit.iter().copied(), "worldedit.set"),
+        Some(false)
+    );
+    assert_eq!(
+        lookup(worldedit.iter().copied(), "worldedit.copy"),
+        Some(true)
+    );
+    assert_eq!(lookup(worldedit.iter().copied(), "worldedit"), None);
+
+    let permissions = Permissions::default();
+    assert_eq!(
+        permissions.group_value("default", "worldedit.set", 0),
+        Some(true)
+    );
+    assert_eq!(permissions.group_value("default", "server.stop", 0), None);
+    assert_eq!(
+        permissions.group_value("admin", "server.stop", 0),
+        Some(true)
+    );
+    assert_eq!(
+        permissions.group_value("default", "plot.keepticking", 0),
+        Some(false)
+    );
+    assert_eq!(
+        permissions.group_value("admin", "plot.keepticking", 0),
+        Some(true)
+    );
+}
+
+#[test]
+fn invalid_permissions_test() {
+    let dir = std::env::temp_dir().join(format!("mchprs_permissions_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("Permissions.toml");
+    let path = path.to_str().unwrap();
+
+    // Broken files fall back to the defaults without being overwritten
+    fs::write(path, "default_group = [\n").unwrap();
+    let permissions = Permissions::load(path);
+    assert_eq!(permissions.default_group, "default");
+    assert_eq!(fs::read_to_string(path).unwrap(), "default_group = [\n");
+
+    fs::remove_file(path).unwrap();
+    let permissions = Permissions::load(path);
+    assert!(!Path::new(path).exists());
+    permissions.save_if_missing(path);
+    assert!(Permissions::load(path).groups.contains_key("admin"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn permission_overrides_test() {
+    let permissions = Permissions::default();
+    let uuid = 0x1234_5678_9abc_def0;
+    database::cache_permission_overrides(
+        uuid,
+        vec![
+            ("worldedit.set".to_owned(), false),
+            ("group.admin".to_owned(), true),
+        ],
+    );
+    assert!(!permissions.has_permission(uuid, "Tester", "worldedit.set"));
+    assert!(permissions.has_permission(uuid, "Tester", "worldedit.copy"));
+    assert!(permissions.has_permission(uuid, "Tester", "server.stop"));
+}
@@ -1,9 +1,11 @@
-use crate::blocks::{BlockDirection, BlockPos};
+use crate::blocks::{BlockDirection, BlockFacing, BlockPos};
 use crate::items::{Item, ItemStack};
 use crate::network::packets::clientbound::*;
+use crate::network::session::ProfileProperty;
 use crate::network::NetworkClient;
-use crate::plot::worldedit::WorldEditClipboard;
-use crate::plot::Plot;
+use crate::permissions::PERMISSIONS;
+use crate::plot::worldedit::{WorldEditClipboard, WorldEditUndo};
+use crate::plot::{self, ContainerWindow};
 use byteorder::{BigEndian, ReadBytesExt};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -50,6 +52,8 @@ bitflags! {
 pub struct Player {
     pub uuid: u128,
     pub username: String,
+    /// The profile properties from the session server, such as the skin texture
+    pub properties: Vec<ProfileProperty>,
     pub skin_parts: SkinParts,
     pub inventory: Vec<Option<ItemStack>>,
     pub selected_slot: u32,
@@ -75,6 +79,13 @@ pub struct Player {
     pub first_position: Option<BlockPos>,
     pub second_position: Option<BlockPos>,
     pub worldedit_clipboard: Option<WorldEditClipboard>,
+    pub worldedit_undo: Vec<WorldEditUndo>,
+    pub worldedit_redo: Vec<WorldEditUndo>,
+    // Windows
+    /// The container the player is looking into
+    pub open_window: Option<ContainerWindow>,
+    /// The id of the last window that was opened. Ids go up to 100 and start over.
+    pub last_window_id: u8,
 }
 
 impl fmt::Debug for Player {
@@ -126,6 +137,7 @@ impl Player {
             Player {
                 uuid,
                 username,
+                properties: Vec::new(),
                 skin_parts: Default::default(),
                 inventory,
                 selected_slot: player_data.selected_item_slot as u32,
@@ -149,6 +161,10 @@ impl Player {
                 first_position: None,
                 second_position: None,
                 worldedit_clipboard: None,
+                worldedit_undo: Vec::new(),
+                worldedit_redo: Vec::new(),
+                open_window: None,
+                last_window_id: 0,
             }
         } else {
             Player::create_player(uuid, username, client)
@@ -158,16 +174,18 @@ impl Player {
     fn create_player(uuid: u128, username: String, client: NetworkClient) -> Player {
         let mut inventory: Vec<Option<ItemStack>> = vec![];
         inventory.resize_with(46, || None);
+        let (x, y, z) = plot::spawn_pos();
         Player {
             uuid,
             username,
+            properties: Vec::new(),
             skin_parts: Default::default(),
             selected_slot: 0,
-            x: 128f64,
-            y: 128f64,
-            z: 128f64,
-            last_chunk_x: 8,
-            last_chunk_z: 8,
+            x,
+            y,
+            z,
+            last_chunk_x: x as i32 >> 4,
+            last_chunk_z: z as i32 >> 4,
             yaw: 0f32,
             pitch: 0f32,
             entity_id: client.id,
@@ -184,6 +202,10 @@ impl Player {
             first_position: None,
             second_position: None,
             worldedit_clipboard: None,
+            worldedit_undo: Vec::new(),
+            worldedit_redo: Vec::new(),
+            open_window: None,
+            last_window_id: 0,
         }
     }
 
@@ -264,6 +286,17 @@ impl Player {
         }
     }
 
+    /// Returns the direction the player is looking in, including up and down
+    pub fn get_facing(&self) -> BlockFacing {
+        if self.pitch > 45.0 {
+            BlockFacing::Down
+        } else if self.pitch < -45.0 {
+            BlockFacing::Up
+        } else {
+            self.get_direction().block_facing()
+        }
+    }
+
     pub fn teleport(&mut self, x: f64, y: f64, z: f64) {
         let player_position_and_look = C36PlayerPositionAndLook {
             x,
@@ -303,6 +336,10 @@ impl Player {
         self.send_raw_chat(json!({ "text": message }).to_string());
     }
 
+    pub fn has_permission(&self, node: &str) -> bool {
+        PERMISSIONS.has_permission(self.uuid, &self.username, node)
+    }
+
     pub fn send_system_message(&mut self, message: &str) {
         self.send_raw_system_message(
             json!({
@@ -1,4 +1,8 @@
-use super::{database, Plot};
+use super::database::{self, PlotRole};
+use super::debugger::{Breakpoint, BreakpointKind};
+use super::profiler::Profiler;
+use super::{plot_at, Plot};
+use crate::blocks::BlockPos;
 use crate::network::packets::clientbound::{
     C12DeclareCommands, C12DeclareCommandsNode as Node, C12DeclareCommandsNodeParser as Parser,
     C32PlayerAbilities, ClientBoundPacket,
@@ -7,12 +11,239 @@ use crate::network::packets::PacketEncoder;
 use crate::server::Message;
 use log::info;
 
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many of the scheduled ticks that ran are listed by `/rstep`
+const MAX_STEP_LINES: usize = 20;
+
+/// Parses the `x y z` arguments of a command.
+fn parse_block_pos(args: &[&str]) -> Option<BlockPos> {
+    match args {
+        [x, y, z] => Some(BlockPos::new(
+            x.parse().ok()?,
+            y.parse().ok()?,
+            z.parse().ok()?,
+        )),
+        _ => None,
+    }
+}
+
+/// Returns the permission node needed to run a command, or `None` if anyone can run it.
+fn permission_node(command: &str, args: &[&str]) -> Option<&'static str> {
+    Some(match command {
+        "//1" | "//pos1" | "//2" | "//pos2" => "worldedit.selection",
+        "//set" => "worldedit.set",
+        "//replace" => "worldedit.replace",
+        "//find" => "worldedit.find",
+        "//copy" | "//c" => "worldedit.copy",
+        "//paste" | "//p" => "worldedit.paste",
+        "//count" => "worldedit.count",
+        "//load" => "worldedit.load",
+        "//save" => "worldedit.save",
+        "//undo" | "//redo" => "worldedit.history",
+        "/rtps" => "redstone.rtps",
+        "/radv" | "/radvance" => "redstone.radvance",
+        "/rprofile" => "redstone.rprofile",
+        "/rpause" => "redstone.rpause",
+        "/rstep" => "redstone.rstep",
+        "/rbreak" => "redstone.rbreak",
+        "/rpending" => "redstone.rpending",
+        "//probe" | "/probe" => "redstone.probe",
+        "/redpiler" | "/rp" => "redstone.redpiler",
+        "/teleport" | "/tp" => "teleport",
+        "/stop" => "server.stop",
+        "/speed" => "speed",
+        "/permissions" | "/perm" => "permissions.manage",
+        "/plot" | "/p" => match args.first() {
+            Some(&"claim") | Some(&"c") => "plot.claim",
+            Some(&"info") | Some(&"i") => "plot.info",
+            Some(&"add") => "plot.add",
+            Some(&"trust") => "plot.trust",
+            Some(&"remove") => "plot.remove",
+            Some(&"deny") => "plot.deny",
+            Some(&"kick") => "plot.kick",
+            Some(&"auto") | Some(&"a") => "plot.auto",
+            Some(&"home") | Some(&"h") => "plot.home",
+            Some(&"visit") | Some(&"v") => "plot.visit",
+            Some(&"clear") => "plot.clear",
+            Some(&"unclaim") => "plot.unclaim",
+            Some(&"delete") => "plot.delete",
+            Some(&"keepticking") => "plot.keepticking",
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
 
 impl Plot {
-    fn handle_plot_command(&mut self, player: usize, command: &str, args: Vec<&str>) {
-        let plot_x = self.players[player].x as i32 >> 8;
-        let plot_z = self.players[player].z as i32 >> 8;
+    fn handle_permissions_command(&mut self, player: usize, args: Vec<&str>) {
+        if args.len() != 3 {
+            self.players[player]
+                .send_error_message("Usage: /permissions <grant|revoke|unset> <player> <node>");
+            return;
+        }
+        let (action, username, node) = (args[0], args[1], args[2]);
+        let uuid = if let Some(uuid) = database::get_player_uuid(username) {
+            uuid
+        } else {
+            self.players[player].send_error_message("That player has never joined the server!");
+            return;
+        };
+        let message = match action {
+            "grant" => {
+                database::set_permission_override(uuid, node, true);
+                format!("Granted {} to {}", node, username)
+            }
+            "revoke" => {
+                database::set_permission_override(uuid, node, false);
+                format!("Revoked {} from {}", node, username)
+            }
+            "unset" => {
+                if database::remove_permission_override(uuid, node) {
+                    format!("Unset {} for {}", node, username)
+                } else {
+                    format!("{} has no override for {}", username, node)
+                }
+            }
+            _ => {
+                self.players[player].send_error_message("Wrong argument for /permissions");
+                return;
+            }
+        };
+        self.players[player].send_system_message(&message);
+    }
+
+    /// Handles `/plot add`, `trust`, `remove` and `deny`, which only the owner can use.
+    /// Returns true if a denied player was sent away from the plot.
+    fn handle_plot_member_command(
+        &mut self,
+        player: usize,
+        command: &str,
+        args: Vec<&str>,
+    ) -> bool {
+        if args.len() != 1 {
+            self.players[player].send_error_message(&format!("Usage: /plot {} <player>", command));
+            return false;
+        }
+        let owner = database::get_plot_owner(self.x, self.z);
+        if owner != Some(self.players[player].uuid)
+            && !self.players[player].has_permission("protection.bypass")
+        {
+            self.players[player].send_error_message("You are not the owner of this plot!");
+            return false;
+        }
+        let username = args[0];
+        let uuid = match database::get_player_uuid(username) {
+            Some(uuid) => uuid,
+            None => {
+                self.players[player].send_error_message("That player has never joined the server!");
+                return false;
+            }
+        };
+        if Some(uuid) == owner {
+            self.players[player].send_error_message("That player owns this plot!");
+            return false;
+        }
+        let (role, message) = match command {
+            "add" => (PlotRole::Member, "can now build while an owner is here"),
+            "trust" => (PlotRole::Trusted, "is now trusted on this plot"),
+            "deny" => (PlotRole::Denied, "is now denied from this plot"),
+            _ => {
+                let message = if database::remove_plot_role(self.x, self.z, uuid) {
+                    format!("Removed {} from this plot", username)
+                } else {
+                    format!("{} isn't added, trusted or denied on this plot", username)
+                };
+                self.players[player].send_system_message(&message);
+                return false;
+            }
+        };
+        if role == PlotRole::Denied && (self.x, self.z) == (0, 0) {
+            self.players[player].send_error_message("Players can't be denied from spawn!");
+            return false;
+        }
+        database::set_plot_role(self.x, self.z, uuid, role);
+        self.players[player].send_system_message(&format!("{} {}", username, message));
+
+        // Denied players who are already here get sent away right away
+        if role == PlotRole::Denied {
+            let denied_idx = self.players.iter().position(|p| p.uuid == uuid);
+            if let Some(denied_idx) = denied_idx {
+                if !self.players[denied_idx].has_permission("protection.bypass") {
+                    self.send_to_spawn(denied_idx, "You have been denied from this plot!");
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Handles `/plot clear`, `/plot unclaim` and `/plot delete`. Returns true if players
+    /// were removed from the plot.
+    fn handle_plot_wipe_command(&mut self, player: usize, command: &str) -> bool {
+        if database::get_plot_owner(self.x, self.z) != Some(self.players[player].uuid)
+            && !self.players[player].has_permission("protection.bypass")
+        {
+            self.players[player].send_error_message("You are not the owner of this plot!");
+            return false;
+        }
+        match command {
+            "clear" => {
+                self.clear();
+                self.players[player].send_system_message("Cleared this plot");
+            }
+            "unclaim" => {
+                if database::unclaim_plot(self.x, self.z) {
+                    self.always_running = false;
+                    self.players[player]
+                        .send_system_message(&format!("Unclaimed plot {},{}", self.x, self.z));
+                } else {
+                    self.players[player].send_system_message("Plot is not owned by anyone.");
+                }
+            }
+            _ => {
+                if (self.x, self.z) == (0, 0) {
+                    self.players[player].send_error_message("The spawn plot can't be deleted!");
+                    return false;
+                }
+                database::unclaim_plot(self.x, self.z);
+                self.delete();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Teleports the player to the `n`th plot that `owner` claimed. Returns true if the
+    /// player left this plot.
+    fn teleport_to_owned_plot(&mut self, player: usize, owner: u128, n: Option<&str>) -> bool {
+        let n = match n.map(str::parse::<usize>) {
+            None => 1,
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => {
+                self.players[player].send_error_message("Unable to parse plot number!");
+                return false;
+            }
+        };
+        let plots = database::get_owned_plots(owner);
+        match plots.get(n - 1) {
+            Some(&(plot_x, plot_z)) => self.teleport_to_plot(player, plot_x, plot_z),
+            None => {
+                let message = if plots.is_empty() {
+                    "There are no plots to go to! Use /plot auto to claim one.".to_owned()
+                } else {
+                    format!("There are only {} plots to go to!", plots.len())
+                };
+                self.players[player].send_error_message(&message);
+                false
+            }
+        }
+    }
+
+    // Returns true if players were removed from the plot
+    fn handle_plot_command(&mut self, player: usize, command: &str, args: Vec<&str>) -> bool {
+        let (plot_x, plot_z) =
+            plot_at(self.players[player].x as i32, self.players[player].z as i32);
         match command {
             "claim" | "c" => {
                 if database::get_plot_owner(plot_x, plot_z).is_some() {
@@ -26,14 +257,133 @@ impl Plot {
             }
             "info" | "i" => {
                 if let Some(owner) = database::get_plot_owner(plot_x, plot_z) {
+                    let name = |uuid| {
+                        database::get_player_name(uuid).unwrap_or_else(|| format!("{:032x}", uuid))
+                    };
                     self.players[player]
-                        .send_system_message(&format!("Plot owner is: {:032x}", owner));
+                        .send_system_message(&format!("Plot owner is: {}", name(owner)));
+                    let members = database::get_plot_members(plot_x, plot_z);
+                    for (role, title) in &[
+                        (PlotRole::Trusted, "Trusted"),
+                        (PlotRole::Member, "Members"),
+                        (PlotRole::Denied, "Denied"),
+                    ] {
+                        let names: Vec<String> = members
+                            .iter()
+                            .filter(|(_, r)| r == role)
+                            .map(|(uuid, _)| name(*uuid))
+                            .collect();
+                        if !names.is_empty() {
+                            self.players[player].send_system_message(&format!(
+                                "{}: {}",
+                                title,
+                                names.join(", ")
+                            ));
+                        }
+                    }
                 } else {
                     self.players[player].send_system_message("Plot is not owned by anyone.");
                 }
             }
+            "add" | "trust" | "remove" | "deny" => {
+                return self.handle_plot_member_command(player, command, args);
+            }
+            "clear" | "unclaim" | "delete" => {
+                return self.handle_plot_wipe_command(player, command);
+            }
+            "keepticking" => {
+                let keep_ticking = match args.first() {
+                    Some(&"on") => true,
+                    Some(&"off") => false,
+                    _ => {
+                        self.players[player]
+                            .send_error_message("Usage: /plot keepticking <on|off>");
+                        return false;
+                    }
+                };
+                if database::get_plot_owner(self.x, self.z) != Some(self.players[player].uuid)
+                    && !self.players[player].has_permission("protection.bypass")
+                {
+                    self.players[player].send_error_message("You are not the owner of this plot!");
+                    return false;
+                }
+                database::set_keep_ticking(self.x, self.z, keep_ticking);
+                self.always_running = keep_ticking;
+                let message = if keep_ticking {
+                    "This plot will keep ticking and stay loaded while nobody is in it"
+                } else {
+                    "This plot will stop ticking when everyone leaves"
+                };
+                self.players[player].send_system_message(message);
+            }
+            "auto" | "a" => {
+                let uuid = format!("{}", self.players[player].uuid);
+                let (plot_x, plot_z) = database::auto_claim_plot(plot_x, plot_z, &uuid);
+                self.players[player]
+                    .send_system_message(&format!("Claimed plot {},{}", plot_x, plot_z));
+                return self.teleport_to_plot(player, plot_x, plot_z);
+            }
+            "home" | "h" => {
+                let uuid = self.players[player].uuid;
+                return self.teleport_to_owned_plot(player, uuid, args.first().copied());
+            }
+            "visit" | "v" => {
+                if args.is_empty() || args.len() > 2 {
+                    self.players[player].send_error_message("Usage: /plot visit <player> [n]");
+                    return false;
+                }
+                if let Some(uuid) = database::get_player_uuid(args[0]) {
+                    return self.teleport_to_owned_plot(player, uuid, args.get(1).copied());
+                } else {
+                    self.players[player]
+                        .send_error_message("That player has never joined the server!");
+                }
+            }
+            "kick" => {
+                if args.len() != 1 {
+                    self.players[player].send_error_message("Usage: /plot kick <player>");
+                    return false;
+                }
+                if database::get_plot_owner(plot_x, plot_z) != Some(self.players[player].uuid)
+                    && !self.players[player].has_permission("protection.bypass")
+                {
+                    self.players[player].send_error_message("You are not the owner of this plot!");
+                    return false;
+                }
+                // Kicked players are sent to spawn, so they would end up right back here
+                if (plot_x, plot_z) == (0, 0) {
+                    self.players[player].send_error_message("Players can't be kicked from spawn!");
+                    return false;
+                }
+                let kicked_idx = self
+                    .players
+                    .iter()
+                    .position(|p| p.username.eq_ignore_ascii_case(args[0]));
+                if let Some(kicked_idx) = kicked_idx {
+                    let username = self.players[kicked_idx].username.clone();
+                    if self.players[kicked_idx].has_permission("protection.bypass") {
+                        self.players[player]
+                            .send_error_message(&format!("{} can't be kicked!", username));
+                        return false;
+                    }
+                    self.send_to_spawn(kicked_idx, "You have been kicked from this plot!");
+                    if kicked_idx != player {
+                        let player = if kicked_idx < player {
+                            player - 1
+                        } else {
+                            player
+                        };
+                        self.players[player]
+                            .send_system_message(&format!("Kicked {} from this plot", username));
+                    }
+                    return true;
+                } else {
+                    self.players[player].send_error_message("That player isn't in this plot!");
+                }
+            }
             _ => self.players[player].send_error_message("Wrong argument for /plot"),
         }
+        false
     }
 
     // Returns true if packets should stop being handled
@@ -49,6 +399,43 @@ impl Plot {
             command,
             args.join(" ")
         );
+        if let Some(node) = permission_node(command, &args) {
+            if !self.players[player].has_permission(node) {
+                self.players[player]
+                    .send_error_message("You do not have permission to use this command!");
+                return false;
+            }
+        }
+        // Besides changing blocks, visitors shouldn't be able to change how another
+        // player's plot runs
+        if matches!(
+            command,
+            "//set"
+                | "//replace"
+                | "//paste"
+                | "//p"
+                | "//undo"
+                | "//redo"
+                | "/rtps"
+                | "/rpause"
+                | "/rstep"
+                | "/radv"
+                | "/radvance"
+                | "/rbreak"
+                | "/redpiler"
+                | "/rp"
+                | "/rprofile"
+        ) && !self.check_can_build(player)
+        {
+            return false;
+        }
+        // The compiled graph would no longer match the world after any of these
+        if matches!(
+            command,
+            "//set" | "//replace" | "//paste" | "//p" | "//load" | "//undo" | "//redo"
+        ) {
+            self.reset_redpiler();
+        }
         match command {
             "//1" | "//pos1" => {
                 let player = &mut self.players[player];
@@ -159,6 +546,28 @@ impl Plot {
                 }
                 self.worldedit_load(player, &args[0])
             }
+            "//save" => {
+                if args.is_empty() {
+                    self.players[player].send_error_message("Wrong number of arguments!");
+                    return false;
+                }
+                self.worldedit_save(player, args[0])
+            }
+            "//undo" | "//redo" => {
+                let count = if args.is_empty() {
+                    1
+                } else if let Ok(count) = args[0].parse::<usize>() {
+                    count
+                } else {
+                    self.players[player].send_error_message("Unable to parse count!");
+                    return false;
+                };
+                if command == "//undo" {
+                    self.worldedit_undo(player, count);
+                } else {
+                    self.worldedit_redo(player, count);
+                }
+            }
             "/rtps" => {
                 if args.is_empty() {
                     self.players[player]
@@ -185,6 +594,297 @@ impl Plot {
                 self.tps = tps;
                 self.players[player].send_system_message("The rtps was successfully set.");
             }
+            "//probe" => {
+                if args.len() > 1 {
+                    self.players[player].send_error_message("Usage: //probe [name]");
+                    return false;
+                }
+                if let Some(pos) = self.players[player].first_position {
+                    self.toggle_probe(player, pos, args.first().copied());
+                } else {
+                    self.players[player]
+                        .send_error_message("Set your first position with //pos1 first!");
+                }
+            }
+            "/probe" => match args.first() {
+                Some(&"list") => {
+                    if self.probes.is_empty() {
+                        self.players[player]
+                            .send_system_message("There are no probes in this plot.");
+                        return false;
+                    }
+                    let lines: Vec<String> = self
+                        .probes
+                        .iter()
+                        .map(|probe| {
+                            format!(
+                                "{}: ({}, {}, {})",
+                                probe.name, probe.pos.x, probe.pos.y, probe.pos.z
+                            )
+                        })
+                        .collect();
+                    for line in lines {
+                        self.players[player].send_system_message(&line);
+                    }
+                }
+                Some(&"clear") => {
+                    if !self.check_can_build(player) {
+                        return false;
+                    }
+                    self.probes.clear();
+                    self.players[player].send_system_message("Removed all probes from this plot");
+                }
+                Some(&"dump") => {
+                    if self.probes.is_empty() {
+                        self.players[player]
+                            .send_error_message("There are no probes in this plot!");
+                        return false;
+                    }
+                    match args.get(1).copied() {
+                        Some("vcd") => {
+                            let name = format!("p{},{}", self.x, self.z);
+                            match self.probes.save_vcd(&name) {
+                                Ok(path) => self.players[player].send_system_message(&format!(
+                                    "The probes were saved to {}",
+                                    path
+                                )),
+                                Err(err) => self.players[player].send_error_message(&format!(
+                                    "Unable to save the probes: {}",
+                                    err
+                                )),
+                            }
+                        }
+                        ticks => {
+                            let ticks = match ticks.map(str::parse::<u64>) {
+                                None => 20,
+                                Some(Ok(ticks)) if (1..=100).contains(&ticks) => ticks,
+                                Some(_) => {
+                                    self.players[player].send_error_message(
+                                        "The number of ticks has to be between 1 and 100!",
+                                    );
+                                    return false;
+                                }
+                            };
+                            for line in self.probes.waveform(ticks) {
+                                self.players[player].send_system_message(&line);
+                            }
+                        }
+                    }
+                }
+                _ => self.players[player]
+                    .send_error_message("Usage: /probe <list|clear|dump [ticks|vcd]>"),
+            },
+            "/rpause" => {
+                self.paused = !self.paused;
+                if self.paused {
+                    self.players[player]
+                        .send_system_message("Paused the plot. Use /rstep to step through ticks.");
+                } else {
+                    // Don't try to catch up on the ticks that were missed while paused
+                    self.lag_time = Duration::from_millis(0);
+                    self.last_update_time = SystemTime::now();
+                    self.players[player].send_system_message("Resumed the plot");
+                }
+            }
+            "/rstep" => {
+                let ticks = match args.first().map(|arg| arg.parse::<u32>()) {
+                    None => 1,
+                    Some(Ok(ticks)) if (1..=1000).contains(&ticks) => ticks,
+                    Some(_) => {
+                        self.players[player].send_error_message(
+                            "The number of ticks has to be between 1 and 1000!",
+                        );
+                        return false;
+                    }
+                };
+                // Compiled redstone doesn't go through the scheduled ticks of the plot
+                self.reset_redpiler();
+                self.fired_ticks = Some(Vec::new());
+                let mut lines = Vec::new();
+                let mut stepped = 0;
+                // Unpause so breakpoints can pause the plot again
+                self.paused = false;
+                while stepped < ticks && !self.paused {
+                    self.tick();
+                    stepped += 1;
+                    for entry in self.fired_ticks.replace(Vec::new()).unwrap() {
+                        let pos = entry.pos;
+                        lines.push(format!(
+                            "Tick {}: {} at ({}, {}, {}) with {:?} priority",
+                            stepped,
+                            self.get_block(pos).get_name().unwrap_or("unknown"),
+                            pos.x,
+                            pos.y,
+                            pos.z,
+                            entry.tick_priority
+                        ));
+                    }
+                }
+                self.fired_ticks = None;
+                self.paused = true;
+                self.players[player].send_system_message(&format!(
+                    "Stepped {} ticks, {} scheduled ticks ran",
+                    stepped,
+                    lines.len()
+                ));
+                let hidden = lines.len().saturating_sub(MAX_STEP_LINES);
+                for line in lines.into_iter().take(MAX_STEP_LINES) {
+                    self.players[player].send_system_message(&line);
+                }
+                if hidden > 0 {
+                    self.players[player].send_system_message(&format!("... and {} more", hidden));
+                }
+            }
+            "/rbreak" => match args.first() {
+                Some(&"list") => {
+                    if self.breakpoints.is_empty() {
+                        self.players[player]
+                            .send_system_message("There are no breakpoints in this plot.");
+                        return false;
+                    }
+                    let lines: Vec<String> = self
+                        .breakpoints
+                        .iter()
+                        .map(|breakpoint| {
+                            let pos = breakpoint.pos;
+                            format!(
+                                "({}, {}, {}): {}",
+                                pos.x,
+                                pos.y,
+                                pos.z,
+                                breakpoint.kind.as_str()
+                            )
+                        })
+                        .collect();
+                    for line in lines {
+                        self.players[player].send_system_message(&line);
+                    }
+                }
+                Some(&"clear") => {
+                    self.breakpoints.clear();
+                    self.players[player]
+                        .send_system_message("Removed all breakpoints from this plot");
+                }
+                _ => {
+                    let (pos, kind) = match args.len() {
+                        3 => (parse_block_pos(&args), Some(BreakpointKind::State)),
+                        4 => (
+                            parse_block_pos(&args[..3]),
+                            BreakpointKind::from_str(args[3]),
+                        ),
+                        _ => (None, None),
+                    };
+                    let (pos, kind) = match (pos, kind) {
+                        (Some(pos), Some(kind)) if self.pos_in_plot(pos) => (pos, kind),
+                        _ => {
+                            self.players[player].send_error_message(
+                                "Usage: /rbreak <x y z> [power|state] or /rbreak <list|clear>",
+                            );
+                            return false;
+                        }
+                    };
+                    let existing = self.breakpoints.iter().position(|b| b.pos == pos);
+                    if let Some(idx) = existing {
+                        self.breakpoints.remove(idx);
+                        self.players[player].send_system_message(&format!(
+                            "Removed the breakpoint at ({}, {}, {})",
+                            pos.x, pos.y, pos.z
+                        ));
+                    } else {
+                        let breakpoint = Breakpoint::new(self, pos, kind);
+                        self.breakpoints.push(breakpoint);
+                        self.players[player].send_system_message(&format!(
+                            "Added a {} breakpoint at ({}, {}, {})",
+                            kind.as_str(),
+                            pos.x,
+                            pos.y,
+                            pos.z
+                        ));
+                    }
+                }
+            },
+            "/rpending" => {
+                let pos = if args.is_empty() {
+                    self.players[player].first_position
+                } else {
+                    parse_block_pos(&args)
+                };
+                let pos = if let Some(pos) = pos {
+                    pos
+                } else {
+                    self.players[player].send_error_message(
+                        "Usage: /rpending [x y z], which defaults to your first position",
+                    );
+                    return false;
+                };
+                let ticks = self.pending_ticks_at(pos);
+                if ticks.is_empty() {
+                    self.players[player].send_system_message(&format!(
+                        "There are no ticks scheduled at ({}, {}, {})",
+                        pos.x, pos.y, pos.z
+                    ));
+                    return false;
+                }
+                self.players[player].send_system_message(&format!(
+                    "Ticks scheduled at ({}, {}, {}):",
+                    pos.x, pos.y, pos.z
+                ));
+                for (delay, priority) in ticks {
+                    self.players[player].send_system_message(&format!(
+                        "In {} ticks with {:?} priority",
+                        delay, priority
+                    ));
+                }
+            }
+            "/rprofile" => match args.first() {
+                Some(&"start") => {
+                    if self.profiler.is_some() {
+                        self.players[player]
+                            .send_error_message("This plot is already being profiled!");
+                        return false;
+                    }
+                    // Only count the packets sent from now on
+                    for player in &mut self.players {
+                        player.client.take_packets_sent();
+                    }
+                    self.profiler = Some(Profiler::default());
+                    self.players[player].send_system_message(
+                        "Started profiling this plot. Use /rprofile stop to see the results.",
+                    );
+                }
+                Some(&"stop") => {
+                    let format = args.get(1).copied();
+                    if format.is_some_and(|format| format != "csv" && format != "json") {
+                        self.players[player]
+                            .send_error_message("Profiles can only be saved as csv or json!");
+                        return false;
+                    }
+                    let profiler = if let Some(profiler) = self.profiler.take() {
+                        profiler
+                    } else {
+                        self.players[player].send_error_message(
+                            "This plot isn't being profiled! Use /rprofile start first.",
+                        );
+                        return false;
+                    };
+                    for line in profiler.summary() {
+                        self.players[player].send_system_message(&line);
+                    }
+                    if let Some(format) = format {
+                        let name = format!("p{},{}", self.x, self.z);
+                        match profiler.save(&name, format) {
+                            Ok(path) => self.players[player]
+                                .send_system_message(&format!("The profile was saved to {}", path)),
+                            Err(err) => self.players[player].send_error_message(&format!(
+                                "Unable to save the profile: {}",
+                                err
+                            )),
+                        }
+                    }
+                }
+                _ => self.players[player]
+                    .send_error_message("Usage: /rprofile <start|stop> [csv|json]"),
+            },
             "/radv" | "/radvance" => {
                 if args.is_empty() {
                     self.players[player]
@@ -207,6 +907,39 @@ impl Plot {
                     start_time.elapsed()
                 ));
             }
+            "/redpiler" | "/rp" => {
+                if args.is_empty() {
+                    self.players[player].send_error_message("/redpiler <compile|reset>");
+                    return false;
+                }
+                match args[0] {
+                    "compile" | "c" => {
+                        let start_time = Instant::now();
+                        self.compile_redpiler();
+                        let redpiler = &self.redpiler;
+                        let message = format!(
+                            "Compiled {} nodes with {} links ({:?})",
+                            redpiler.node_count(),
+                            redpiler.link_count(),
+                            start_time.elapsed()
+                        );
+                        let unsupported_count = redpiler.unsupported_count();
+                        self.players[player].send_system_message(&message);
+                        if unsupported_count > 0 {
+                            self.players[player].send_error_message(&format!(
+                                "{} components can't be compiled yet and will not be simulated \
+                                 correctly until the redpiler is reset",
+                                unsupported_count
+                            ));
+                        }
+                    }
+                    "reset" | "r" => {
+                        self.reset_redpiler();
+                        self.players[player].send_system_message("The redpiler has been reset");
+                    }
+                    _ => self.players[player].send_error_message("/redpiler <compile|reset>"),
+                }
+            }
             "/teleport" | "/tp" => {
                 if args.len() == 3 {
                     let x;
@@ -250,8 +983,9 @@ impl Plot {
                     return false;
                 }
                 let command = args.remove(0);
-                self.handle_plot_command(player, command, args);
+                return self.handle_plot_command(player, command, args);
             }
+            "/permissions" | "/perm" => self.handle_permissions_command(player, args),
             "/speed" => {
                 if args.len() != 1 {
                     self.players[player].send_error_message("/speed <0-10>");
@@ -301,7 +1035,7 @@ lazy_static! {
             // 0: Root Node
             Node {
                 flags: CommandFlags::ROOT.bits() as i8,
-                children: vec![1, 4, 5, 6, 11, 12, 14, 16, 18, 19, 20, 21, 22, 23, 24, 26, 29, 31, 32],
+                children: vec![1, 4, 5, 6, 11, 12, 14, 16, 18, 19, 20, 21, 22, 23, 24, 26, 29, 31, 32, 34, 39, 40, 42, 44, 46, 52, 70, 75, 77, 83, 84, 86, 92],
                 redirect_node: None,
                 name: None,
                 parser: None,
@@ -349,7 +1083,7 @@ lazy_static! {
             // 6: /plot
             Node {
                 flags: (CommandFlags::LITERAL).bits() as i8,
-                children: vec![7, 8, 9, 10],
+                children: vec![7, 8, 9, 10, 53, 54, 55, 56, 57, 59, 60, 62, 64, 65, 66, 67, 68, 69, 94],
                 redirect_node: None,
                 name: Some("plot"),
                 parser: None,
@@ -570,8 +1304,553 @@ lazy_static! {
                 name: Some("speed"),
                 //A Parser::Float would be needed here (command still executes with floats though)
                 parser: Some(Parser::Integer(0, 35000)),
-            }
-        ],
-        root_index: 0
-    }.encode();
+            },
+            // 34: /redpiler
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![35, 36, 37, 38],
+                redirect_node: None,
+                name: Some("redpiler"),
+                parser: None,
+            },
+            // 35: /redpiler compile
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("compile"),
+                parser: None,
+            },
+            // 36: /redpiler c
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::REDIRECT).bits() as i8,
+                children: vec![],
+                redirect_node: Some(35),
+                name: Some("c"),
+                parser: None,
+            },
+            // 37: /redpiler reset
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("reset"),
+                parser: None,
+            },
+            // 38: /redpiler r
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::REDIRECT).bits() as i8,
+                children: vec![],
+                redirect_node: Some(37),
+                name: Some("r"),
+                parser: None,
+            },
+            // 39: /rp
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::REDIRECT).bits() as i8,
+                children: vec![],
+                redirect_node: Some(34),
+                name: Some("rp"),
+                parser: None,
+            },
+            // 40: //save
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![41],
+                redirect_node: None,
+                name: Some("/save"),
+                parser: None,
+            },
+            // 41: //save [name]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("name"),
+                parser: Some(Parser::Word),
+            },
+            // 42: //undo
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![43],
+                redirect_node: None,
+                name: Some("/undo"),
+                parser: None,
+            },
+            // 43: //undo [count]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("count"),
+                parser: Some(Parser::Integer(1, i32::MAX)),
+            },
+            // 44: //redo
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![45],
+                redirect_node: None,
+                name: Some("/redo"),
+                parser: None,
+            },
+            // 45: //redo [count]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("count"),
+                parser: Some(Parser::Integer(1, i32::MAX)),
+            },
+            // 46: /permissions
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![47, 48, 49],
+                redirect_node: None,
+                name: Some("permissions"),
+                parser: None,
+            },
+            // 47: /permissions grant
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![50],
+                redirect_node: None,
+                name: Some("grant"),
+                parser: None,
+            },
+            // 48: /permissions revoke
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![50],
+                redirect_node: None,
+                name: Some("revoke"),
+                parser: None,
+            },
+            // 49: /permissions unset
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![50],
+                redirect_node: None,
+                name: Some("unset"),
+                parser: None,
+            },
+            // 50: /permissions <action> [player]
+            Node {
+                flags: (CommandFlags::ARGUMENT).bits() as i8,
+                children: vec![51],
+                redirect_node: None,
+                name: Some("player"),
+                parser: Some(Parser::Word),
+            },
+            // 51: /permissions <action> [player] [node]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("node"),
+                parser: Some(Parser::GreedyString),
+            },
+            // 52: /perm
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::REDIRECT).bits() as i8,
+                children: vec![],
+                redirect_node: Some(46),
+                name: Some("perm"),
+                parser: None,
+            },
+            // 53: /plot add
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![58],
+                redirect_node: None,
+                name: Some("add"),
+                parser: None,
+            },
+            // 54: /plot trust
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![58],
+                redirect_node: None,
+                name: Some("trust"),
+                parser: None,
+            },
+            // 55: /plot remove
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![58],
+                redirect_node: None,
+                name: Some("remove"),
+                parser: None,
+            },
+            // 56: /plot deny
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![58],
+                redirect_node: None,
+                name: Some("deny"),
+                parser: None,
+            },
+            // 57: /plot kick
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![58],
+                redirect_node: None,
+                name: Some("kick"),
+                parser: None,
+            },
+            // 58: /plot <add|trust|remove|deny|kick> [player]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("player"),
+                parser: Some(Parser::Word),
+            },
+            // 59: /plot auto
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("auto"),
+                parser: None,
+            },
+            // 60: /plot home
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![61],
+                redirect_node: None,
+                name: Some("home"),
+                parser: None,
+            },
+            // 61: /plot <home|visit [player]> [n]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("n"),
+                parser: Some(Parser::Integer(1, i32::MAX)),
+            },
+            // 62: /plot visit
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![63],
+                redirect_node: None,
+                name: Some("visit"),
+                parser: None,
+            },
+            // 63: /plot visit [player]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![61],
+                redirect_node: None,
+                name: Some("player"),
+                parser: Some(Parser::Word),
+            },
+            // 64: /plot a
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::REDIRECT).bits() as i8,
+                children: vec![],
+                redirect_node: Some(59),
+                name: Some("a"),
+                parser: None,
+            },
+            // 65: /plot h
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::REDIRECT).bits() as i8,
+                children: vec![],
+                redirect_node: Some(60),
+                name: Some("h"),
+                parser: None,
+            },
+            // 66: /plot v
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::REDIRECT).bits() as i8,
+                children: vec![],
+                redirect_node: Some(62),
+                name: Some("v"),
+                parser: None,
+            },
+            // 67: /plot clear
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("clear"),
+                parser: None,
+            },
+            // 68: /plot unclaim
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("unclaim"),
+                parser: None,
+            },
+            // 69: /plot delete
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("delete"),
+                parser: None,
+            },
+            // 70: /rprofile
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![71, 72],
+                redirect_node: None,
+                name: Some("rprofile"),
+                parser: None,
+            },
+            // 71: /rprofile start
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("start"),
+                parser: None,
+            },
+            // 72: /rprofile stop
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![73, 74],
+                redirect_node: None,
+                name: Some("stop"),
+                parser: None,
+            },
+            // 73: /rprofile stop csv
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("csv"),
+                parser: None,
+            },
+            // 74: /rprofile stop json
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("json"),
+                parser: None,
+            },
+            // 75: //probe
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![76],
+                redirect_node: None,
+                name: Some("/probe"),
+                parser: None,
+            },
+            // 76: //probe [name]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("name"),
+                parser: Some(Parser::Word),
+            },
+            // 77: /probe
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![78, 79, 80],
+                redirect_node: None,
+                name: Some("probe"),
+                parser: None,
+            },
+            // 78: /probe list
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("list"),
+                parser: None,
+            },
+            // 79: /probe clear
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("clear"),
+                parser: None,
+            },
+            // 80: /probe dump
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![81, 82],
+                redirect_node: None,
+                name: Some("dump"),
+                parser: None,
+            },
+            // 81: /probe dump [ticks]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("ticks"),
+                parser: Some(Parser::Integer(1, 100)),
+            },
+            // 82: /probe dump vcd
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("vcd"),
+                parser: None,
+            },
+            // 83: /rpause
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("rpause"),
+                parser: None,
+            },
+            // 84: /rstep
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![85],
+                redirect_node: None,
+                name: Some("rstep"),
+                parser: None,
+            },
+            // 85: /rstep [ticks]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("ticks"),
+                parser: Some(Parser::Integer(1, 1000)),
+            },
+            // 86: /rbreak
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![87, 88, 89],
+                redirect_node: None,
+                name: Some("rbreak"),
+                parser: None,
+            },
+            // 87: /rbreak list
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("list"),
+                parser: None,
+            },
+            // 88: /rbreak clear
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("clear"),
+                parser: None,
+            },
+            // 89: /rbreak [pos]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![90, 91],
+                redirect_node: None,
+                name: Some("pos"),
+                parser: Some(Parser::BlockPos),
+            },
+            // 90: /rbreak [pos] power
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("power"),
+                parser: None,
+            },
+            // 91: /rbreak [pos] state
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("state"),
+                parser: None,
+            },
+            // 92: /rpending
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![93],
+                redirect_node: None,
+                name: Some("rpending"),
+                parser: None,
+            },
+            // 93: /rpending [pos]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("pos"),
+                parser: Some(Parser::BlockPos),
+            },
+            // 94: /plot keepticking
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: vec![95, 96],
+                redirect_node: None,
+                name: Some("keepticking"),
+                parser: None,
+            },
+            // 95: /plot keepticking on
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("on"),
+                parser: None,
+            },
+            // 96: /plot keepticking off
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("off"),
+                parser: None,
+            }
+        ],
+        root_index: 0
+    }.encode();
+}
+
+#[test]
+fn redstone_commands_need_build_test() {
+    use crate::network::NetworkClient;
+    use crate::player::Player;
+    use database::PlotRoles;
+
+    let (x, z) = (7, -3);
+    let (visitor, owner) = (2, 3);
+    let mut plot = Plot::load_headless(x, z, None).unwrap();
+    let (client, _remote) = NetworkClient::connect_local(0);
+    plot.players
+        .push(Player::load_player(visitor, "Visitor".to_owned(), client));
+    database::cache_permission_overrides(visitor, Vec::new());
+
+    database::cache_plot_roles(
+        x,
+        z,
+        PlotRoles {
+            owner: Some(owner),
+            ..Default::default()
+        },
+    );
+    plot.handle_command(0, "/rtps", vec!["100"]);
+    plot.handle_command(0, "/rpause", vec![]);
+    assert_eq!(plot.tps, 20);
+    assert!(!plot.paused);
+
+    database::cache_plot_roles(
+        x,
+        z,
+        PlotRoles {
+            owner: Some(visitor),
+            ..Default::default()
+        },
+    );
+    plot.handle_command(0, "/rtps", vec!["100"]);
+    plot.handle_command(0, "/rpause", vec![]);
+    assert_eq!(plot.tps, 100);
+    assert!(plot.paused);
 }
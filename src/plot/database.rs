@@ -1,15 +1,100 @@
 use rusqlite::{params, Connection, NO_PARAMS};
-use std::sync::{Mutex, MutexGuard};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What a player other than the owner is allowed to do on a plot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlotRole {
+    /// Can build while an owner is in the plot
+    Member,
+    /// Can always build
+    Trusted,
+    /// Can't enter the plot
+    Denied,
+}
+
+impl PlotRole {
+    fn as_str(self) -> &'static str {
+        match self {
+            PlotRole::Member => "member",
+            PlotRole::Trusted => "trusted",
+            PlotRole::Denied => "denied",
+        }
+    }
+
+    fn from_str(role: &str) -> Option<PlotRole> {
+        match role {
+            "member" => Some(PlotRole::Member),
+            "trusted" => Some(PlotRole::Trusted),
+            "denied" => Some(PlotRole::Denied),
+            _ => None,
+        }
+    }
+}
+
+/// The owner of a plot and the roles other players have on it.
+#[derive(Debug, Default)]
+pub struct PlotRoles {
+    pub owner: Option<u128>,
+    pub roles: HashMap<u128, PlotRole>,
+}
+
+impl PlotRoles {
+    pub fn get(&self, uuid: u128) -> Option<PlotRole> {
+        self.roles.get(&uuid).copied()
+    }
+}
+
+/// Permission nodes with whether they are granted (`true`) or revoked (`false`)
+pub type PermissionOverrides = Vec<(String, bool)>;
 
 lazy_static! {
-    static ref CONN: Mutex<Connection> =
-        Mutex::new(Connection::open("./world/plots.db").expect("Error opening plot database!"));
+    static ref CONN: Mutex<Connection> = Mutex::new(open_connection());
+    /// Roles are checked on every block change, so they are kept here until they change
+    static ref ROLE_CACHE: Mutex<HashMap<(i32, i32), Arc<PlotRoles>>> = Default::default();
+    /// Permissions are checked on every block change too
+    static ref PERMISSION_CACHE: Mutex<HashMap<u128, Arc<PermissionOverrides>>> =
+        Default::default();
+}
+
+#[cfg(not(test))]
+fn open_connection() -> Connection {
+    Connection::open("./world/plots.db").expect("Error opening plot database!")
+}
+
+/// Tests use an empty database so that they don't change the world
+#[cfg(test)]
+fn open_connection() -> Connection {
+    Connection::open_in_memory().unwrap()
 }
 
 fn lock<'a>() -> MutexGuard<'a, Connection> {
     CONN.lock().unwrap()
 }
 
+/// Returns the owner and roles of a plot, only querying the database if they changed
+/// since the last call.
+pub fn get_plot_roles(plot_x: i32, plot_z: i32) -> Arc<PlotRoles> {
+    // The cache stays locked while querying so that a change made in the meantime
+    // can't be overwritten with the old roles
+    let mut cache = ROLE_CACHE.lock().unwrap();
+    cache
+        .entry((plot_x, plot_z))
+        .or_insert_with(|| {
+            Arc::new(PlotRoles {
+                owner: get_plot_owner(plot_x, plot_z),
+                roles: get_plot_members(plot_x, plot_z).into_iter().collect(),
+            })
+        })
+        .clone()
+}
+
+/// Must be called after changing the owner or roles of a plot, once the connection
+/// is unlocked.
+fn invalidate_plot_roles(plot_x: i32, plot_z: i32) {
+    ROLE_CACHE.lock().unwrap().remove(&(plot_x, plot_z));
+}
+
 pub fn get_plot_owner(plot_x: i32, plot_z: i32) -> Option<u128> {
     lock()
         .query_row(
@@ -21,6 +106,43 @@ pub fn get_plot_owner(plot_x: i32, plot_z: i32) -> Option<u128> {
         .map(|uuid| uuid.parse().unwrap())
 }
 
+pub fn get_plot_members(plot_x: i32, plot_z: i32) -> Vec<(u128, PlotRole)> {
+    let conn = lock();
+    let mut stmt = conn
+        .prepare("SELECT uuid, role FROM plot_members WHERE plot_x=?1 AND plot_z=?2")
+        .unwrap();
+    stmt.query_map(params![plot_x, plot_z], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })
+    .unwrap()
+    .filter_map(Result::ok)
+    .filter_map(|(uuid, role)| Some((uuid.parse().ok()?, PlotRole::from_str(&role)?)))
+    .collect()
+}
+
+pub fn set_plot_role(plot_x: i32, plot_z: i32, uuid: u128, role: PlotRole) {
+    lock()
+        .execute(
+            "INSERT OR REPLACE INTO plot_members (plot_x, plot_z, uuid, role) VALUES (?1, ?2, ?3, ?4)",
+            params![plot_x, plot_z, uuid.to_string(), role.as_str()],
+        )
+        .unwrap();
+    invalidate_plot_roles(plot_x, plot_z);
+}
+
+/// Removes any role the player has on the plot, returning whether they had one.
+pub fn remove_plot_role(plot_x: i32, plot_z: i32, uuid: u128) -> bool {
+    let removed = lock()
+        .execute(
+            "DELETE FROM plot_members WHERE plot_x=?1 AND plot_z=?2 AND uuid=?3",
+            params![plot_x, plot_z, uuid.to_string()],
+        )
+        .unwrap()
+        > 0;
+    invalidate_plot_roles(plot_x, plot_z);
+    removed
+}
+
 pub fn claim_plot(plot_x: i32, plot_z: i32, owner: &str) {
     lock()
         .execute(
@@ -28,6 +150,217 @@ pub fn claim_plot(plot_x: i32, plot_z: i32, owner: &str) {
             params![plot_x, plot_z, owner],
         )
         .unwrap();
+    invalidate_plot_roles(plot_x, plot_z);
+}
+
+/// Removes the owner and members of a plot and stops it from ticking while empty.
+/// Returns false if the plot wasn't claimed.
+pub fn unclaim_plot(plot_x: i32, plot_z: i32) -> bool {
+    let unclaimed = unclaim_plot_locked(&lock(), plot_x, plot_z);
+    invalidate_plot_roles(plot_x, plot_z);
+    unclaimed
+}
+
+fn unclaim_plot_locked(conn: &Connection, plot_x: i32, plot_z: i32) -> bool {
+    conn.execute(
+        "DELETE FROM plot_members WHERE plot_x=?1 AND plot_z=?2",
+        params![plot_x, plot_z],
+    )
+    .unwrap();
+    // Nobody would be left who could turn it off again
+    conn.execute(
+        "DELETE FROM keep_ticking_plots WHERE plot_x=?1 AND plot_z=?2",
+        params![plot_x, plot_z],
+    )
+    .unwrap();
+    conn.execute(
+        "DELETE FROM plots WHERE plot_x=?1 AND plot_z=?2",
+        params![plot_x, plot_z],
+    )
+    .unwrap()
+        > 0
+}
+
+/// Claims the unclaimed plot closest to the given plot, skipping the spawn plot, and
+/// returns its coordinates.
+pub fn auto_claim_plot(plot_x: i32, plot_z: i32, owner: &str) -> (i32, i32) {
+    let (x, z) = auto_claim_plot_locked(&lock(), plot_x, plot_z, owner);
+    invalidate_plot_roles(x, z);
+    (x, z)
+}
+
+fn auto_claim_plot_locked(conn: &Connection, plot_x: i32, plot_z: i32, owner: &str) -> (i32, i32) {
+    let is_claimed = |x: i32, z: i32| {
+        conn.query_row(
+            "SELECT 1 FROM plots WHERE plot_x=?1 AND plot_z=?2",
+            params![x, z],
+            |_| Ok(()),
+        )
+        .is_ok()
+    };
+    // Search in growing squares around the plot
+    let (x, z) = (0..)
+        .flat_map(|radius: i32| {
+            (-radius..=radius)
+                .flat_map(move |dx| (-radius..=radius).map(move |dz| (dx, dz)))
+                .filter(move |(dx, dz)| dx.abs().max(dz.abs()) == radius)
+        })
+        .map(|(dx, dz)| (plot_x + dx, plot_z + dz))
+        .find(|&(x, z)| (x, z) != (0, 0) && !is_claimed(x, z))
+        .unwrap();
+    conn.execute(
+        "INSERT INTO plots (plot_x, plot_z, owner) VALUES (?1, ?2, ?3)",
+        params![x, z, owner],
+    )
+    .unwrap();
+    (x, z)
+}
+
+/// Returns the plots a player owns in the order they were claimed.
+pub fn get_owned_plots(owner: u128) -> Vec<(i32, i32)> {
+    let conn = lock();
+    let mut stmt = conn
+        .prepare("SELECT plot_x, plot_z FROM plots WHERE owner=?1 ORDER BY id")
+        .unwrap();
+    stmt.query_map(params![owner.to_string()], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })
+    .unwrap()
+    .filter_map(Result::ok)
+    .collect()
+}
+
+/// Remembers the username of a player so that commands can refer to players who
+/// aren't online.
+pub fn update_player_name(uuid: u128, username: &str) {
+    lock()
+        .execute(
+            "INSERT OR REPLACE INTO players (uuid, username) VALUES (?1, ?2)",
+            params![uuid.to_string(), username],
+        )
+        .unwrap();
+}
+
+pub fn get_player_uuid(username: &str) -> Option<u128> {
+    lock()
+        .query_row(
+            "SELECT uuid FROM players WHERE username=?1 COLLATE NOCASE",
+            params![username],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .map(|uuid| uuid.parse().unwrap())
+}
+
+pub fn get_player_name(uuid: u128) -> Option<String> {
+    lock()
+        .query_row(
+            "SELECT username FROM players WHERE uuid=?1",
+            params![uuid.to_string()],
+            |row| row.get(0),
+        )
+        .ok()
+}
+
+/// Returns the permission nodes that have been granted (`true`) or revoked (`false`)
+/// for a player, only querying the database if they changed since the last call.
+pub fn get_permission_overrides(uuid: u128) -> Arc<PermissionOverrides> {
+    let mut cache = PERMISSION_CACHE.lock().unwrap();
+    cache
+        .entry(uuid)
+        .or_insert_with(|| {
+            let conn = lock();
+            let mut stmt = conn
+                .prepare("SELECT node, value FROM permissions WHERE uuid=?1")
+                .unwrap();
+            let overrides = stmt
+                .query_map(params![uuid.to_string()], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            Arc::new(overrides)
+        })
+        .clone()
+}
+
+/// Must be called after changing the overrides of a player, once the connection is
+/// unlocked.
+fn invalidate_permission_overrides(uuid: u128) {
+    PERMISSION_CACHE.lock().unwrap().remove(&uuid);
+}
+
+pub fn set_permission_override(uuid: u128, node: &str, value: bool) {
+    lock()
+        .execute(
+            "INSERT OR REPLACE INTO permissions (uuid, node, value) VALUES (?1, ?2, ?3)",
+            params![uuid.to_string(), node, value],
+        )
+        .unwrap();
+    invalidate_permission_overrides(uuid);
+}
+
+/// Removes the override for a node, returning whether there was one.
+pub fn remove_permission_override(uuid: u128, node: &str) -> bool {
+    let removed = lock()
+        .execute(
+            "DELETE FROM permissions WHERE uuid=?1 AND node=?2",
+            params![uuid.to_string(), node],
+        )
+        .unwrap()
+        > 0;
+    invalidate_permission_overrides(uuid);
+    removed
+}
+
+/// Fills the caches so that tests don't need the database.
+#[cfg(test)]
+pub fn cache_plot_roles(plot_x: i32, plot_z: i32, roles: PlotRoles) {
+    ROLE_CACHE
+        .lock()
+        .unwrap()
+        .insert((plot_x, plot_z), Arc::new(roles));
+}
+
+#[cfg(test)]
+pub fn cache_permission_overrides(uuid: u128, overrides: PermissionOverrides) {
+    PERMISSION_CACHE
+        .lock()
+        .unwrap()
+        .insert(uuid, Arc::new(overrides));
+}
+
+/// Returns whether the plot should keep ticking and stay loaded while nobody is in it.
+pub fn get_keep_ticking(plot_x: i32, plot_z: i32) -> bool {
+    lock()
+        .query_row(
+            "SELECT 1 FROM keep_ticking_plots WHERE plot_x=?1 AND plot_z=?2",
+            params![plot_x, plot_z],
+            |_| Ok(()),
+        )
+        .is_ok()
+}
+
+pub fn set_keep_ticking(plot_x: i32, plot_z: i32, keep_ticking: bool) {
+    let sql = if keep_ticking {
+        "INSERT OR IGNORE INTO keep_ticking_plots (plot_x, plot_z) VALUES (?1, ?2)"
+    } else {
+        "DELETE FROM keep_ticking_plots WHERE plot_x=?1 AND plot_z=?2"
+    };
+    lock().execute(sql, params![plot_x, plot_z]).unwrap();
+}
+
+/// Returns the plots that should be loaded when the server starts.
+pub fn get_keep_ticking_plots() -> Vec<(i32, i32)> {
+    let conn = lock();
+    let mut stmt = conn
+        .prepare("SELECT plot_x, plot_z FROM keep_ticking_plots")
+        .unwrap();
+    stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
 }
 
 pub fn init() {
@@ -43,4 +376,61 @@ pub fn init() {
         NO_PARAMS,
     )
     .unwrap();
+
+    conn.execute(
+        "create table if not exists plot_members (
+            plot_x INTEGER NOT NULL,
+            plot_z INTEGER NOT NULL,
+            uuid VARCHAR(40) NOT NULL,
+            role VARCHAR(16) NOT NULL,
+            PRIMARY KEY (plot_x, plot_z, uuid)
+        )",
+        NO_PARAMS,
+    )
+    .unwrap();
+
+    conn.execute(
+        "create table if not exists players (
+            uuid VARCHAR(40) PRIMARY KEY NOT NULL,
+            username VARCHAR(16) NOT NULL
+        )",
+        NO_PARAMS,
+    )
+    .unwrap();
+
+    conn.execute(
+        "create table if not exists permissions (
+            uuid VARCHAR(40) NOT NULL,
+            node TEXT NOT NULL,
+            value BOOLEAN NOT NULL,
+            PRIMARY KEY (uuid, node)
+        )",
+        NO_PARAMS,
+    )
+    .unwrap();
+
+    let new_keep_ticking_table = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type='table' AND name='keep_ticking_plots'",
+            NO_PARAMS,
+            |_| Ok(()),
+        )
+        .is_err();
+    conn.execute(
+        "create table if not exists keep_ticking_plots (
+            plot_x INTEGER NOT NULL,
+            plot_z INTEGER NOT NULL,
+            PRIMARY KEY (plot_x, plot_z)
+        )",
+        NO_PARAMS,
+    )
+    .unwrap();
+    // The spawn plot used to always be loaded, so it starts out ticking
+    if new_keep_ticking_table {
+        conn.execute(
+            "INSERT INTO keep_ticking_plots (plot_x, plot_z) VALUES (0, 0)",
+            NO_PARAMS,
+        )
+        .unwrap();
+    }
 }
@@ -0,0 +1,80 @@
+//! Breakpoints pause a plot as soon as a watched block changes, which together with
+//! `/rpause` and `/rstep` lets circuits be looked at one tick at a time.
+
+use super::probe::read_power;
+use super::Plot;
+use crate::blocks::BlockPos;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointKind {
+    /// Triggers when the redstone power at the position changes
+    Power,
+    /// Triggers when the block state at the position changes
+    State,
+}
+
+impl BreakpointKind {
+    pub fn from_str(name: &str) -> Option<BreakpointKind> {
+        match name {
+            "power" => Some(BreakpointKind::Power),
+            "state" => Some(BreakpointKind::State),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakpointKind::Power => "power",
+            BreakpointKind::State => "state",
+        }
+    }
+}
+
+pub struct Breakpoint {
+    pub pos: BlockPos,
+    pub kind: BreakpointKind,
+    /// The value when the breakpoint was last checked
+    value: u32,
+}
+
+impl Breakpoint {
+    pub fn new(plot: &Plot, pos: BlockPos, kind: BreakpointKind) -> Breakpoint {
+        Breakpoint {
+            pos,
+            kind,
+            value: Breakpoint::read(plot, pos, kind),
+        }
+    }
+
+    fn read(plot: &Plot, pos: BlockPos, kind: BreakpointKind) -> u32 {
+        match kind {
+            BreakpointKind::Power => read_power(plot, pos) as u32,
+            BreakpointKind::State => plot.get_block_raw(pos),
+        }
+    }
+
+    /// Returns true if the watched value changed since the last check.
+    pub fn check(&mut self, plot: &Plot) -> bool {
+        let value = Breakpoint::read(plot, self.pos, self.kind);
+        let changed = value != self.value;
+        self.value = value;
+        changed
+    }
+}
+
+#[test]
+fn breakpoint_test() {
+    use crate::blocks::Block;
+
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let pos = BlockPos::new(5, 10, 5);
+    let mut state = Breakpoint::new(&plot, pos, BreakpointKind::State);
+    let mut power = Breakpoint::new(&plot, pos, BreakpointKind::Power);
+    assert!(!state.check(&plot));
+
+    plot.set_block(pos, Block::RedstoneBlock);
+    assert!(state.check(&plot));
+    assert!(power.check(&plot));
+    assert!(!state.check(&plot));
+    assert!(!power.check(&plot));
+}
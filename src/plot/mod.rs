@@ -1,25 +1,42 @@
 pub mod commands;
 pub mod database;
+mod debugger;
 mod packets;
+mod probe;
+mod profiler;
+mod scheduler;
 mod storage;
+mod window;
 pub mod worldedit;
 
 use crate::blocks::{Block, BlockEntity, BlockPos};
+use crate::config::{UnclaimedPlotPolicy, CONFIG};
 use crate::network::packets::clientbound::*;
 use crate::network::packets::SlotData;
 use crate::player::Player;
+use crate::redpiler::Compiler;
 use crate::server::{BroadcastMessage, Message, PrivMessage};
-use bus::BusReader;
-use log::{debug, warn};
+use bus::{Bus, BusReader};
+use database::PlotRole;
+use debugger::Breakpoint;
+use log::{debug, error, warn};
+use probe::Probes;
+use profiler::Profiler;
+use scheduler::TickScheduler;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::io::{self, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
-use std::time::{Duration, SystemTime};
-use storage::{Chunk, ChunkData, PlotData};
+use std::time::{Duration, Instant, SystemTime};
+use storage::{Chunk, ChunkData, PlotData, PLOT_FORMAT_VERSION};
+
+pub use window::ContainerWindow;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TickPriority {
@@ -30,7 +47,7 @@ pub enum TickPriority {
 }
 
 impl TickPriority {
-    fn values() -> [TickPriority; 4] {
+    pub fn values() -> [TickPriority; 4] {
         use TickPriority::*;
         [Highest, Higher, High, Normal]
     }
@@ -50,7 +67,7 @@ pub struct Plot {
     // It's kinda dumb making this pub but it would be too much work to do it differently.
     pub players: Vec<Player>,
     tps: u32,
-    to_be_ticked: Vec<TickEntry>,
+    to_be_ticked: TickScheduler,
     last_update_time: SystemTime,
     lag_time: Duration,
     last_player_time: SystemTime,
@@ -59,27 +76,78 @@ pub struct Plot {
     x: i32,
     z: i32,
     show_redstone: bool,
+    /// Set with `/plot keepticking` to keep the plot ticking and loaded while nobody is in it
     always_running: bool,
+    /// Headless plots aren't connected to a server and are never saved.
+    headless: bool,
+    /// Set when the plot is deleted so it doesn't get saved again when it unloads
+    deleted: bool,
+    /// Set once the plot thread panicked and the players were sent back to the server
+    /// thread
+    crashed: bool,
     chunks: Vec<Chunk>,
+    redpiler: Compiler,
+    /// The latest state of each block that changed since the last time block changes
+    /// were sent to the players.
+    block_changes: HashMap<BlockPos, u32>,
+    last_block_change_flush: Instant,
+    /// Set while `/rprofile` is running
+    profiler: Option<Profiler>,
+    probes: Probes,
+    /// Set by `/rpause` or a breakpoint to stop the plot from ticking on its own
+    paused: bool,
+    breakpoints: Vec<Breakpoint>,
+    /// Collects the scheduled ticks that ran while `/rstep` is stepping
+    fired_ticks: Option<Vec<TickEntry>>,
+}
+
+/// The height players are teleported to when they go to a plot
+const TELEPORT_Y: f64 = 128.0;
+
+/// The width of a plot in blocks, which is always a multiple of 16
+pub fn plot_size() -> i32 {
+    CONFIG.generator.plot_size
+}
+
+/// The width of a plot in chunks
+fn plot_chunks() -> i32 {
+    plot_size() >> 4
+}
+
+/// Returns the coordinates of the plot containing the block at `x`, `z`.
+pub fn plot_at(x: i32, z: i32) -> (i32, i32) {
+    (x.div_euclid(plot_size()), z.div_euclid(plot_size()))
+}
+
+/// Where players are sent when they get kicked or denied from a plot
+pub fn spawn_pos() -> (f64, f64, f64) {
+    Plot::plot_center(0, 0)
 }
 
+/// If more blocks than this change in a single chunk at once, the whole chunk gets
+/// resent instead of a multi block change.
+const MULTI_BLOCK_CHANGE_LIMIT: usize = 2048;
+/// How often block changes get sent to the players. Redstone can run much faster
+/// than this, so only the latest state of each block gets sent.
+const BLOCK_CHANGE_FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+
 impl Plot {
     fn get_chunk_index_for_chunk(&self, chunk_x: i32, chunk_z: i32) -> usize {
-        let local_x = chunk_x - self.x * 16;
-        let local_z = chunk_z - self.z * 16;
-        (local_x * 16 + local_z).abs() as usize
+        let local_x = chunk_x - self.x * plot_chunks();
+        let local_z = chunk_z - self.z * plot_chunks();
+        (local_x * plot_chunks() + local_z).abs() as usize
     }
 
     fn get_chunk_index_for_block(&self, block_x: i32, block_z: i32) -> usize {
-        let chunk_x = (block_x - (self.x << 8)) >> 4;
-        let chunk_z = (block_z - (self.z << 8)) >> 4;
-        ((chunk_x << 4) + chunk_z).abs() as usize
+        let chunk_x = (block_x - self.x * plot_size()) >> 4;
+        let chunk_z = (block_z - self.z * plot_size()) >> 4;
+        (chunk_x * plot_chunks() + chunk_z).abs() as usize
     }
 
     /// Sets a block in storage without sending a block change packet to the client. Returns true if a block was changed.
     fn set_block_raw(&mut self, pos: BlockPos, block: u32) -> bool {
         let chunk_index = self.get_chunk_index_for_block(pos.x, pos.z);
-        if chunk_index >= 256 {
+        if chunk_index >= self.chunks.len() {
             return false;
         }
         let chunk = &mut self.chunks[chunk_index];
@@ -92,13 +160,14 @@ impl Plot {
         let changed = self.set_block_raw(pos, block_id);
         if changed {
             self.send_block_change(pos, block_id);
+            Block::notify_observers(self, pos);
         }
         changed
     }
 
     pub fn get_block_raw(&self, pos: BlockPos) -> u32 {
         let chunk_index = self.get_chunk_index_for_block(pos.x, pos.z);
-        if chunk_index >= 256 {
+        if chunk_index >= self.chunks.len() {
             return 0;
         }
         let chunk = &self.chunks[chunk_index];
@@ -109,22 +178,75 @@ impl Plot {
         Block::from_block_state(self.get_block_raw(pos))
     }
 
+    /// Queues a block change to be sent to the players in the plot. The changes are
+    /// sent in batches by `flush_block_changes`.
     pub fn send_block_change(&mut self, pos: BlockPos, id: u32) {
-        let block_change = C0CBlockChange {
-            block_id: id as i32,
-            x: pos.x,
-            y: pos.y as i32,
-            z: pos.z,
+        if self.players.is_empty() {
+            return;
         }
-        .encode();
+        self.block_changes.insert(pos, id);
+    }
+
+    /// Sends all the queued block changes to the players in the plot. This needs to
+    /// be called before anything that could depend on the changes already being sent,
+    /// such as chunk data.
+    pub fn flush_block_changes(&mut self) {
+        self.last_block_change_flush = Instant::now();
+        if self.block_changes.is_empty() {
+            return;
+        }
+        let mut chunks: HashMap<(i32, i32), Vec<C10MultiBlockChangeRecord>> = HashMap::new();
+        for (pos, id) in self.block_changes.drain() {
+            chunks
+                .entry((pos.x >> 4, pos.z >> 4))
+                .or_default()
+                .push(C10MultiBlockChangeRecord {
+                    x: (pos.x & 0xF) as i8,
+                    y: pos.y as u8,
+                    z: (pos.z & 0xF) as i8,
+                    block_id: id as i32,
+                });
+        }
+        for ((chunk_x, chunk_z), records) in chunks {
+            self.send_multi_block_change(C10MultiBlockChange {
+                chunk_x,
+                chunk_z,
+                records,
+            });
+        }
+    }
+
+    /// Sends the changes in a chunk using the smallest packet for the number of changes.
+    fn send_multi_block_change(&mut self, multi_block_change: C10MultiBlockChange) {
+        let chunk_x = multi_block_change.chunk_x;
+        let chunk_z = multi_block_change.chunk_z;
+        let records = &multi_block_change.records;
+        if records.is_empty() || !Plot::chunk_in_plot_bounds(self.x, self.z, chunk_x, chunk_z) {
+            return;
+        }
+        let packet = if records.len() == 1 {
+            let record = &records[0];
+            C0CBlockChange {
+                block_id: record.block_id,
+                x: (chunk_x << 4) | record.x as i32,
+                y: record.y as i32,
+                z: (chunk_z << 4) | record.z as i32,
+            }
+            .encode()
+        } else if records.len() > MULTI_BLOCK_CHANGE_LIMIT {
+            let chunk_index = self.get_chunk_index_for_chunk(chunk_x, chunk_z);
+            self.chunks[chunk_index].encode_packet(false)
+        } else {
+            multi_block_change.encode()
+        };
         for player in &mut self.players {
-            player.client.send_packet(&block_change);
+            player.client.send_packet(&packet);
         }
     }
 
     pub fn delete_block_entity(&mut self, pos: BlockPos) {
         let chunk_index = self.get_chunk_index_for_block(pos.x, pos.z);
-        if chunk_index >= 256 {
+        if chunk_index >= self.chunks.len() {
             return;
         }
         let chunk = &mut self.chunks[chunk_index];
@@ -133,7 +255,7 @@ impl Plot {
 
     pub fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
         let chunk_index = self.get_chunk_index_for_block(pos.x, pos.z);
-        if chunk_index >= 256 {
+        if chunk_index >= self.chunks.len() {
             return None;
         }
         let chunk = &self.chunks[chunk_index];
@@ -142,10 +264,12 @@ impl Plot {
 
     pub fn set_block_entity(&mut self, pos: BlockPos, block_entity: BlockEntity) {
         let chunk_index = self.get_chunk_index_for_block(pos.x, pos.z);
-        if chunk_index >= 256 {
+        if chunk_index >= self.chunks.len() {
             return;
         }
         if let Some(nbt) = block_entity.to_nbt(pos) {
+            // The block has to be there before the client will accept its block entity
+            self.flush_block_changes();
             let block_entity_data = C0ABlockEntityData {
                 x: pos.x,
                 y: pos.y as i32,
@@ -175,30 +299,348 @@ impl Plot {
     }
 
     pub fn schedule_tick(&mut self, pos: BlockPos, delay: u32, priority: TickPriority) {
-        self.to_be_ticked.push(TickEntry {
-            pos,
-            ticks_left: delay,
-            tick_priority: priority,
-        });
-        self.to_be_ticked
-            .sort_by_key(|e| (e.ticks_left, e.tick_priority.clone()));
+        self.to_be_ticked.schedule(pos, delay, priority);
     }
 
     pub fn pending_tick_at(&mut self, pos: BlockPos) -> bool {
-        self.to_be_ticked.iter().any(|e| e.pos == pos)
+        self.to_be_ticked.is_pending(pos)
+    }
+
+    pub fn remove_pending_ticks_at(&mut self, pos: BlockPos) {
+        self.to_be_ticked.cancel(pos);
+    }
+
+    /// Returns the delay and priority of every tick scheduled at `pos`, including the
+    /// ones scheduled by the redpiler.
+    pub fn pending_ticks_at(&self, pos: BlockPos) -> Vec<(u32, TickPriority)> {
+        let mut ticks = self.to_be_ticked.pending_at(pos);
+        if self.redpiler.is_active() {
+            ticks.extend(
+                self.redpiler
+                    .pending_ticks()
+                    .into_iter()
+                    .filter(|(tick_pos, _, _)| *tick_pos == pos)
+                    .map(|(_, delay, priority)| (delay, priority)),
+            );
+        }
+        ticks.sort();
+        ticks
+    }
+
+    /// Counts the players touching the box from `min` to `max`, given in block
+    /// coordinates. Players are 0.6 blocks wide and 1.8 blocks tall.
+    pub fn players_in_box(&self, min: [f64; 3], max: [f64; 3]) -> usize {
+        self.players
+            .iter()
+            .filter(|player| {
+                player.x + 0.3 > min[0]
+                    && player.x - 0.3 < max[0]
+                    && player.y + 1.8 > min[1]
+                    && player.y < max[1]
+                    && player.z + 0.3 > min[2]
+                    && player.z - 0.3 < max[2]
+            })
+            .count()
+    }
+
+    /// Lets the pressure plates and tripwire that players are touching know about
+    /// them. Blocks which are already powered check for players again when they are
+    /// ticked.
+    fn check_player_collisions(&mut self) {
+        let mut positions = Vec::new();
+        for player in &self.players {
+            let min_x = (player.x - 0.3).floor() as i32;
+            let max_x = (player.x + 0.3).floor() as i32;
+            let min_y = player.y.floor().max(0.0) as u32;
+            let max_y = (player.y + 1.8).floor().max(0.0) as u32;
+            let min_z = (player.z - 0.3).floor() as i32;
+            let max_z = (player.z + 0.3).floor() as i32;
+            for x in min_x..=max_x {
+                for y in min_y..=max_y {
+                    for z in min_z..=max_z {
+                        let pos = BlockPos::new(x, y, z);
+                        if !positions.contains(&pos) && self.pos_in_plot(pos) {
+                            positions.push(pos);
+                        }
+                    }
+                }
+            }
+        }
+        for pos in positions {
+            match self.get_block(pos) {
+                Block::PressurePlate(plate) if plate.power == 0 => plate.check_pressed(self, pos),
+                Block::Tripwire(tripwire) if !tripwire.powered => tripwire.check_powered(self, pos),
+                _ => {}
+            }
+        }
+    }
+
+    /// Counts a block update while the plot is being profiled.
+    pub fn profile_block_update(&mut self, block: Block) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_block_update(block);
+        }
+    }
+
+    pub fn pos_in_plot(&self, pos: BlockPos) -> bool {
+        Plot::in_plot_bounds(self.x, self.z, pos.x, pos.z) && pos.y < 256
+    }
+
+    /// Checks if the player may change blocks in this plot, returning the reason if
+    /// they can't. Only the owner and trusted players can build on a claimed plot, as
+    /// well as members while an owner is in the plot.
+    pub fn can_build(&self, player: usize) -> Result<(), String> {
+        let player = &self.players[player];
+        if player.has_permission("protection.bypass") {
+            return Ok(());
+        }
+        let roles = database::get_plot_roles(self.x, self.z);
+        match roles.owner {
+            Some(owner) => {
+                let owner_present = self.players.iter().any(|p| p.uuid == owner);
+                match roles.get(player.uuid) {
+                    _ if owner == player.uuid => return Ok(()),
+                    Some(PlotRole::Trusted) => return Ok(()),
+                    Some(PlotRole::Member) if owner_present => return Ok(()),
+                    _ => {}
+                }
+                let owner_name = database::get_player_name(owner)
+                    .unwrap_or_else(|| Player::uuid_with_hyphens(owner));
+                Err(format!(
+                    "You can't build here, this plot belongs to {}!",
+                    owner_name
+                ))
+            }
+            None => match CONFIG.unclaimed_plot_policy {
+                UnclaimedPlotPolicy::Allow => Ok(()),
+                UnclaimedPlotPolicy::Deny => {
+                    Err("You can't build here until the plot is claimed with /plot claim!".into())
+                }
+            },
+        }
+    }
+
+    /// Like `can_build`, but sends the player an error if they can't build.
+    pub fn check_can_build(&mut self, player: usize) -> bool {
+        match self.can_build(player) {
+            Ok(()) => true,
+            Err(message) => {
+                self.players[player].send_error_message(&message);
+                false
+            }
+        }
+    }
+
+    /// Calls `f` with every block in the plot that isn't air.
+    pub fn for_each_block<F: FnMut(BlockPos, Block)>(&self, mut f: F) {
+        for chunk in &self.chunks {
+            for section_y in chunk.sections.keys() {
+                let base_y = *section_y as u32 * 16;
+                for y in base_y..base_y + 16 {
+                    for z in 0..16 {
+                        for x in 0..16 {
+                            let id = chunk.get_block(x, y, z);
+                            if id != 0 {
+                                let pos = BlockPos::new(
+                                    chunk.x * 16 + x as i32,
+                                    y,
+                                    chunk.z * 16 + z as i32,
+                                );
+                                f(pos, Block::from_block_state(id));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the blocks changed by the redpiler back into the world and sends them to the players.
+    pub fn flush_redpiler(&mut self) {
+        for change in self.redpiler.take_changes() {
+            let block_id = change.block.get_id();
+            if self.set_block_raw(change.pos, block_id) {
+                self.send_block_change(change.pos, block_id);
+            }
+            if let Some(block_entity) = change.block_entity {
+                self.set_block_entity(change.pos, block_entity);
+            }
+        }
+    }
+
+    /// Compiles the redstone in the plot and starts simulating it with the redpiler.
+    pub fn compile_redpiler(&mut self) {
+        self.reset_redpiler();
+        let mut redpiler = Compiler::compile(self);
+        self.to_be_ticked.retain(|entry| {
+            !redpiler.take_pending_tick(entry.pos, entry.ticks_left, entry.tick_priority.clone())
+        });
+        self.redpiler = redpiler;
+    }
+
+    /// Stops the redpiler and hands everything it was simulating back to the world.
+    /// This needs to be called before the world is changed in any other way.
+    pub fn reset_redpiler(&mut self) {
+        if !self.redpiler.is_active() {
+            return;
+        }
+        self.flush_redpiler();
+        for (pos, delay, priority) in self.redpiler.reset() {
+            self.schedule_tick(pos, delay, priority);
+        }
+    }
+
+    /// Uses the block at `pos` as if a player had right clicked it with an empty hand.
+    pub fn use_block(&mut self, pos: BlockPos) {
+        if self.redpiler.is_active() {
+            if self.redpiler.on_use_block(pos) {
+                return;
+            }
+            self.reset_redpiler();
+        }
+        let block = self.get_block(pos);
+        block.on_use(self, pos, None);
     }
 
-    fn tick(&mut self) {
-        for pending in &mut self.to_be_ticked {
-            pending.ticks_left = pending.ticks_left.saturating_sub(1);
+    pub fn tick(&mut self) {
+        let start = self.profiler.as_ref().map(|_| Instant::now());
+        if self.redpiler.is_active() {
+            self.redpiler.tick();
         }
-        while self.to_be_ticked.first().map(|e| e.ticks_left).unwrap_or(1) == 0 {
-            let entry = self.to_be_ticked.remove(0);
+        self.to_be_ticked.next_tick();
+        while let Some(entry) = self.to_be_ticked.pop() {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record_scheduled_tick(&entry.tick_priority);
+            }
+            if let Some(fired_ticks) = &mut self.fired_ticks {
+                fired_ticks.push(entry.clone());
+            }
             self.get_block(entry.pos).tick(self, entry.pos);
         }
+        // Like entities in vanilla, players press plates after the scheduled ticks ran,
+        // so that the ticks they schedule wait for their whole delay
+        self.check_player_collisions();
+        if !self.probes.is_empty() || !self.breakpoints.is_empty() {
+            // Probes and breakpoints read the world, so it needs to be up to date
+            self.flush_redpiler();
+        }
+        self.check_breakpoints();
+        if !self.probes.is_empty() {
+            let mut probes = mem::take(&mut self.probes);
+            probes.record(self);
+            self.probes = probes;
+        }
+        if let (Some(profiler), Some(start)) = (&mut self.profiler, start) {
+            profiler.record_tick(start.elapsed());
+        }
+    }
+
+    /// Pauses the plot if any of the breakpoints were hit.
+    fn check_breakpoints(&mut self) {
+        if self.breakpoints.is_empty() {
+            return;
+        }
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        let hits: Vec<String> = breakpoints
+            .iter_mut()
+            .filter_map(|breakpoint| {
+                if breakpoint.check(self) {
+                    let pos = breakpoint.pos;
+                    Some(format!(
+                        "Paused at the {} breakpoint at ({}, {}, {})",
+                        breakpoint.kind.as_str(),
+                        pos.x,
+                        pos.y,
+                        pos.z
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        self.breakpoints = breakpoints;
+        if !hits.is_empty() {
+            self.paused = true;
+            for message in hits {
+                for player in &mut self.players {
+                    player.send_system_message(&message);
+                }
+            }
+        }
+    }
+
+    /// Adds a probe at `pos`, or removes the probe that is already there.
+    pub fn toggle_probe(&mut self, player: usize, pos: BlockPos, name: Option<&str>) {
+        if !self.check_can_build(player) {
+            return;
+        }
+        if let Some(name) = self.probes.remove_at(pos) {
+            self.players[player].send_system_message(&format!("Removed probe {}", name));
+            return;
+        }
+        let mut probes = mem::take(&mut self.probes);
+        let result = probes.add(self, pos, name);
+        self.probes = probes;
+        match result {
+            Ok(name) => self.players[player].send_system_message(&format!(
+                "Added probe {} at ({}, {}, {})",
+                name, pos.x, pos.y, pos.z
+            )),
+            Err(message) => self.players[player].send_error_message(&message),
+        }
+    }
+
+    /// Returns where players get teleported to when they go to a plot.
+    pub fn plot_center(plot_x: i32, plot_z: i32) -> (f64, f64, f64) {
+        (
+            (plot_x * plot_size() + plot_size() / 2) as f64,
+            TELEPORT_Y,
+            (plot_z * plot_size() + plot_size() / 2) as f64,
+        )
+    }
+
+    /// Teleports a player to another plot. Returns true if the player left this plot.
+    fn teleport_to_plot(&mut self, player_idx: usize, plot_x: i32, plot_z: i32) -> bool {
+        let (x, y, z) = Plot::plot_center(plot_x, plot_z);
+        if (plot_x, plot_z) == (self.x, self.z) {
+            self.players[player_idx].teleport(x, y, z);
+            return false;
+        }
+        let mut player = self.leave_plot(player_idx);
+        player.teleport(x, y, z);
+        self.message_sender
+            .send(Message::PlayerLeavePlot(player))
+            .unwrap();
+        true
+    }
+
+    /// Sends a player in this plot back to spawn.
+    fn send_to_spawn(&mut self, player_idx: usize, reason: &str) {
+        let player = self.leave_plot(player_idx);
+        self.bounce_to_spawn(player, reason);
+    }
+
+    fn bounce_to_spawn(&mut self, mut player: Player, reason: &str) {
+        player.send_error_message(reason);
+        let (x, y, z) = spawn_pos();
+        player.teleport(x, y, z);
+        self.message_sender
+            .send(Message::PlayerLeavePlot(player))
+            .unwrap();
     }
 
     fn enter_plot(&mut self, mut player: Player) {
+        // Players can't be denied from the spawn plot since that's where they get sent
+        let is_spawn = (self.x, self.z) == (0, 0);
+        if !is_spawn
+            && database::get_plot_roles(self.x, self.z).get(player.uuid) == Some(PlotRole::Denied)
+            && !player.has_permission("protection.bypass")
+        {
+            self.bounce_to_spawn(player, "You have been denied from this plot!");
+            return;
+        }
+        // Only count the packets sent while the player is in this plot
+        player.client.take_packets_sent();
         self.save();
         let spawn_player = C05SpawnPlayer {
             entity_id: player.entity_id as i32,
@@ -372,6 +814,7 @@ impl Plot {
     }
 
     fn leave_plot(&mut self, player_index: usize) -> Player {
+        self.close_container(player_index);
         let mut player = self.players.remove(player_index);
         let mut entity_ids = Vec::new();
         for player in &self.players {
@@ -379,13 +822,11 @@ impl Plot {
         }
         let destroy_other_entities = C38DestroyEntities { entity_ids }.encode();
         player.client.send_packet(&destroy_other_entities);
-        let chunk_offset_x = self.x << 4;
-        let chunk_offset_z = self.z << 4;
         for chunk in &self.chunks {
             player.client.send_packet(
                 &C1EUnloadChunk {
-                    chunk_x: chunk_offset_x + chunk.x,
-                    chunk_z: chunk_offset_z + chunk.z,
+                    chunk_x: chunk.x,
+                    chunk_z: chunk.z,
                 }
                 .encode(),
             );
@@ -395,14 +836,15 @@ impl Plot {
     }
 
     fn chunk_in_plot_bounds(plot_x: i32, plot_z: i32, chunk_x: i32, chunk_z: i32) -> bool {
-        chunk_x >= plot_x * 16
-            && chunk_x < (plot_x + 1) * 16
-            && chunk_z >= plot_z * 16
-            && chunk_z < (plot_z + 1) * 16
+        let size = plot_chunks();
+        chunk_x >= plot_x * size
+            && chunk_x < (plot_x + 1) * size
+            && chunk_z >= plot_z * size
+            && chunk_z < (plot_z + 1) * size
     }
 
     fn in_plot_bounds(plot_x: i32, plot_z: i32, x: i32, z: i32) -> bool {
-        x >= plot_x * 256 && x < (plot_x + 1) * 256 && z >= plot_z * 256 && z < (plot_z + 1) * 256
+        plot_at(x, z) == (plot_x, plot_z)
     }
 
     fn update(&mut self) {
@@ -417,7 +859,11 @@ impl Plot {
                 BroadcastMessage::PlayerJoinedInfo(player_join_info) => {
                     let player_info = C34PlayerInfo::AddPlayer(vec![C34PlayerInfoAddPlayer {
                         name: player_join_info.username,
-                        properties: Vec::new(),
+                        properties: player_join_info
+                            .properties
+                            .into_iter()
+                            .map(Into::into)
+                            .collect(),
                         gamemode: 1,
                         ping: 0,
                         uuid: player_join_info.uuid,
@@ -465,12 +911,15 @@ impl Plot {
                 }
             }
         }
-        // Only tick if there are players in the plot
         if !self.players.is_empty() {
             self.last_player_time = SystemTime::now();
-            if self.tps != 0 {
+        }
+        // Only tick if there are players in the plot, unless it should keep ticking
+        if !self.players.is_empty() || self.always_running {
+            if self.tps != 0 && !self.paused {
                 let dur_per_tick = Duration::from_micros(1_000_000 / self.tps as u64);
-                let elapsed_time = self.last_update_time.elapsed().unwrap();
+                // The clock can go back, which counts as no time passing
+                let elapsed_time = self.last_update_time.elapsed().unwrap_or_default();
                 self.lag_time += elapsed_time;
                 self.last_update_time = SystemTime::now();
                 let ticks = self
@@ -486,14 +935,32 @@ impl Plot {
                 while self.lag_time >= dur_per_tick {
                     self.tick();
                     self.lag_time -= dur_per_tick;
+                    // A breakpoint was hit
+                    if self.paused {
+                        self.lag_time = Duration::from_secs(0);
+                        break;
+                    }
                 }
+                self.flush_redpiler();
             }
         } else {
             // Unload plot after 600 seconds unless the plot should be always loaded
-            if self.last_player_time.elapsed().unwrap().as_secs() > 600 && !self.always_running {
+            if self
+                .last_player_time
+                .elapsed()
+                .unwrap_or_default()
+                .as_secs()
+                > 600
+                && !self.always_running
+            {
                 self.running = false;
             }
         }
+        if let Some(profiler) = &mut self.profiler {
+            for player in &mut self.players {
+                profiler.record_packets_sent(player.client.take_packets_sent());
+            }
+        }
         // Update players
         for player_idx in 0..self.players.len() {
             if self.players[player_idx].update() {
@@ -544,6 +1011,10 @@ impl Plot {
             let player_leave_plot = Message::PlayerLeavePlot(player);
             self.message_sender.send(player_leave_plot).unwrap();
         }
+
+        if self.last_block_change_flush.elapsed() >= BLOCK_CHANGE_FLUSH_INTERVAL {
+            self.flush_block_changes();
+        }
     }
 
     fn load_from_file(
@@ -554,23 +1025,38 @@ impl Plot {
         tx: Sender<Message>,
         priv_rx: Receiver<PrivMessage>,
         always_running: bool,
-    ) -> Plot {
-        let chunk_x_offset = x << 4;
-        let chunk_z_offset = z << 4;
-        let plot_data: PlotData = bincode::deserialize(&data).unwrap();
+    ) -> Result<Plot, String> {
+        let size = plot_chunks();
+        let chunk_x_offset = x * size;
+        let chunk_z_offset = z * size;
+        let plot_data = PlotData::deserialize(&data)
+            .map_err(|err| format!("Plot {},{} could not be read: {}", x, z, err))?;
+        // Chunks are stored in order, so they would end up in the wrong places
+        if plot_data.chunk_data.len() != (size * size) as usize {
+            return Err(format!(
+                "Plot {},{} has {} chunks, but a plot_size of {} needs {}. Was plot_size changed?",
+                x,
+                z,
+                plot_data.chunk_data.len(),
+                plot_size(),
+                size * size
+            ));
+        }
+        let version = plot_data.version;
         let chunks: Vec<Chunk> = plot_data
             .chunk_data
             .into_iter()
             .enumerate()
             .map(|(i, c)| {
                 Chunk::load(
-                    chunk_x_offset + i as i32 / 16,
-                    chunk_z_offset + i as i32 % 16,
+                    chunk_x_offset + i as i32 / size,
+                    chunk_z_offset + i as i32 % size,
                     c,
+                    version,
                 )
             })
             .collect();
-        Plot {
+        Ok(Plot {
             last_player_time: SystemTime::now(),
             last_update_time: SystemTime::now(),
             lag_time: Duration::new(0, 0),
@@ -589,9 +1075,20 @@ impl Plot {
             x,
             z,
             always_running,
+            headless: false,
+            deleted: false,
+            crashed: false,
             chunks,
-            to_be_ticked: plot_data.pending_ticks,
-        }
+            to_be_ticked: TickScheduler::from_entries(plot_data.pending_ticks),
+            redpiler: Default::default(),
+            block_changes: HashMap::new(),
+            last_block_change_flush: Instant::now(),
+            profiler: None,
+            probes: Default::default(),
+            paused: false,
+            breakpoints: Vec::new(),
+            fired_ticks: None,
+        })
     }
 
     fn load(
@@ -601,7 +1098,7 @@ impl Plot {
         tx: Sender<Message>,
         priv_rx: Receiver<PrivMessage>,
         always_running: bool,
-    ) -> Plot {
+    ) -> Result<Plot, String> {
         if let Ok(data) = fs::read(format!("./world/plots/p{},{}", x, z)) {
             Plot::load_from_file(data, x, z, rx, tx, priv_rx, always_running)
         } else if Path::new("./world/plots/pTEMPLATE").exists() {
@@ -612,44 +1109,129 @@ impl Plot {
                 "Plot {},{} does not exist and no template was found, generating now.",
                 x, z
             );
-            let chunk_x_offset = x << 4;
-            let chunk_z_offset = z << 4;
-            let mut chunks = Vec::new();
-            for chunk_x in 0..16 {
-                for chunk_z in 0..16 {
-                    chunks.push(Chunk::generate(
-                        8,
-                        chunk_x + chunk_x_offset,
-                        chunk_z + chunk_z_offset,
-                    ));
-                }
+            Ok(Plot::generate(x, z, rx, tx, priv_rx, always_running))
+        }
+    }
+
+    fn generate_chunks(x: i32, z: i32) -> Vec<Chunk> {
+        let size = plot_chunks();
+        let chunk_x_offset = x * size;
+        let chunk_z_offset = z * size;
+        let mut chunks = Vec::new();
+        for chunk_x in 0..size {
+            for chunk_z in 0..size {
+                chunks.push(Chunk::generate(
+                    chunk_x + chunk_x_offset,
+                    chunk_z + chunk_z_offset,
+                ));
             }
-            Plot {
-                last_player_time: SystemTime::now(),
-                last_update_time: SystemTime::now(),
-                lag_time: Duration::new(0, 0),
-                sleep_time: Duration::from_millis(30),
-                message_receiver: rx,
-                message_sender: tx,
-                priv_message_receiver: priv_rx,
-                players: Vec::new(),
-                running: true,
-                show_redstone: true,
-                tps: 20,
-                x,
-                z,
-                always_running,
-                chunks,
-                to_be_ticked: Vec::new(),
+        }
+        chunks
+    }
+
+    /// Replaces every chunk with a freshly generated one. Pending ticks, block entities,
+    /// compiled redstone, probes and breakpoints are thrown away and the plot is
+    /// unpaused.
+    pub fn clear(&mut self) {
+        self.redpiler = Default::default();
+        self.to_be_ticked.clear();
+        self.block_changes.clear();
+        self.probes = Default::default();
+        self.breakpoints.clear();
+        self.paused = false;
+        self.fired_ticks = None;
+        self.chunks = Plot::generate_chunks(self.x, self.z);
+        for player_idx in 0..self.players.len() {
+            self.update_view_pos_for_player(player_idx, true);
+        }
+    }
+
+    /// Sends everyone to spawn, removes the plot file and unloads the plot without
+    /// saving it, so it gets generated again the next time someone enters it.
+    pub fn delete(&mut self) {
+        for player_idx in (0..self.players.len()).rev() {
+            self.send_to_spawn(player_idx, "This plot has been deleted!");
+        }
+        let path = format!("./world/plots/p{},{}", self.x, self.z);
+        if let Err(err) = fs::remove_file(&path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!("Unable to remove {}: {}", path, err);
             }
         }
+        self.deleted = true;
+        self.running = false;
+    }
+
+    fn generate(
+        x: i32,
+        z: i32,
+        rx: BusReader<BroadcastMessage>,
+        tx: Sender<Message>,
+        priv_rx: Receiver<PrivMessage>,
+        always_running: bool,
+    ) -> Plot {
+        let chunks = Plot::generate_chunks(x, z);
+        Plot {
+            last_player_time: SystemTime::now(),
+            last_update_time: SystemTime::now(),
+            lag_time: Duration::new(0, 0),
+            sleep_time: Duration::from_millis(30),
+            message_receiver: rx,
+            message_sender: tx,
+            priv_message_receiver: priv_rx,
+            players: Vec::new(),
+            running: true,
+            show_redstone: true,
+            tps: 20,
+            x,
+            z,
+            always_running,
+            headless: false,
+            deleted: false,
+            crashed: false,
+            chunks,
+            to_be_ticked: Default::default(),
+            redpiler: Default::default(),
+            block_changes: HashMap::new(),
+            last_block_change_flush: Instant::now(),
+            profiler: None,
+            probes: Default::default(),
+            paused: false,
+            breakpoints: Vec::new(),
+            fired_ticks: None,
+        }
+    }
+
+    /// Creates a plot that isn't connected to a server so redstone can be simulated
+    /// without any players. If `data` is `None`, an empty plot is generated.
+    pub fn load_headless(x: i32, z: i32, data: Option<Vec<u8>>) -> Result<Plot, String> {
+        let rx = Bus::new(1).add_rx();
+        let (tx, _) = mpsc::channel();
+        let (_, priv_rx) = mpsc::channel();
+        let mut plot = match data {
+            Some(data) => Plot::load_from_file(data, x, z, rx, tx, priv_rx, false)?,
+            None => Plot::generate(x, z, rx, tx, priv_rx, false),
+        };
+        plot.headless = true;
+        Ok(plot)
     }
 
-    fn save(&self) {
+    fn save(&mut self) {
         debug!("Saving plot {},{}", self.x, self.z);
+        self.flush_redpiler();
+        let mut pending_ticks = self.to_be_ticked.entries();
+        for (pos, ticks_left, tick_priority) in self.redpiler.pending_ticks() {
+            pending_ticks.push(TickEntry {
+                pos,
+                ticks_left,
+                tick_priority,
+            });
+        }
+        pending_ticks.sort_by_key(|e| (e.ticks_left, e.tick_priority.clone()));
         let mut file = OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(format!("./world/plots/p{},{}", self.x, self.z))
             .unwrap();
         let chunk_data: Vec<ChunkData> = self.chunks.iter().map(|c| c.save()).collect();
@@ -657,7 +1239,8 @@ impl Plot {
             tps: self.tps,
             show_redstone: self.show_redstone,
             chunk_data,
-            pending_ticks: self.to_be_ticked.clone(),
+            pending_ticks,
+            version: PLOT_FORMAT_VERSION,
         })
         .unwrap();
         file.write_all(&encoded).unwrap();
@@ -682,34 +1265,69 @@ impl Plot {
         rx: BusReader<BroadcastMessage>,
         tx: Sender<Message>,
         priv_rx: Receiver<PrivMessage>,
-        always_running: bool,
         initial_player: Option<Player>,
     ) {
-        let mut plot = Plot::load(x, z, rx, tx, priv_rx, always_running);
+        let always_running = database::get_keep_ticking(x, z);
+        let mut plot = match Plot::load(x, z, rx, tx.clone(), priv_rx, always_running) {
+            Ok(plot) => plot,
+            Err(err) => {
+                error!("{}", err);
+                // The server handles this like a crash and sends the player to spawn.
+                // Loading it again would fail the same way, so it isn't restarted.
+                let players = initial_player.into_iter().collect();
+                tx.send(Message::PlotCrashed(x, z, players, false)).unwrap();
+                return;
+            }
+        };
         thread::Builder::new()
             .name(format!("p{},{}", x, z))
-            .spawn(move || {
-                plot.run(initial_player);
-            })
+            .spawn(move || plot.run_catching_crashes(initial_player))
+            .unwrap();
+    }
+
+    /// Runs the plot until it unloads, handling a panic by handing the plot back to the
+    /// server thread.
+    fn run_catching_crashes(&mut self, initial_player: Option<Player>) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.run(initial_player)));
+        if result.is_err() {
+            self.handle_crash();
+        }
+    }
+
+    /// Saves what can be saved of a plot whose thread panicked and hands its players
+    /// back to the server thread, which sends them to spawn.
+    fn handle_crash(&mut self) {
+        error!("Plot {},{} crashed", self.x, self.z);
+        // The panic might have left the plot in a state that can't be saved
+        let save = !self.deleted && !self.headless;
+        if save && panic::catch_unwind(AssertUnwindSafe(|| self.save())).is_err() {
+            error!("Unable to save plot {},{} after it crashed", self.x, self.z);
+        }
+        let mut players = Vec::new();
+        for player_idx in (0..self.players.len()).rev() {
+            players.push(self.leave_plot(player_idx));
+        }
+        self.message_sender
+            .send(Message::PlotCrashed(
+                self.x,
+                self.z,
+                players,
+                self.always_running,
+            ))
             .unwrap();
+        self.crashed = true;
     }
 }
 
 impl Drop for Plot {
     fn drop(&mut self) {
-        if !self.players.is_empty() {
-            // TODO: send all players to spawn and send them message along the lines of:
-            // "The plot you were previously in has crashed, you have been teleported to the spawn plot."
-            for player in &mut self.players {
-                player.save();
-                // Give the player the bad news.
-                player.kick(
-                    r#"{ "text": "The plot you were previously in has crashed!", "color": "red" }"#
-                        .to_owned(),
-                );
-            }
+        // Crashed plots were already handed back to the server thread
+        if self.headless || self.crashed {
+            return;
+        }
+        if !self.deleted {
+            self.save();
         }
-        self.save();
         debug!("Plot {},{} unloaded", self.x, self.z);
         self.message_sender
             .send(Message::PlotUnload(self.x, self.z))
@@ -723,8 +1341,117 @@ fn chunk_save_and_load_test() {
     chunk.set_block(13, 63, 12, 332);
     chunk.set_block(13, 62, 12, 331);
     let chunk_data = chunk.save();
-    let loaded_chunk = Chunk::load(1, 1, chunk_data);
+    let loaded_chunk = Chunk::load(1, 1, chunk_data, PLOT_FORMAT_VERSION);
     assert_eq!(loaded_chunk.get_block(13, 63, 12), 332);
     assert_eq!(loaded_chunk.get_block(13, 62, 12), 331);
     assert_eq!(loaded_chunk.get_block(13, 64, 12), 0);
 }
+
+#[test]
+fn legacy_hopper_test() {
+    use crate::blocks::{get_inventory, ContainerType};
+
+    // Hoppers used to be containers that were always saved as this state
+    let hopper_pos = BlockPos::new(5, 10, 5);
+    let barrel_pos = BlockPos::new(5, 10, 4);
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    plot.set_block(hopper_pos, Block::from_block_state(6198));
+    plot.set_block_entity(
+        hopper_pos,
+        BlockEntity::LegacyContainer {
+            comparator_override: 8,
+        },
+    );
+    plot.set_block(barrel_pos, Block::Container(11136));
+    let chunk_data: Vec<ChunkData> = plot.chunks.iter().map(|c| c.save()).collect();
+    // Plots used to be saved without a version
+    let baseline_save = bincode::serialize(&(
+        plot.tps,
+        plot.show_redstone,
+        chunk_data,
+        Vec::<TickEntry>::new(),
+    ))
+    .unwrap();
+
+    let mut plot = Plot::load_headless(0, 0, Some(baseline_save)).unwrap();
+    let (ty, inventory) = get_inventory(&plot, hopper_pos).unwrap();
+    assert_eq!(ty, ContainerType::Hopper);
+    assert!(!inventory.is_empty());
+    // A neighbor update doesn't make the hopper start pushing items north
+    Block::from_name("stone")
+        .unwrap()
+        .place_in_plot(&mut plot, BlockPos::new(6, 10, 5), &None);
+    for _ in 0..20 {
+        plot.tick();
+    }
+    assert_eq!(plot.get_block(hopper_pos), Block::from_block_state(6198));
+    assert_eq!(get_inventory(&plot, hopper_pos).unwrap().1, inventory);
+    assert!(get_inventory(&plot, barrel_pos).unwrap().1.is_empty());
+}
+
+#[test]
+fn plot_chunk_count_test() {
+    let plot = Plot::load_headless(0, 0, None).unwrap();
+    let mut chunk_data: Vec<ChunkData> = plot.chunks.iter().map(|c| c.save()).collect();
+    chunk_data.pop();
+    let data = bincode::serialize(&PlotData {
+        tps: plot.tps,
+        show_redstone: plot.show_redstone,
+        chunk_data,
+        pending_ticks: Vec::new(),
+        version: PLOT_FORMAT_VERSION,
+    })
+    .unwrap();
+    assert!(Plot::load_headless(0, 0, Some(data)).is_err());
+    assert!(Plot::load_headless(0, 0, Some(vec![1, 2, 3])).is_err());
+}
+
+#[test]
+fn clear_test() {
+    use debugger::BreakpointKind;
+
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let pos = BlockPos::new(5, 10, 5);
+    plot.set_block(pos, Block::RedstoneBlock);
+    let mut probes = mem::take(&mut plot.probes);
+    probes.add(&plot, pos, None).unwrap();
+    plot.probes = probes;
+    plot.breakpoints
+        .push(Breakpoint::new(&plot, pos, BreakpointKind::Power));
+    plot.paused = true;
+
+    plot.clear();
+    assert_eq!(plot.get_block(pos), Block::Air);
+    assert!(plot.probes.is_empty());
+    assert!(plot.breakpoints.is_empty());
+    assert!(!plot.paused);
+}
+
+#[test]
+fn crash_test() {
+    use crate::network::NetworkClient;
+
+    let (tx, rx) = mpsc::channel();
+    let (_priv_tx, priv_rx) = mpsc::channel();
+    let mut bus = Bus::new(1);
+    let mut plot = Plot::generate(1, 0, bus.add_rx(), tx, priv_rx, true);
+    // Headless plots aren't saved, even when they crash
+    plot.headless = true;
+    let (client, _remote) = NetworkClient::connect_local(0);
+    plot.players
+        .push(Player::load_player(1, "crash_test".to_owned(), client));
+    let pos = BlockPos::new(plot.x * plot_size() + 8, 64, 8);
+    plot.set_block(pos, Block::Solid(crate::blocks::CRASHING_BLOCK_ID));
+    plot.schedule_tick(pos, 1, TickPriority::Normal);
+    plot.lag_time = Duration::from_secs(1);
+
+    plot.run_catching_crashes(None);
+    assert!(plot.players.is_empty());
+    match rx.try_recv() {
+        Ok(Message::PlotCrashed(1, 0, players, true)) => {
+            assert_eq!(players.len(), 1);
+            assert_eq!(players[0].username, "crash_test");
+        }
+        _ => panic!("The crash wasn't sent to the server thread"),
+    }
+}
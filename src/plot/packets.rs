@@ -1,5 +1,5 @@
 use super::Plot;
-use crate::blocks::{BlockFace, BlockPos};
+use crate::blocks::{Block, BlockFace, BlockPos, ContainerType};
 use crate::items::{Item, ItemStack, UseOnBlockContext};
 use crate::network::packets::clientbound::*;
 use crate::network::packets::serverbound::*;
@@ -41,6 +41,8 @@ impl Plot {
         match packet.packet_id {
             0x03 => return Ok(self.handle_chat_message(player, S03ChatMessage::decode(packet)?)),
             0x05 => self.handle_client_settings(player, S05ClientSettings::decode(packet)?),
+            0x09 => self.handle_click_window(player, S09ClickWindow::decode(packet)?),
+            0x0A => self.handle_close_window(player, S0ACloseWindow::decode(packet)?),
             0x0B => self.handle_plugin_message(player, S0BPluginMessage::decode(packet)?),
             0x0F => self.players[player].last_keep_alive_received = Instant::now(), // Keep Alive
             0x11 => self.handle_player_position(player, S11PlayerPosition::decode(packet)?),
@@ -165,6 +167,50 @@ impl Plot {
             return;
         }
 
+        // Probes are checked by `toggle_probe`, since they can be added by command too
+        if matches!(&item_in_hand, Some(item) if item.item_type == Item::RedstoneProbe) {
+            self.toggle_probe(player, block_pos, None);
+            return;
+        }
+
+        if let Err(message) = self.can_build(player) {
+            let is_wand = matches!(&item_in_hand, Some(item) if item.item_type == Item::WEWand);
+            if is_wand {
+                self.players[player].worldedit_set_second_position(
+                    block_pos.x,
+                    block_pos.y,
+                    block_pos.z,
+                );
+                return;
+            }
+            // Visitors can still flick switches to try out builds
+            let block = self.get_block(block_pos);
+            let is_switch = matches!(block, Block::Lever(_) | Block::StoneButton(_));
+            if !is_switch || self.players[player].crouching {
+                self.players[player].send_error_message(&message);
+                let placed_pos = block_pos.offset(block_face);
+                let placed_id = self.get_block_raw(placed_pos);
+                self.send_block_change(placed_pos, placed_id);
+                return;
+            }
+        }
+
+        // Opening a container doesn't change anything until items are moved around
+        if !self.players[player].crouching
+            && ContainerType::from_block(self.get_block(block_pos)).is_some()
+        {
+            self.open_container(player, block_pos);
+            return;
+        }
+
+        if self.redpiler.is_active() {
+            // Levers and buttons can be used without leaving the compiled simulation
+            if !self.players[player].crouching && self.redpiler.on_use_block(block_pos) {
+                return;
+            }
+            self.reset_redpiler();
+        }
+
         if let Some(item) = item_in_hand {
             item.use_on_block(
                 self,
@@ -173,6 +219,7 @@ impl Plot {
                     block_pos,
                     player_crouching: self.players[player].crouching,
                     player_direction: self.players[player].get_direction(),
+                    player_facing: self.players[player].get_facing(),
                     player_idx: player,
                 },
             );
@@ -409,6 +456,13 @@ impl Plot {
                 }
             }
 
+            if !self.check_can_build(player) {
+                let block_id = self.get_block_raw(block_pos);
+                self.send_block_change(block_pos, block_id);
+                return;
+            }
+
+            self.reset_redpiler();
             let other_block = self.get_block(block_pos);
             other_block.destroy(self, block_pos);
 
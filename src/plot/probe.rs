@@ -0,0 +1,247 @@
+//! Probes record the redstone power at a position every tick so the timing of a
+//! circuit can be checked without watching lamps.
+
+use super::Plot;
+use crate::blocks::{BlockFace, BlockPos};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::time::SystemTime;
+
+pub struct Probe {
+    pub pos: BlockPos,
+    pub name: String,
+    /// The ticks at which the power changed along with the new power
+    changes: Vec<(u64, u8)>,
+}
+
+impl Probe {
+    /// Returns the power at `tick`, or `None` if the probe didn't exist yet.
+    fn power_at(&self, tick: u64) -> Option<u8> {
+        let idx = self.changes.partition_point(|&(t, _)| t <= tick);
+        idx.checked_sub(1).map(|idx| self.changes[idx].1)
+    }
+}
+
+/// Returns the strongest signal the block at `pos` outputs or receives.
+pub fn read_power(plot: &Plot, pos: BlockPos) -> u8 {
+    let block = plot.get_block(pos);
+    BlockFace::values()
+        .iter()
+        .map(|&face| {
+            let neighbor_pos = pos.offset(face);
+            let received =
+                plot.get_block(neighbor_pos)
+                    .get_redstone_power(plot, neighbor_pos, face);
+            block.get_redstone_power(plot, pos, face).max(received)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(Default)]
+pub struct Probes {
+    probes: Vec<Probe>,
+    /// The number of ticks that have been recorded
+    tick: u64,
+}
+
+impl Probes {
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Probe> {
+        self.probes.iter()
+    }
+
+    /// Adds a probe and returns its name. The name is generated if none is given.
+    pub fn add(
+        &mut self,
+        plot: &Plot,
+        pos: BlockPos,
+        name: Option<&str>,
+    ) -> Result<String, String> {
+        if self.probes.iter().any(|probe| probe.pos == pos) {
+            return Err("There is already a probe at that position!".to_owned());
+        }
+        let name = match name {
+            Some(name) if self.probes.iter().any(|probe| probe.name == name) => {
+                return Err(format!("There is already a probe named {}!", name));
+            }
+            Some(name) => name.to_owned(),
+            None => (1..)
+                .map(|n| format!("p{}", n))
+                .find(|name| self.probes.iter().all(|probe| &probe.name != name))
+                .unwrap(),
+        };
+        self.probes.push(Probe {
+            pos,
+            name: name.clone(),
+            changes: vec![(self.tick, read_power(plot, pos))],
+        });
+        Ok(name)
+    }
+
+    /// Removes the probe at `pos` and returns its name.
+    pub fn remove_at(&mut self, pos: BlockPos) -> Option<String> {
+        let idx = self.probes.iter().position(|probe| probe.pos == pos)?;
+        Some(self.probes.remove(idx).name)
+    }
+
+    pub fn clear(&mut self) {
+        self.probes.clear();
+        self.tick = 0;
+    }
+
+    /// Records the power at every probe for the tick that just ran.
+    pub fn record(&mut self, plot: &Plot) {
+        self.tick += 1;
+        for probe in &mut self.probes {
+            let power = read_power(plot, probe.pos);
+            if probe.changes.last().map(|&(_, last)| last) != Some(power) {
+                probe.changes.push((self.tick, power));
+            }
+        }
+    }
+
+    /// Returns a table with a row for each of the last `ticks` ticks and a column for
+    /// each probe. Powers are written in hex so every column is one character wide.
+    pub fn waveform(&self, ticks: u64) -> Vec<String> {
+        let names: Vec<&str> = self
+            .probes
+            .iter()
+            .map(|probe| probe.name.as_str())
+            .collect();
+        let mut lines = vec![format!("tick | {}", names.join(" "))];
+        for tick in self.tick.saturating_sub(ticks - 1)..=self.tick {
+            let mut line = format!("{} |", tick);
+            for probe in &self.probes {
+                let power = match probe.power_at(tick) {
+                    Some(power) => format!("{:X}", power),
+                    None => "-".to_owned(),
+                };
+                let _ = write!(line, " {:>width$}", power, width = probe.name.len());
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Writes everything that was recorded as a Value Change Dump, which waveform
+    /// viewers such as GTKWave can open, and returns the path.
+    pub fn save_vcd(&self, name: &str) -> io::Result<String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = format!("./probes/{}-{}.vcd", name, timestamp);
+        fs::create_dir_all("./probes")?;
+        fs::write(&path, self.to_vcd(name))?;
+        Ok(path)
+    }
+
+    fn to_vcd(&self, name: &str) -> String {
+        let mut vcd = String::new();
+        let _ = writeln!(
+            vcd,
+            "$comment Recorded by MCHPRS. Each time step is one redstone tick. $end"
+        );
+        // A redstone tick takes 100ms at the vanilla 10 rtps
+        let _ = writeln!(vcd, "$timescale 100 ms $end");
+        let _ = writeln!(vcd, "$scope module {} $end", name.replace(',', "_"));
+        // Identifiers are made of printable characters starting at `!`
+        let ids: Vec<String> = (0..self.probes.len())
+            .map(|mut idx| {
+                let mut id = String::new();
+                loop {
+                    id.push((b'!' + (idx % 94) as u8) as char);
+                    idx /= 94;
+                    if idx == 0 {
+                        break id;
+                    }
+                }
+            })
+            .collect();
+        for (probe, id) in self.probes.iter().zip(&ids) {
+            let _ = writeln!(vcd, "$var wire 4 {} {} $end", id, probe.name);
+        }
+        let _ = writeln!(vcd, "$upscope $end");
+        let _ = writeln!(vcd, "$enddefinitions $end");
+
+        let mut changes: Vec<(u64, usize, u8)> = self
+            .probes
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, probe)| {
+                probe
+                    .changes
+                    .iter()
+                    .map(move |&(tick, power)| (tick, idx, power))
+            })
+            .collect();
+        changes.sort_unstable();
+        let mut last_tick = None;
+        for (tick, idx, power) in changes {
+            if last_tick != Some(tick) {
+                let _ = writeln!(vcd, "#{}", tick);
+                last_tick = Some(tick);
+            }
+            let _ = writeln!(vcd, "b{:b} {}", power, ids[idx]);
+        }
+        let _ = writeln!(vcd, "#{}", self.tick + 1);
+        vcd
+    }
+}
+
+#[test]
+fn probe_power_at_test() {
+    let probe = Probe {
+        pos: BlockPos::new(0, 0, 0),
+        name: "p1".to_owned(),
+        changes: vec![(3, 0), (5, 15), (8, 0)],
+    };
+    assert_eq!(probe.power_at(2), None);
+    assert_eq!(probe.power_at(4), Some(0));
+    assert_eq!(probe.power_at(5), Some(15));
+    assert_eq!(probe.power_at(7), Some(15));
+    assert_eq!(probe.power_at(100), Some(0));
+}
+
+#[test]
+fn probe_vcd_test() {
+    let probes = Probes {
+        probes: vec![
+            Probe {
+                pos: BlockPos::new(0, 0, 0),
+                name: "a".to_owned(),
+                changes: vec![(0, 0), (2, 15)],
+            },
+            Probe {
+                pos: BlockPos::new(1, 0, 0),
+                name: "b".to_owned(),
+                changes: vec![(1, 3), (2, 0)],
+            },
+        ],
+        tick: 4,
+    };
+    let vcd = probes.to_vcd("p0,0");
+    let expected = [
+        "$comment Recorded by MCHPRS. Each time step is one redstone tick. $end",
+        "$timescale 100 ms $end",
+        "$scope module p0_0 $end",
+        "$var wire 4 ! a $end",
+        "$var wire 4 \" b $end",
+        "$upscope $end",
+        "$enddefinitions $end",
+        "#0",
+        "b0 !",
+        "#1",
+        "b11 \"",
+        "#2",
+        "b1111 !",
+        "b0 \"",
+        "#5",
+    ];
+    assert_eq!(vcd.lines().collect::<Vec<_>>(), expected);
+}
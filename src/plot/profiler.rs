@@ -0,0 +1,186 @@
+//! Records what a plot spends its time on while `/rprofile` is running.
+
+use super::TickPriority;
+use crate::blocks::Block;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many of the most updated blocks are listed in the chat summary
+const SUMMARY_BLOCK_COUNT: usize = 5;
+/// How many tick times are kept for saving, which is 8 MiB worth. Plots running at a
+/// high rtps would otherwise use up memory quickly.
+const MAX_TICK_TIMES: usize = 1 << 20;
+
+#[derive(Debug, Serialize)]
+pub struct Profiler {
+    #[serde(skip)]
+    started: Instant,
+    /// How many ticks ran
+    ticks: u64,
+    /// How long all ticks took in microseconds
+    total_tick_time: u64,
+    max_tick_time: u64,
+    /// How long each of the last `MAX_TICK_TIMES` ticks took in microseconds
+    tick_times: VecDeque<u64>,
+    /// How many scheduled ticks ran for each priority, in the order of `TickPriority::values`
+    scheduled_ticks: [u64; 4],
+    /// How many times each kind of block got updated by a neighbor
+    block_updates: HashMap<&'static str, u64>,
+    packets_sent: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler {
+            started: Instant::now(),
+            ticks: 0,
+            total_tick_time: 0,
+            max_tick_time: 0,
+            tick_times: VecDeque::new(),
+            scheduled_ticks: [0; 4],
+            block_updates: HashMap::new(),
+            packets_sent: 0,
+        }
+    }
+}
+
+impl Profiler {
+    pub fn record_tick(&mut self, time: Duration) {
+        let time = time.as_micros() as u64;
+        self.ticks += 1;
+        self.total_tick_time += time;
+        self.max_tick_time = self.max_tick_time.max(time);
+        if self.tick_times.len() == MAX_TICK_TIMES {
+            self.tick_times.pop_front();
+        }
+        self.tick_times.push_back(time);
+    }
+
+    pub fn record_scheduled_tick(&mut self, priority: &TickPriority) {
+        let idx = TickPriority::values()
+            .iter()
+            .position(|p| p == priority)
+            .unwrap();
+        self.scheduled_ticks[idx] += 1;
+    }
+
+    pub fn record_block_update(&mut self, block: Block) {
+        let name = block.get_name().unwrap_or("unknown");
+        *self.block_updates.entry(name).or_default() += 1;
+    }
+
+    pub fn record_packets_sent(&mut self, count: u64) {
+        self.packets_sent += count;
+    }
+
+    /// Returns the lines that get sent in chat when profiling stops.
+    pub fn summary(&self) -> Vec<String> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let ticks = self.ticks;
+        let total = self.total_tick_time;
+        let max = self.max_tick_time;
+        let average = if ticks > 0 {
+            total as f64 / ticks as f64
+        } else {
+            0.0
+        };
+        let mut lines = vec![
+            format!(
+                "Profiled {} ticks over {:.1}s, {:.1}% of the time was spent ticking",
+                ticks,
+                elapsed,
+                total as f64 / 10_000.0 / elapsed.max(f64::EPSILON)
+            ),
+            format!(
+                "Tick time: {:.3}ms average, {:.3}ms max",
+                average / 1000.0,
+                max as f64 / 1000.0
+            ),
+        ];
+
+        let mut scheduled = String::from("Scheduled ticks:");
+        for (priority, count) in TickPriority::values().iter().zip(&self.scheduled_ticks) {
+            let _ = write!(scheduled, " {:?} {}", priority, count);
+        }
+        lines.push(scheduled);
+
+        let mut block_updates: Vec<(&str, u64)> =
+            self.block_updates.iter().map(|(k, v)| (*k, *v)).collect();
+        block_updates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let top: Vec<String> = block_updates
+            .iter()
+            .take(SUMMARY_BLOCK_COUNT)
+            .map(|(name, count)| format!("{} {}", name, count))
+            .collect();
+        if top.is_empty() {
+            lines.push("Block updates: none".to_owned());
+        } else {
+            lines.push(format!("Block updates: {}", top.join(", ")));
+        }
+
+        lines.push(format!(
+            "Packets sent: {} ({:.1}/s)",
+            self.packets_sent,
+            self.packets_sent as f64 / elapsed.max(f64::EPSILON)
+        ));
+        lines
+    }
+
+    /// Writes the profile into the `./profiles/` folder and returns the path. CSV files
+    /// contain the time of each tick while JSON files contain everything.
+    pub fn save(&self, name: &str, format: &str) -> io::Result<String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = format!("./profiles/{}-{}.{}", name, timestamp, format);
+        let data = if format == "csv" {
+            let mut csv = String::from("tick,time_us\n");
+            let first_tick = self.ticks - self.tick_times.len() as u64;
+            for (tick, time) in (first_tick..).zip(&self.tick_times) {
+                let _ = writeln!(csv, "{},{}", tick, time);
+            }
+            csv
+        } else {
+            serde_json::to_string_pretty(self).unwrap()
+        };
+        fs::create_dir_all("./profiles")?;
+        fs::write(&path, data)?;
+        Ok(path)
+    }
+}
+
+#[test]
+fn profiler_test() {
+    let mut profiler = Profiler::default();
+    for time in &[3, 1, 2] {
+        profiler.record_tick(Duration::from_millis(*time));
+    }
+    profiler.record_scheduled_tick(&TickPriority::Normal);
+    profiler.record_block_update(Block::from_name("lever").unwrap());
+    profiler.record_block_update(Block::from_name("lever").unwrap());
+    profiler.record_packets_sent(4);
+
+    let summary = profiler.summary();
+    assert!(summary[0].starts_with("Profiled 3 ticks"));
+    assert_eq!(summary[1], "Tick time: 2.000ms average, 3.000ms max");
+    assert_eq!(
+        summary[2],
+        "Scheduled ticks: Highest 0 Higher 0 High 0 Normal 1"
+    );
+    assert_eq!(summary[3], "Block updates: lever 2");
+    assert!(summary[4].starts_with("Packets sent: 4 "));
+
+    // Only the last tick times are kept, but the summary still covers every tick
+    for _ in 0..MAX_TICK_TIMES {
+        profiler.record_tick(Duration::from_micros(1));
+    }
+    assert_eq!(profiler.tick_times.len(), MAX_TICK_TIMES);
+    assert_eq!(profiler.tick_times.front(), Some(&1));
+    assert!(profiler.summary()[0].starts_with(&format!("Profiled {} ticks", MAX_TICK_TIMES + 3)));
+    assert!(profiler.summary()[1].ends_with("3.000ms max"));
+}
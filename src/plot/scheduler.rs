@@ -0,0 +1,173 @@
+//! The ticks that blocks in the plot scheduled for themselves. Ticks are kept in a
+//! bucket for the tick they run in, so scheduling one and checking if a block has
+//! one pending doesn't depend on how many others are waiting.
+
+use super::{TickEntry, TickPriority};
+use crate::blocks::BlockPos;
+use std::collections::{HashMap, VecDeque};
+
+/// The positions to tick in one tick, with a queue for each priority
+type Bucket = [VecDeque<BlockPos>; 4];
+
+#[derive(Default)]
+pub struct TickScheduler {
+    /// The number of ticks that were started
+    current_tick: u64,
+    /// Set while the ticks of the current tick are being run
+    running: bool,
+    buckets: HashMap<u64, Bucket>,
+    /// How many ticks are pending at each position
+    pending: HashMap<BlockPos, u32>,
+}
+
+impl TickScheduler {
+    /// Creates a scheduler from ticks that were saved with `entries`.
+    pub fn from_entries(entries: Vec<TickEntry>) -> TickScheduler {
+        let mut scheduler = TickScheduler::default();
+        for entry in entries {
+            scheduler.schedule(entry.pos, entry.ticks_left, entry.tick_priority);
+        }
+        scheduler
+    }
+
+    /// Schedules a tick at `pos` in `delay` ticks. Ticks scheduled with no delay
+    /// while ticks are running still run in the current tick after the others with
+    /// the same priority, which is how pistons handle block events.
+    pub fn schedule(&mut self, pos: BlockPos, delay: u32, priority: TickPriority) {
+        let delay = if self.running { delay } else { delay.max(1) };
+        let bucket = self
+            .buckets
+            .entry(self.current_tick + delay as u64)
+            .or_default();
+        bucket[priority as usize].push_back(pos);
+        *self.pending.entry(pos).or_default() += 1;
+    }
+
+    pub fn is_pending(&self, pos: BlockPos) -> bool {
+        self.pending.contains_key(&pos)
+    }
+
+    /// Cancels every tick scheduled at `pos`.
+    pub fn cancel(&mut self, pos: BlockPos) {
+        if self.pending.remove(&pos).is_none() {
+            return;
+        }
+        for bucket in self.buckets.values_mut() {
+            for queue in bucket.iter_mut() {
+                queue.retain(|&p| p != pos);
+            }
+        }
+        self.buckets
+            .retain(|_, bucket| bucket.iter().any(|queue| !queue.is_empty()));
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.pending.clear();
+    }
+
+    /// Starts the next tick. `pop` has to be called until it returns `None` afterwards.
+    pub fn next_tick(&mut self) {
+        self.current_tick += 1;
+        self.running = true;
+    }
+
+    /// Takes the next tick that should run in the current tick.
+    pub fn pop(&mut self) -> Option<TickEntry> {
+        let bucket = match self.buckets.get_mut(&self.current_tick) {
+            Some(bucket) => bucket,
+            None => {
+                self.running = false;
+                return None;
+            }
+        };
+        let (priority, pos) = TickPriority::values()
+            .iter()
+            .zip(bucket.iter_mut())
+            .find_map(|(priority, queue)| Some((priority.clone(), queue.pop_front()?)))
+            .unwrap();
+        if bucket.iter().all(VecDeque::is_empty) {
+            self.buckets.remove(&self.current_tick);
+        }
+        if let Some(count) = self.pending.get_mut(&pos) {
+            *count -= 1;
+            if *count == 0 {
+                self.pending.remove(&pos);
+            }
+        }
+        Some(TickEntry {
+            ticks_left: 0,
+            tick_priority: priority,
+            pos,
+        })
+    }
+
+    /// Returns the delay and priority of every tick scheduled at `pos`.
+    pub fn pending_at(&self, pos: BlockPos) -> Vec<(u32, TickPriority)> {
+        if !self.is_pending(pos) {
+            return Vec::new();
+        }
+        self.entries()
+            .into_iter()
+            .filter(|entry| entry.pos == pos)
+            .map(|entry| (entry.ticks_left, entry.tick_priority))
+            .collect()
+    }
+
+    /// Returns all pending ticks in the order they will run.
+    pub fn entries(&self) -> Vec<TickEntry> {
+        let mut ticks: Vec<&u64> = self.buckets.keys().collect();
+        ticks.sort_unstable();
+        let mut entries = Vec::new();
+        for tick in ticks {
+            let ticks_left = (tick - self.current_tick) as u32;
+            for (priority, queue) in TickPriority::values().iter().zip(&self.buckets[tick]) {
+                entries.extend(queue.iter().map(|&pos| TickEntry {
+                    ticks_left,
+                    tick_priority: priority.clone(),
+                    pos,
+                }));
+            }
+        }
+        entries
+    }
+
+    /// Keeps only the ticks for which `f` returns true. `f` is called in the order
+    /// the ticks would run.
+    pub fn retain<F: FnMut(&TickEntry) -> bool>(&mut self, mut f: F) {
+        let entries = self.entries();
+        self.clear();
+        for entry in entries {
+            if f(&entry) {
+                self.schedule(entry.pos, entry.ticks_left, entry.tick_priority);
+            }
+        }
+    }
+}
+
+#[test]
+fn tick_scheduler_test() {
+    let a = BlockPos::new(0, 0, 0);
+    let b = BlockPos::new(1, 0, 0);
+    let c = BlockPos::new(2, 0, 0);
+    let mut scheduler = TickScheduler::default();
+    scheduler.schedule(a, 2, TickPriority::Normal);
+    scheduler.schedule(b, 2, TickPriority::Highest);
+    // Ticks with no delay wait for the next tick when nothing is running
+    scheduler.schedule(c, 0, TickPriority::Normal);
+    assert!(scheduler.is_pending(a));
+    assert_eq!(scheduler.pending_at(c), vec![(1, TickPriority::Normal)]);
+
+    scheduler.next_tick();
+    assert_eq!(scheduler.pop().map(|e| e.pos), Some(c));
+    assert!(scheduler.pop().is_none());
+    assert!(!scheduler.is_pending(c));
+
+    scheduler.next_tick();
+    assert_eq!(scheduler.pop().map(|e| e.pos), Some(b));
+    // Ticks with no delay scheduled while running go after the others
+    scheduler.schedule(c, 0, TickPriority::Normal);
+    assert_eq!(scheduler.pop().map(|e| e.pos), Some(a));
+    assert_eq!(scheduler.pop().map(|e| e.pos), Some(c));
+    assert!(scheduler.pop().is_none());
+}
@@ -1,17 +1,104 @@
 use super::TickEntry;
-use crate::blocks::{BlockEntity, BlockPos};
+use crate::blocks::{Block, BlockEntity, BlockPos};
+use crate::config::{GeneratorConfig, CONFIG};
 use crate::network::packets::clientbound::{C22ChunkData, C22ChunkDataSection, ClientBoundPacket};
 use crate::network::packets::PacketEncoder;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::mem;
 
+/// The generator config with the block names looked up
+struct Generator {
+    plot_size: i32,
+    road_width: i32,
+    road_block: u32,
+    border_block: u32,
+    wall_block: Option<u32>,
+    floor_height: u32,
+    floor_layers: Vec<u32>,
+}
+
+impl Generator {
+    fn new(config: &GeneratorConfig) -> Generator {
+        // The block names were already checked when the config was loaded
+        let id = |name: &str| Block::from_name(name).unwrap().get_id();
+        Generator {
+            plot_size: config.plot_size,
+            road_width: config.road_width,
+            road_block: id(&config.road_block),
+            border_block: id(&config.border_block),
+            wall_block: config.wall_block.as_deref().map(id),
+            floor_height: config.floor_height,
+            floor_layers: config.floor_layers.iter().map(|name| id(name)).collect(),
+        }
+    }
+
+    /// Returns the blocks of a column from the bottom up.
+    fn column(&self, block_x: i32, block_z: i32) -> Vec<u32> {
+        let local_x = block_x.rem_euclid(self.plot_size);
+        let local_z = block_z.rem_euclid(self.plot_size);
+        let edge_distance = local_x
+            .min(local_z)
+            .min(self.plot_size - 1 - local_x)
+            .min(self.plot_size - 1 - local_z);
+        let height = self.floor_height as usize;
+        if edge_distance < self.road_width {
+            vec![self.road_block; height]
+        } else if edge_distance == self.road_width {
+            let mut column = vec![self.border_block; height];
+            column.extend(self.wall_block);
+            column
+        } else {
+            (0..height)
+                .rev()
+                .map(|depth| self.floor_layers[depth.min(self.floor_layers.len() - 1)])
+                .collect()
+        }
+    }
+}
+
+lazy_static! {
+    static ref GENERATOR: Generator = Generator::new(&CONFIG.generator);
+}
+
+/// The version of the plot format written by `Plot::save`. Plots saved before the
+/// version was recorded are version 0.
+pub const PLOT_FORMAT_VERSION: u32 = 1;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlotData {
     pub tps: u32,
     pub show_redstone: bool,
     pub chunk_data: Vec<ChunkData>,
     pub pending_ticks: Vec<TickEntry>,
+    pub version: u32,
+}
+
+/// The layout of plots saved before `PlotData` had a version
+#[derive(Deserialize)]
+struct LegacyPlotData {
+    tps: u32,
+    show_redstone: bool,
+    chunk_data: Vec<ChunkData>,
+    pending_ticks: Vec<TickEntry>,
+}
+
+impl PlotData {
+    pub fn deserialize(data: &[u8]) -> bincode::Result<PlotData> {
+        match bincode::deserialize::<PlotData>(data) {
+            Ok(plot_data) if plot_data.version == PLOT_FORMAT_VERSION => Ok(plot_data),
+            _ => {
+                let legacy: LegacyPlotData = bincode::deserialize(data)?;
+                Ok(PlotData {
+                    tps: legacy.tps,
+                    show_redstone: legacy.show_redstone,
+                    chunk_data: legacy.chunk_data,
+                    pending_ticks: legacy.pending_ticks,
+                    version: 0,
+                })
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -160,6 +247,11 @@ impl PalettedBitBuffer {
     pub fn entries(&self) -> usize {
         self.data.entries
     }
+
+    /// The approximate amount of memory used by the buffer in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        self.data.longs.len() * 8 + self.palette.len() * 4
+    }
 }
 
 #[derive(Debug)]
@@ -375,8 +467,9 @@ impl Chunk {
         }
     }
 
-    pub fn load(x: i32, z: i32, chunk_data: ChunkData) -> Chunk {
-        Chunk {
+    /// Loads a chunk saved in a plot with the format `version`.
+    pub fn load(x: i32, z: i32, chunk_data: ChunkData, version: u32) -> Chunk {
+        let mut chunk = Chunk {
             x,
             z,
             sections: chunk_data
@@ -384,7 +477,42 @@ impl Chunk {
                 .into_iter()
                 .map(|(y, cs)| (y, ChunkSection::load(cs)))
                 .collect(),
-            block_entities: chunk_data.block_entities,
+            block_entities: HashMap::new(),
+        };
+        for (pos, block_entity) in chunk_data.block_entities {
+            let block = Block::from_block_state(chunk.get_block(pos.x as u32, pos.y, pos.z as u32));
+            chunk
+                .block_entities
+                .insert(pos, block_entity.upgrade(block));
+        }
+        if version == 0 {
+            chunk.upgrade_legacy_hoppers();
+        }
+        chunk
+    }
+
+    /// Hoppers used to be plain containers which were always saved as a disabled
+    /// hopper facing north. They are made inert so they don't start pushing items
+    /// north on the next block update.
+    fn upgrade_legacy_hoppers(&mut self) {
+        const LEGACY_HOPPER: u32 = 6198;
+        let mut hoppers = Vec::new();
+        for (&section_y, section) in &self.sections {
+            for y in 0..16 {
+                for z in 0..16 {
+                    for x in 0..16 {
+                        if section.get_block(x, y, z) == LEGACY_HOPPER {
+                            let y = section_y as u32 * 16 + y;
+                            hoppers.push(BlockPos::new(x as i32, y, z as i32));
+                        }
+                    }
+                }
+            }
+        }
+        for pos in hoppers {
+            let block_entity = self.block_entities.remove(&pos);
+            self.block_entities
+                .insert(pos, BlockEntity::legacy_hopper(block_entity));
         }
     }
 
@@ -397,7 +525,8 @@ impl Chunk {
         }
     }
 
-    pub fn generate(layers: i32, x: i32, z: i32) -> Chunk {
+    /// Generates a chunk using the generator from the config.
+    pub fn generate(x: i32, z: i32) -> Chunk {
         let mut chunk = Chunk {
             sections: BTreeMap::new(),
             x,
@@ -405,21 +534,11 @@ impl Chunk {
             block_entities: HashMap::new(),
         };
 
-        for ry in 0..layers {
-            for rx in 0..16 {
-                for rz in 0..16 {
-                    let block_x = (x << 4) | rx;
-                    let block_z = (z << 4) | rz;
-
-                    if block_x % 256 == 0
-                        || block_z % 256 == 0
-                        || (block_x + 1) % 256 == 0
-                        || (block_z + 1) % 256 == 0
-                    {
-                        chunk.set_block(rx as u32, ry as u32, rz as u32, 4481); // Stone Bricks
-                    } else {
-                        chunk.set_block(rx as u32, ry as u32, rz as u32, 245); // Sandstone
-                    }
+        for rx in 0..16 {
+            for rz in 0..16 {
+                let column = GENERATOR.column((x << 4) | rx, (z << 4) | rz);
+                for (ry, block) in column.into_iter().enumerate() {
+                    chunk.set_block(rx as u32, ry as u32, rz as u32, block);
                 }
             }
         }
@@ -440,3 +559,18 @@ pub struct ChunkData {
     sections: BTreeMap<u8, ChunkSectionData>,
     block_entities: HashMap<BlockPos, BlockEntity>,
 }
+
+#[test]
+fn generator_column_test() {
+    let generator = Generator::new(&GeneratorConfig {
+        plot_size: 128,
+        road_width: 2,
+        wall_block: Some("stone".to_string()),
+        floor_height: 3,
+        floor_layers: vec!["sandstone".to_string(), "stone_bricks".to_string()],
+        ..Default::default()
+    });
+    assert_eq!(generator.column(129, 64), vec![1, 1, 1]);
+    assert_eq!(generator.column(130, 64), vec![4481, 4481, 4481, 1]);
+    assert_eq!(generator.column(-64, -64), vec![4481, 4481, 245]);
+}
@@ -0,0 +1,532 @@
+//! Containers open in a window where players can move items between the container
+//! and their inventory. The container's block entity is updated after every click,
+//! so comparators reading it change right away.
+
+use super::Plot;
+use crate::blocks::{self, BlockPos, ContainerType, InventoryEntry};
+use crate::items::{Item, ItemStack};
+use crate::network::packets::clientbound::*;
+use crate::network::packets::serverbound::{S09ClickWindow, S0ACloseWindow};
+use crate::network::packets::SlotData;
+use serde_json::json;
+use std::io::Cursor;
+
+/// The slot that clicks outside of the window are sent with
+const OUTSIDE_WINDOW: i16 = -999;
+
+pub struct ContainerWindow {
+    pub id: u8,
+    pub pos: BlockPos,
+    ty: ContainerType,
+    /// The item the player is holding with their cursor
+    cursor: Option<ItemStack>,
+    /// The slots the cursor was dragged over so far
+    drag_slots: Vec<usize>,
+}
+
+/// Returns the window type and the translation key of the title for a container.
+fn window_type(ty: ContainerType) -> (i32, &'static str) {
+    match ty {
+        ContainerType::Furnace => (13, "container.furnace"),
+        ContainerType::Barrel => (2, "container.barrel"),
+        ContainerType::Hopper => (15, "container.hopper"),
+        ContainerType::Dropper => (6, "container.dropper"),
+    }
+}
+
+fn to_item_stack(entry: &InventoryEntry) -> ItemStack {
+    let nbt = entry
+        .nbt
+        .as_ref()
+        .and_then(|data| nbt::Blob::from_reader(&mut Cursor::new(data)).ok());
+    ItemStack {
+        item_type: Item::from_id(entry.id),
+        count: entry.count,
+        damage: 0,
+        nbt,
+    }
+}
+
+fn to_inventory_entry(slot: usize, item: &ItemStack) -> InventoryEntry {
+    let nbt = item.nbt.as_ref().map(|blob| {
+        let mut data = Vec::new();
+        blob.to_writer(&mut data).unwrap();
+        data
+    });
+    InventoryEntry {
+        id: item.item_type.get_id(),
+        slot: slot as u8,
+        count: item.count,
+        nbt,
+    }
+}
+
+fn to_slot_data(item: &Option<ItemStack>) -> Option<SlotData> {
+    item.as_ref().map(|item| SlotData {
+        item_count: item.count as i8,
+        item_id: item.item_type.get_id() as i32,
+        nbt: item.nbt.clone(),
+    })
+}
+
+fn stacks_with(a: &ItemStack, b: &ItemStack) -> bool {
+    a.item_type == b.item_type && a.nbt == b.nbt
+}
+
+fn max_count(item: &ItemStack) -> u8 {
+    item.item_type.max_stack_size() as u8
+}
+
+/// Takes up to `count` items out of `slot`.
+fn take(slot: &mut Option<ItemStack>, count: u8) -> Option<ItemStack> {
+    let item = slot.as_mut()?;
+    let count = count.min(item.count);
+    item.count -= count;
+    let taken = ItemStack {
+        count,
+        ..item.clone()
+    };
+    if item.count == 0 {
+        *slot = None;
+    }
+    Some(taken)
+}
+
+/// Puts as much of `item` into `slot` as fits and returns the rest.
+fn insert(slot: &mut Option<ItemStack>, mut item: ItemStack) -> Option<ItemStack> {
+    match slot {
+        None => {
+            *slot = Some(item);
+            None
+        }
+        Some(existing) if stacks_with(existing, &item) => {
+            let moved = item
+                .count
+                .min(max_count(existing).saturating_sub(existing.count));
+            existing.count += moved;
+            item.count -= moved;
+            if item.count == 0 {
+                None
+            } else {
+                Some(item)
+            }
+        }
+        Some(_) => Some(item),
+    }
+}
+
+/// The items in a window, which are the container's slots followed by the player's
+/// main inventory and hotbar, along with the item on the cursor.
+struct WindowSlots {
+    slots: Vec<Option<ItemStack>>,
+    container_slots: usize,
+    cursor: Option<ItemStack>,
+}
+
+impl WindowSlots {
+    /// Moves the items around the way the client does for a click on `slot`. Clicks
+    /// that don't make sense leave everything as it was.
+    fn click(&mut self, slot: i16, button: i8, mode: i32, drag_slots: &mut Vec<usize>) {
+        let idx = if slot >= 0 && (slot as usize) < self.slots.len() {
+            Some(slot as usize)
+        } else {
+            None
+        };
+        match (mode, idx) {
+            (0, None) if slot == OUTSIDE_WINDOW => {
+                if button == 0 {
+                    self.cursor = None;
+                } else {
+                    take(&mut self.cursor, 1);
+                }
+            }
+            (0, Some(idx)) => self.click_slot(idx, button == 1),
+            (1, Some(idx)) => self.quick_move(idx),
+            // Number keys swap the slot with a slot in the hotbar
+            (2, Some(idx)) if (0..9).contains(&button) => {
+                let hotbar_slot = self.slots.len() - 9 + button as usize;
+                self.slots.swap(idx, hotbar_slot);
+            }
+            // Middle clicking picks up a full stack in creative mode
+            (3, Some(idx)) => {
+                if let (Some(item), None) = (&self.slots[idx], &self.cursor) {
+                    self.cursor = Some(ItemStack {
+                        count: max_count(item),
+                        ..item.clone()
+                    });
+                }
+            }
+            (4, Some(idx)) => {
+                if button == 0 {
+                    take(&mut self.slots[idx], 1);
+                } else {
+                    self.slots[idx] = None;
+                }
+            }
+            (5, _) => self.drag(idx, button, drag_slots),
+            (6, _) => self.collect_to_cursor(),
+            _ => {}
+        }
+    }
+
+    /// Left clicks pick up or put down the whole stack, right clicks half of the stack
+    /// in the slot or one item from the cursor.
+    fn click_slot(&mut self, idx: usize, right: bool) {
+        let slot = &mut self.slots[idx];
+        match (slot.as_ref(), self.cursor.take()) {
+            (None, None) => {}
+            (Some(item), None) => {
+                let count = if right {
+                    item.count.div_ceil(2)
+                } else {
+                    item.count
+                };
+                self.cursor = take(slot, count);
+            }
+            (Some(item), Some(held)) if !stacks_with(item, &held) => {
+                self.cursor = slot.replace(held);
+            }
+            (_, Some(mut held)) if right => {
+                let one = ItemStack {
+                    count: 1,
+                    ..held.clone()
+                };
+                if insert(slot, one).is_none() {
+                    held.count -= 1;
+                }
+                if held.count > 0 {
+                    self.cursor = Some(held);
+                }
+            }
+            (_, Some(held)) => self.cursor = insert(slot, held),
+        }
+    }
+
+    /// Moves the stack in `idx` from the container to the player's inventory or the
+    /// other way around. Stacks of the same item are filled up before empty slots.
+    fn quick_move(&mut self, idx: usize) {
+        let mut item = self.slots[idx].take();
+        let targets: Vec<usize> = if idx < self.container_slots {
+            // Like in vanilla, the hotbar is filled up first
+            (self.container_slots..self.slots.len()).rev().collect()
+        } else {
+            (0..self.container_slots).collect()
+        };
+        for &fill_empty in &[false, true] {
+            for &target in &targets {
+                if self.slots[target].is_none() != fill_empty {
+                    continue;
+                }
+                if let Some(rest) = item.take() {
+                    item = insert(&mut self.slots[target], rest);
+                }
+            }
+        }
+        self.slots[idx] = item;
+    }
+
+    /// Spreads the cursor's stack over the slots it was dragged across. The button
+    /// tells if the drag is starting, going over a slot or done, and whether the stack
+    /// is split evenly, one item goes in each slot or each slot gets a full stack.
+    fn drag(&mut self, idx: Option<usize>, button: i8, drag_slots: &mut Vec<usize>) {
+        match button % 4 {
+            0 => drag_slots.clear(),
+            1 => {
+                if let Some(idx) = idx {
+                    if !drag_slots.contains(&idx) {
+                        drag_slots.push(idx);
+                    }
+                }
+            }
+            2 => {
+                let mut held = match self.cursor.take() {
+                    Some(held) => held,
+                    None => return drag_slots.clear(),
+                };
+                let slots: Vec<usize> = drag_slots
+                    .drain(..)
+                    .filter(|&idx| {
+                        self.slots[idx]
+                            .as_ref()
+                            .is_none_or(|item| stacks_with(item, &held))
+                    })
+                    .collect();
+                let creative = button / 4 == 2;
+                let per_slot = match button / 4 {
+                    0 => (held.count as usize / slots.len().max(1)) as u8,
+                    1 => 1,
+                    _ => max_count(&held),
+                };
+                for idx in slots {
+                    let count = if creative {
+                        per_slot
+                    } else {
+                        per_slot.min(held.count)
+                    };
+                    if count == 0 {
+                        break;
+                    }
+                    let leftover = insert(
+                        &mut self.slots[idx],
+                        ItemStack {
+                            count,
+                            ..held.clone()
+                        },
+                    );
+                    if !creative {
+                        held.count -= count - leftover.map_or(0, |item| item.count);
+                    }
+                }
+                if held.count > 0 {
+                    self.cursor = Some(held);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Double clicking fills up the cursor's stack with the same item from the other
+    /// slots, taking from stacks that aren't full first.
+    fn collect_to_cursor(&mut self) {
+        let held = match &mut self.cursor {
+            Some(held) => held,
+            None => return,
+        };
+        let max = max_count(held);
+        for &take_full in &[false, true] {
+            for slot in self.slots.iter_mut() {
+                if held.count >= max {
+                    return;
+                }
+                let matches = slot.as_ref().is_some_and(|item| {
+                    stacks_with(item, held) && (item.count >= max_count(item)) == take_full
+                });
+                if matches {
+                    if let Some(taken) = take(slot, max - held.count) {
+                        held.count += taken.count;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Plot {
+    /// Opens the window of the container at `pos` for `player`.
+    pub(super) fn open_container(&mut self, player: usize, pos: BlockPos) {
+        let ty = match blocks::get_inventory(self, pos) {
+            Some((ty, _)) => ty,
+            None => return,
+        };
+        let (window_type, title) = window_type(ty);
+        let id = self.players[player].last_window_id % 100 + 1;
+        let open_window = C2FOpenWindow {
+            window_id: id as i32,
+            window_type,
+            window_title: json!({ "translate": title }).to_string(),
+        }
+        .encode();
+        self.players[player].client.send_packet(&open_window);
+        self.players[player].last_window_id = id;
+        self.players[player].open_window = Some(ContainerWindow {
+            id,
+            pos,
+            ty,
+            cursor: None,
+            drag_slots: Vec::new(),
+        });
+        self.send_window_items(player);
+    }
+
+    /// Closes the window `player` has open. The item on their cursor is lost.
+    pub(super) fn close_container(&mut self, player: usize) {
+        if let Some(window) = self.players[player].open_window.take() {
+            let close_window = C14CloseWindow {
+                window_id: window.id,
+            }
+            .encode();
+            self.players[player].client.send_packet(&close_window);
+        }
+    }
+
+    /// Returns the items in the window `player` has open, or `None` if the container
+    /// isn't there anymore.
+    fn window_slots(&self, player: usize) -> Option<WindowSlots> {
+        let window = self.players[player].open_window.as_ref()?;
+        let (ty, inventory) = blocks::get_inventory(self, window.pos)?;
+        if ty != window.ty {
+            return None;
+        }
+        let container_slots = ty.num_slots() as usize;
+        let mut slots = vec![None; container_slots];
+        for entry in &inventory {
+            if let Some(slot) = slots.get_mut(entry.slot as usize) {
+                *slot = Some(to_item_stack(entry));
+            }
+        }
+        slots.extend(self.players[player].inventory[9..45].iter().cloned());
+        Some(WindowSlots {
+            slots,
+            container_slots,
+            cursor: window.cursor.clone(),
+        })
+    }
+
+    fn send_window_items(&mut self, player: usize) {
+        let slots = match self.window_slots(player) {
+            Some(slots) => slots,
+            None => return self.close_container(player),
+        };
+        let window_id = match &self.players[player].open_window {
+            Some(window) => window.id,
+            None => return,
+        };
+        let window_items = C15WindowItems {
+            window_id,
+            slot_data: slots.slots.iter().map(to_slot_data).collect(),
+        }
+        .encode();
+        // The cursor is set using the window id and slot -1
+        let set_cursor = C17SetSlot {
+            window_id: -1,
+            slot: -1,
+            slot_data: to_slot_data(&slots.cursor),
+        }
+        .encode();
+        let client = &mut self.players[player].client;
+        client.send_packet(&window_items);
+        client.send_packet(&set_cursor);
+    }
+
+    /// Sends the items in the inventory of `player` to them.
+    fn send_player_inventory(&mut self, player: usize) {
+        let window_items = C15WindowItems {
+            window_id: 0,
+            slot_data: self.players[player]
+                .inventory
+                .iter()
+                .map(to_slot_data)
+                .collect(),
+        }
+        .encode();
+        self.players[player].client.send_packet(&window_items);
+    }
+
+    /// Sends the items of the container at `pos` to the players looking into it.
+    /// Windows of containers that aren't there anymore are closed.
+    pub fn update_container_windows(&mut self, pos: BlockPos) {
+        for player in 0..self.players.len() {
+            if matches!(&self.players[player].open_window, Some(window) if window.pos == pos) {
+                self.send_window_items(player);
+            }
+        }
+    }
+
+    pub(super) fn handle_click_window(&mut self, player: usize, click_window: S09ClickWindow) {
+        match &self.players[player].open_window {
+            Some(window) if window.id == click_window.window_id => {}
+            _ => return,
+        }
+        // Players can lose their permission to build while the window is open
+        if !self.check_can_build(player) {
+            self.close_container(player);
+            // The client already moved the items on its side
+            self.send_player_inventory(player);
+            return;
+        }
+        let mut slots = match self.window_slots(player) {
+            Some(slots) => slots,
+            None => return self.close_container(player),
+        };
+        let window = self.players[player].open_window.as_mut().unwrap();
+        slots.click(
+            click_window.slot,
+            click_window.button,
+            click_window.mode,
+            &mut window.drag_slots,
+        );
+        window.cursor = slots.cursor.take();
+        let (pos, ty) = (window.pos, window.ty);
+
+        let confirmation = C13WindowConfirmation {
+            window_id: click_window.window_id as i8,
+            action_number: click_window.action_number,
+            accepted: true,
+        }
+        .encode();
+        self.players[player].client.send_packet(&confirmation);
+
+        let mut slots = slots.slots;
+        for (slot, item) in slots.drain(ty.num_slots() as usize..).enumerate() {
+            self.players[player].inventory[slot + 9] = item;
+        }
+        let inventory: Vec<InventoryEntry> = slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, item)| Some(to_inventory_entry(slot, item.as_ref()?)))
+            .collect();
+        let mut old_inventory = blocks::get_inventory(self, pos)
+            .map(|(_, inventory)| inventory)
+            .unwrap_or_default();
+        old_inventory.sort_by_key(|entry| entry.slot);
+        if inventory != old_inventory {
+            // This also sends the new items to everyone looking into the container
+            blocks::set_inventory(self, pos, ty, inventory);
+        } else {
+            self.send_window_items(player);
+        }
+    }
+
+    pub(super) fn handle_close_window(&mut self, player: usize, close_window: S0ACloseWindow) {
+        let window_id = close_window.window_id;
+        let open_window = &mut self.players[player].open_window;
+        if matches!(open_window, Some(window) if window.id == window_id) {
+            *open_window = None;
+        }
+    }
+}
+
+#[test]
+fn window_click_test() {
+    let redstone = ItemStack {
+        item_type: Item::BlockItem(600),
+        count: 64,
+        damage: 0,
+        nbt: None,
+    };
+    let mut slots = vec![None; 5 + 36];
+    slots[5] = Some(redstone);
+    let mut window = WindowSlots {
+        slots,
+        container_slots: 5,
+        cursor: None,
+    };
+    let mut drag_slots = Vec::new();
+    let count =
+        |window: &WindowSlots, idx: usize| window.slots[idx].as_ref().map(|item| item.count);
+
+    // Right clicking takes half of the stack and puts one item down
+    window.click(5, 1, 0, &mut drag_slots);
+    assert_eq!(window.cursor.as_ref().map(|item| item.count), Some(32));
+    window.click(0, 1, 0, &mut drag_slots);
+    assert_eq!(count(&window, 0), Some(1));
+
+    // Dragging with the left button splits the cursor evenly
+    window.click(OUTSIDE_WINDOW, 0, 5, &mut drag_slots);
+    window.click(1, 1, 5, &mut drag_slots);
+    window.click(2, 1, 5, &mut drag_slots);
+    window.click(OUTSIDE_WINDOW, 2, 5, &mut drag_slots);
+    assert_eq!((count(&window, 1), count(&window, 2)), (Some(15), Some(15)));
+    assert_eq!(window.cursor.as_ref().map(|item| item.count), Some(1));
+
+    window.click(0, 0, 0, &mut drag_slots);
+    assert!(window.cursor.is_none());
+
+    // Shift clicking moves stacks between the container and the inventory, filling up
+    // stacks of the same item first
+    window.click(1, 0, 1, &mut drag_slots);
+    assert_eq!((count(&window, 1), count(&window, 5)), (None, Some(47)));
+    window.click(5, 0, 1, &mut drag_slots);
+    assert_eq!(count(&window, 0), Some(49));
+    assert_eq!((count(&window, 2), count(&window, 5)), (Some(15), None));
+}
@@ -1,24 +1,17 @@
 use super::storage::PalettedBitBuffer;
-use super::Plot;
+use super::{plot_size, Plot};
 use crate::blocks::{Block, BlockEntity, BlockPos};
+use crate::config::CONFIG;
 use crate::network::packets::clientbound::*;
 use rand::Rng;
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs::File;
+use std::io;
 use std::ops::RangeInclusive;
+use std::path::Path;
 use std::time::Instant;
 
-// TODO: Actually use the multiblock change record.
-// Right now I'm just resending the whole chunk no
-// matter how big or small the operation is.
-pub struct MultiBlockChangeRecord {
-    pub x: i32,
-    pub y: i32,
-    pub z: i32,
-    pub block_id: u32,
-}
-
 pub struct WorldEditPatternPart {
     pub weight: f32,
     pub block_id: u32,
@@ -39,7 +32,11 @@ pub struct WorldEditClipboard {
 impl WorldEditClipboard {
     fn load_from_schematic(file_name: &str) -> Option<WorldEditClipboard> {
         // I greaty dislike this
-        let mut file = match File::open("./schems/".to_owned() + file_name + ".schem") {
+        WorldEditClipboard::load_from_schematic_file("./schems/".to_owned() + file_name + ".schem")
+    }
+
+    pub fn load_from_schematic_file<P: AsRef<Path>>(path: P) -> Option<WorldEditClipboard> {
+        let mut file = match File::open(path) {
             Ok(file) => file,
             Err(_) => return None,
         };
@@ -123,6 +120,110 @@ impl WorldEditClipboard {
             block_entities: parsed_block_entities,
         })
     }
+
+    fn save_to_schematic(&self, file_name: &str) -> nbt::Result<()> {
+        std::fs::create_dir_all("./schems")?;
+        self.save_to_schematic_file("./schems/".to_owned() + file_name + ".schem")
+    }
+
+    /// Saves the clipboard as a version 2 Sponge schematic, which can be read back
+    /// with `load_from_schematic_file`.
+    pub fn save_to_schematic_file<P: AsRef<Path>>(&self, path: P) -> nbt::Result<()> {
+        use nbt::Value;
+        let mut palette: HashMap<u32, i32> = HashMap::new();
+        let mut nbt_palette: HashMap<String, Value> = HashMap::new();
+        let mut blocks: Vec<i8> = Vec::new();
+        for i in 0..(self.size_x * self.size_y * self.size_z) as usize {
+            let block_id = self.data.get_entry(i);
+            let palette_id = match palette.get(&block_id) {
+                Some(&palette_id) => palette_id,
+                None => {
+                    // Saving an unknown block as something else would lose it silently
+                    let state = Block::from_block_state(block_id)
+                        .get_state_string()
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("the block with state id {} has no known name", block_id),
+                            )
+                        })?;
+                    let palette_id = palette.len() as i32;
+                    palette.insert(block_id, palette_id);
+                    nbt_palette.insert(state, Value::Int(palette_id));
+                    palette_id
+                }
+            };
+            let mut palette_id = palette_id as u32;
+            loop {
+                let byte = (palette_id & 127) as u8;
+                palette_id >>= 7;
+                if palette_id == 0 {
+                    blocks.push(byte as i8);
+                    break;
+                }
+                blocks.push((byte | 128) as i8);
+            }
+        }
+
+        let mut block_entities = Vec::new();
+        for (pos, block_entity) in &self.block_entities {
+            let index = (pos.y * self.size_z + pos.z as u32) * self.size_x + pos.x as u32;
+            let block = Block::from_block_state(self.data.get_entry(index as usize));
+            if let Some(mut nbt) = block_entity.to_schematic_nbt(block) {
+                nbt.insert(
+                    "Pos".to_owned(),
+                    Value::IntArray(vec![pos.x, pos.y as i32, pos.z]),
+                );
+                block_entities.push(Value::Compound(nbt));
+            }
+        }
+
+        let mut metadata = HashMap::new();
+        metadata.insert("WEOffsetX".to_owned(), Value::Int(-self.offset_x));
+        metadata.insert("WEOffsetY".to_owned(), Value::Int(-self.offset_y));
+        metadata.insert("WEOffsetZ".to_owned(), Value::Int(-self.offset_z));
+
+        let mut nbt = nbt::Blob::named("Schematic");
+        nbt.insert("Version", Value::Int(2))?;
+        // Data version for 1.15.2
+        nbt.insert("DataVersion", Value::Int(2230))?;
+        nbt.insert("Width", Value::Short(self.size_x as i16))?;
+        nbt.insert("Height", Value::Short(self.size_y as i16))?;
+        nbt.insert("Length", Value::Short(self.size_z as i16))?;
+        nbt.insert("PaletteMax", Value::Int(palette.len() as i32))?;
+        nbt.insert("Palette", Value::Compound(nbt_palette))?;
+        nbt.insert("BlockData", Value::ByteArray(blocks))?;
+        nbt.insert("BlockEntities", Value::List(block_entities))?;
+        nbt.insert("Metadata", Value::Compound(metadata))?;
+
+        let mut file = File::create(path)?;
+        nbt.to_gzip_writer(&mut file)
+    }
+}
+
+/// A snapshot of the blocks in a region from before it was changed by an operation,
+/// which is used to undo or redo that operation.
+#[derive(Clone, Debug)]
+pub struct WorldEditUndo {
+    plot_x: i32,
+    plot_z: i32,
+    pos: BlockPos,
+    clipboard: WorldEditClipboard,
+}
+
+impl WorldEditUndo {
+    fn size_in_bytes(&self) -> usize {
+        self.clipboard.data.size_in_bytes()
+            + self.clipboard.block_entities.len() * std::mem::size_of::<(BlockPos, BlockEntity)>()
+    }
+
+    fn end_pos(&self) -> BlockPos {
+        BlockPos::new(
+            self.pos.x + self.clipboard.size_x as i32 - 1,
+            self.pos.y + self.clipboard.size_y - 1,
+            self.pos.z + self.clipboard.size_z as i32 - 1,
+        )
+    }
 }
 
 pub enum PatternParseError {
@@ -253,14 +354,29 @@ impl WorldEditOperation {
             .find(|c| c.chunk_x == chunk_x && c.chunk_z == chunk_z)
         {
             packet.records.push(C10MultiBlockChangeRecord {
-                x: (block_pos.x >> 4) as i8,
-                y: (block_pos.y >> 4) as u8,
-                z: (block_pos.z >> 4) as i8,
+                x: (block_pos.x & 0xF) as i8,
+                y: block_pos.y as u8,
+                z: (block_pos.z & 0xF) as i8,
                 block_id: block_id as i32,
             })
         }
     }
 
+    fn bounds(&self) -> (BlockPos, BlockPos) {
+        (
+            BlockPos::new(
+                *self.x_range.start(),
+                *self.y_range.start(),
+                *self.z_range.start(),
+            ),
+            BlockPos::new(
+                *self.x_range.end(),
+                *self.y_range.end(),
+                *self.z_range.end(),
+            ),
+        )
+    }
+
     fn blocks_updated(&self) -> usize {
         let mut blocks_updated = 0;
 
@@ -284,21 +400,9 @@ impl WorldEditOperation {
 
 impl Plot {
     fn worldedit_send_operation(&mut self, operation: WorldEditOperation) {
+        self.flush_block_changes();
         for packet in operation.records {
-            // if packet.records.len() >= 8192 {
-            let chunk_index = self.get_chunk_index_for_chunk(packet.chunk_x, packet.chunk_z);
-            let chunk = &self.chunks[chunk_index];
-            let chunk_data = chunk.encode_packet(false);
-            for player in &mut self.players {
-                player.client.send_packet(&chunk_data);
-            }
-            // } else {
-            //     let multi_block_change = &packet.encode();
-
-            //     for player in &mut self.players {
-            //         player.client.send_packet(&multi_block_change);
-            //     }
-            // }
+            self.send_multi_block_change(packet);
         }
     }
 
@@ -339,6 +443,8 @@ impl Plot {
         let pattern = WorldEditPattern::from_str(pattern_str)?;
 
         if let Some(mut operation) = self.worldedit_start_operation(player) {
+            let (first_pos, second_pos) = operation.bounds();
+            let undo = self.worldedit_create_undo(first_pos, second_pos);
             for x in operation.x_range() {
                 for y in operation.y_range() {
                     for z in operation.z_range() {
@@ -354,6 +460,9 @@ impl Plot {
 
             let blocks_updated = operation.blocks_updated();
             self.worldedit_send_operation(operation);
+            if blocks_updated > 0 {
+                self.worldedit_push_undo(player, undo);
+            }
 
             self.players[player].send_worldedit_message(&format!(
                 "Operation completed: {} block(s) affected ({:?})",
@@ -376,6 +485,8 @@ impl Plot {
         let pattern = WorldEditPattern::from_str(pattern_str)?;
 
         if let Some(mut operation) = self.worldedit_start_operation(player) {
+            let (first_pos, second_pos) = operation.bounds();
+            let undo = self.worldedit_create_undo(first_pos, second_pos);
             for x in operation.x_range() {
                 for y in operation.y_range() {
                     for z in operation.z_range() {
@@ -394,6 +505,9 @@ impl Plot {
 
             let blocks_updated = operation.blocks_updated();
             self.worldedit_send_operation(operation);
+            if blocks_updated > 0 {
+                self.worldedit_push_undo(player, undo);
+            }
 
             self.players[player].send_worldedit_message(&format!(
                 "Operation completed: {} block(s) affected ({:?})",
@@ -455,15 +569,20 @@ impl Plot {
             size_y,
             size_z,
             data: PalettedBitBuffer::with_entries((size_x * size_y * size_z) as usize),
-            // TODO: Get the block entities in the selection
             block_entities: HashMap::new(),
         };
         let mut i = 0;
         for y in start_pos.y..=end_pos.y {
             for z in start_pos.z..=end_pos.z {
                 for x in start_pos.x..=end_pos.x {
-                    cb.data
-                        .set_entry(i, self.get_block_raw(BlockPos::new(x, y, z)));
+                    let pos = BlockPos::new(x, y, z);
+                    cb.data.set_entry(i, self.get_block_raw(pos));
+                    if let Some(block_entity) = self.get_block_entity(pos) {
+                        // Block entities are stored relative to the corner of the clipboard
+                        let cb_pos =
+                            BlockPos::new(x - start_pos.x, y - start_pos.y, z - start_pos.z);
+                        cb.block_entities.insert(cb_pos, block_entity.clone());
+                    }
                     i += 1;
                 }
             }
@@ -471,7 +590,7 @@ impl Plot {
         cb
     }
 
-    fn paste_clipboard(&mut self, cb: &WorldEditClipboard, pos: BlockPos) {
+    pub fn paste_clipboard(&mut self, cb: &WorldEditClipboard, pos: BlockPos) {
         let offset_x = pos.x - cb.offset_x;
         let offset_y = pos.y as i32 - cb.offset_y;
         let offset_z = pos.z - cb.offset_z;
@@ -494,13 +613,16 @@ impl Plot {
                 }
             }
         }
-        let chunk_x_range =
-            (offset_x - (self.x << 8)) >> 4..=(offset_x + cb.size_x as i32 - (self.x << 8)) >> 4;
-        let chunk_z_range =
-            (offset_z - (self.z << 8)) >> 4..=(offset_z + cb.size_z as i32 - (self.z << 8)) >> 4;
+        self.flush_block_changes();
+        let (plot_x, plot_z) = (self.x * plot_size(), self.z * plot_size());
+        let max_chunk = (plot_size() >> 4) - 1;
+        let chunk_x_range = ((offset_x - plot_x) >> 4).max(0)
+            ..=((offset_x + cb.size_x as i32 - 1 - plot_x) >> 4).min(max_chunk);
+        let chunk_z_range = ((offset_z - plot_z) >> 4).max(0)
+            ..=((offset_z + cb.size_z as i32 - 1 - plot_z) >> 4).min(max_chunk);
         for chunk_x in chunk_x_range {
             for chunk_z in chunk_z_range.clone() {
-                let chunk = &self.chunks[((chunk_x << 4) + chunk_z) as usize];
+                let chunk = &self.chunks[(chunk_x * (max_chunk + 1) + chunk_z) as usize];
                 let chunk_data = chunk.encode_packet(false);
                 for player in &mut self.players {
                     player.client.send_packet(&chunk_data);
@@ -552,7 +674,18 @@ impl Plot {
                 self.players[player].y.floor() as u32,
                 self.players[player].z.floor() as i32,
             );
+            let start_y = (pos.y as i32 - cb.offset_y).max(0);
+            let end_y = (start_y + cb.size_y as i32 - 1).min(255);
+            let undo = self.worldedit_create_undo(
+                BlockPos::new(pos.x - cb.offset_x, start_y as u32, pos.z - cb.offset_z),
+                BlockPos::new(
+                    pos.x - cb.offset_x + cb.size_x as i32 - 1,
+                    end_y as u32,
+                    pos.z - cb.offset_z + cb.size_z as i32 - 1,
+                ),
+            );
             self.paste_clipboard(cb, pos);
+            self.worldedit_push_undo(player, undo);
             self.players[player].send_worldedit_message(&format!(
                 "Your clipboard was pasted. ({:?})",
                 start_time.elapsed()
@@ -581,6 +714,150 @@ impl Plot {
         }
     }
 
+    pub(super) fn worldedit_save(&mut self, player: usize, file_name: &str) {
+        let start_time = Instant::now();
+
+        let clipboard = match &self.players[player].worldedit_clipboard {
+            Some(cb) => cb,
+            None => {
+                self.players[player].send_system_message("Your clipboard is empty!");
+                return;
+            }
+        };
+        if file_name.is_empty()
+            || !file_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            self.players[player].send_error_message(
+                "Schematic names may only contain letters, numbers, underscores and dashes.",
+            );
+            return;
+        }
+        match clipboard.save_to_schematic(file_name) {
+            Ok(()) => {
+                self.players[player].send_worldedit_message(&format!(
+                    "Your clipboard was saved to {}.schem ({:?})",
+                    file_name,
+                    start_time.elapsed()
+                ));
+            }
+            Err(err) => {
+                self.players[player].send_error_message(&format!(
+                    "There was an error saving the schematic: {}",
+                    err
+                ));
+            }
+        }
+    }
+
+    /// Takes a snapshot of the region so that the operation about to change it can be undone.
+    fn worldedit_create_undo(&self, first_pos: BlockPos, second_pos: BlockPos) -> WorldEditUndo {
+        let pos = first_pos.min(second_pos);
+        WorldEditUndo {
+            plot_x: self.x,
+            plot_z: self.z,
+            pos,
+            clipboard: self.create_clipboard(pos, first_pos, second_pos),
+        }
+    }
+
+    fn worldedit_push_undo(&mut self, player: usize, undo: WorldEditUndo) {
+        let max_size = CONFIG.worldedit_history_mb as usize * 1024 * 1024;
+        let player = &mut self.players[player];
+        player.worldedit_redo.clear();
+        player.worldedit_undo.push(undo);
+        let mut history_size: usize = player
+            .worldedit_undo
+            .iter()
+            .map(WorldEditUndo::size_in_bytes)
+            .sum();
+        // Forget the oldest operations until the history fits
+        while history_size > max_size {
+            let removed = player.worldedit_undo.remove(0);
+            history_size -= removed.size_in_bytes();
+        }
+        if player.worldedit_undo.is_empty() && max_size > 0 {
+            player.send_system_message("This operation was too large to be undone.");
+        }
+    }
+
+    /// Puts the blocks from the snapshot back into the world and resends the chunks.
+    /// Returns a snapshot of what was there before so that this can be reverted too.
+    fn worldedit_restore(&mut self, undo: &WorldEditUndo) -> WorldEditUndo {
+        let current = self.worldedit_create_undo(undo.pos, undo.end_pos());
+        for pos in current.clipboard.block_entities.keys() {
+            self.delete_block_entity(BlockPos::new(
+                undo.pos.x + pos.x,
+                undo.pos.y + pos.y,
+                undo.pos.z + pos.z,
+            ));
+        }
+        self.paste_clipboard(&undo.clipboard, undo.pos);
+        current
+    }
+
+    /// Moves up to `count` operations from the undo history to the redo history, or the
+    /// other way around if `redo` is set, applying each of them. Returns how many were applied.
+    fn worldedit_apply_history(&mut self, player: usize, count: usize, redo: bool) -> usize {
+        let mut applied = 0;
+        while applied < count {
+            let history = if redo {
+                &mut self.players[player].worldedit_redo
+            } else {
+                &mut self.players[player].worldedit_undo
+            };
+            let entry = match history.last() {
+                Some(entry) if entry.plot_x == self.x && entry.plot_z == self.z => {
+                    history.pop().unwrap()
+                }
+                Some(_) => {
+                    self.players[player].send_error_message(
+                        "The next operation in your history was made in another plot.",
+                    );
+                    break;
+                }
+                None => break,
+            };
+            let reverse = self.worldedit_restore(&entry);
+            if redo {
+                self.players[player].worldedit_undo.push(reverse);
+            } else {
+                self.players[player].worldedit_redo.push(reverse);
+            }
+            applied += 1;
+        }
+        applied
+    }
+
+    pub(super) fn worldedit_undo(&mut self, player: usize, count: usize) {
+        let start_time = Instant::now();
+        let undone = self.worldedit_apply_history(player, count, false);
+        if undone == 0 {
+            self.players[player].send_system_message("There is nothing left to undo.");
+        } else {
+            self.players[player].send_worldedit_message(&format!(
+                "Undid {} operation(s) ({:?})",
+                undone,
+                start_time.elapsed()
+            ));
+        }
+    }
+
+    pub(super) fn worldedit_redo(&mut self, player: usize, count: usize) {
+        let start_time = Instant::now();
+        let redone = self.worldedit_apply_history(player, count, true);
+        if redone == 0 {
+            self.players[player].send_system_message("There is nothing left to redo.");
+        } else {
+            self.players[player].send_worldedit_message(&format!(
+                "Redid {} operation(s) ({:?})",
+                redone,
+                start_time.elapsed()
+            ));
+        }
+    }
+
     pub(super) fn worldedit_find(&mut self, player: usize, block_id: u32) {
         let start_time = Instant::now();
 
@@ -603,3 +880,142 @@ impl Plot {
         }
     }
 }
+
+#[test]
+fn schematic_save_and_load_test() {
+    use crate::blocks::{ContainerType, InventoryEntry};
+
+    let blocks = [
+        Block::Air,
+        Block::Solid(1),
+        Block::Transparent(7855),
+        // Redstone dot with a power of 7
+        Block::from_block_state(3279),
+        // Locked repeater with a delay of 3
+        Block::from_block_state(4058),
+        // Powered lever on the floor
+        Block::from_block_state(3781),
+        Block::from_name("comparator").unwrap(),
+        Block::from_name("barrel").unwrap(),
+    ];
+    let mut data = PalettedBitBuffer::with_entries(blocks.len());
+    for (i, block) in blocks.iter().enumerate() {
+        data.set_entry(i, block.get_id());
+    }
+    let mut block_entities = HashMap::new();
+    block_entities.insert(
+        BlockPos::new(6, 0, 0),
+        BlockEntity::Comparator { output_strength: 7 },
+    );
+    // 16 stacks of redstone in a barrel give a signal of 9
+    let inventory: Vec<InventoryEntry> = (0..16)
+        .map(|slot| InventoryEntry {
+            id: 600,
+            slot,
+            count: 64,
+            nbt: None,
+        })
+        .collect();
+    block_entities.insert(
+        BlockPos::new(7, 0, 0),
+        BlockEntity::container(ContainerType::Barrel, inventory.clone()),
+    );
+    let cb = WorldEditClipboard {
+        offset_x: 1,
+        offset_y: -2,
+        offset_z: 3,
+        size_x: blocks.len() as u32,
+        size_y: 1,
+        size_z: 1,
+        data,
+        block_entities,
+    };
+
+    let path = std::env::temp_dir().join("mchprs_schematic_save_and_load.schem");
+    cb.save_to_schematic_file(&path).unwrap();
+    let loaded = WorldEditClipboard::load_from_schematic_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        (loaded.offset_x, loaded.offset_y, loaded.offset_z),
+        (1, -2, 3)
+    );
+    assert_eq!((loaded.size_x, loaded.size_y, loaded.size_z), (8, 1, 1));
+    for (i, block) in blocks.iter().enumerate() {
+        assert_eq!(Block::from_block_state(loaded.data.get_entry(i)), *block);
+    }
+    match loaded.block_entities.get(&BlockPos::new(6, 0, 0)) {
+        Some(BlockEntity::Comparator { output_strength }) => assert_eq!(*output_strength, 7),
+        _ => panic!("comparator block entity was not loaded"),
+    }
+    match loaded.block_entities.get(&BlockPos::new(7, 0, 0)) {
+        Some(BlockEntity::Container {
+            comparator_override,
+            inventory: loaded_inventory,
+            ..
+        }) => {
+            assert_eq!(*comparator_override, 9);
+            assert_eq!(*loaded_inventory, inventory);
+        }
+        _ => panic!("container block entity was not loaded"),
+    }
+}
+
+#[test]
+fn undo_and_redo_test() {
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    let first_pos = BlockPos::new(10, 64, 10);
+    let second_pos = BlockPos::new(20, 70, 20);
+    plot.set_block_raw(BlockPos::new(12, 65, 12), 1);
+    plot.set_block_entity(
+        BlockPos::new(15, 64, 15),
+        BlockEntity::Comparator { output_strength: 3 },
+    );
+
+    let undo = plot.worldedit_create_undo(first_pos, second_pos);
+    plot.set_block_raw(BlockPos::new(12, 65, 12), 0);
+    plot.set_block_raw(BlockPos::new(20, 70, 20), 1);
+    plot.delete_block_entity(BlockPos::new(15, 64, 15));
+
+    let redo = plot.worldedit_restore(&undo);
+    assert_eq!(plot.get_block_raw(BlockPos::new(12, 65, 12)), 1);
+    assert_eq!(plot.get_block_raw(BlockPos::new(20, 70, 20)), 0);
+    assert!(plot.get_block_entity(BlockPos::new(15, 64, 15)).is_some());
+
+    plot.worldedit_restore(&redo);
+    assert_eq!(plot.get_block_raw(BlockPos::new(12, 65, 12)), 0);
+    assert_eq!(plot.get_block_raw(BlockPos::new(20, 70, 20)), 1);
+    assert!(plot.get_block_entity(BlockPos::new(15, 64, 15)).is_none());
+}
+
+#[test]
+fn schematic_unknown_block_test() {
+    // Granite isn't one of the blocks the server knows the name of
+    let block = Block::from_block_state(2);
+    assert_eq!(block.get_name(), None);
+    let mut data = PalettedBitBuffer::with_entries(1);
+    data.set_entry(0, block.get_id());
+    let cb = WorldEditClipboard {
+        offset_x: 0,
+        offset_y: 0,
+        offset_z: 0,
+        size_x: 1,
+        size_y: 1,
+        size_z: 1,
+        data,
+        block_entities: HashMap::new(),
+    };
+    let path = std::env::temp_dir().join("mchprs_schematic_unknown_block.schem");
+    assert!(cb.save_to_schematic_file(&path).is_err());
+    assert!(!path.exists());
+
+    // Schematics saved with the old misspelling still load
+    assert_eq!(
+        Block::from_name("megenta_wool"),
+        Block::from_name("magenta_wool")
+    );
+    assert_eq!(
+        Block::from_name("magenta_concrete").unwrap().get_name(),
+        Some("magenta_concrete")
+    );
+}
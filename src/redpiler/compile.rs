@@ -0,0 +1,404 @@
+use super::{Link, LinkType, Node, NodeId, NodeKind};
+use crate::blocks::{
+    Block, BlockDirection, BlockEntity, BlockFace, BlockPos, ButtonFace, LeverFace, RedstoneWire,
+};
+use crate::plot::Plot;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Redstone wire can't carry a signal further than this.
+const MAX_WIRE_DISTANCE: u8 = 15;
+
+fn node_kind(plot: &Plot, pos: BlockPos, block: Block) -> Option<NodeKind> {
+    Some(match block {
+        Block::RedstoneRepeater(repeater) => {
+            let front_pos = pos.offset(repeater.facing.opposite().block_face());
+            NodeKind::Repeater {
+                delay: repeater.delay,
+                facing_diode: plot.get_block(front_pos).is_diode(),
+            }
+        }
+        Block::RedstoneComparator(comparator) => {
+            let front_pos = pos.offset(comparator.facing.opposite().block_face());
+            let input_pos = pos.offset(comparator.facing.block_face());
+            let input_block = plot.get_block(input_pos);
+            let far_pos = input_pos.offset(comparator.facing.block_face());
+            let far_block = plot.get_block(far_pos);
+            let input_override = if input_block.has_comparator_override() {
+                Some(input_block.get_comparator_override(plot, input_pos))
+            } else {
+                None
+            };
+            let far_override = if input_block.is_cube() && far_block.has_comparator_override() {
+                Some(far_block.get_comparator_override(plot, far_pos))
+            } else {
+                None
+            };
+            NodeKind::Comparator {
+                mode: comparator.mode,
+                input_override,
+                far_override,
+                facing_diode: plot.get_block(front_pos).is_diode(),
+            }
+        }
+        Block::RedstoneTorch(_) | Block::RedstoneWallTorch(_, _) => NodeKind::Torch,
+        Block::RedstoneLamp(_) => NodeKind::Lamp,
+        Block::Lever(_) => NodeKind::Lever,
+        Block::StoneButton(_) => NodeKind::Button,
+        Block::RedstoneBlock => NodeKind::Constant,
+        Block::RedstoneWire(_) => NodeKind::Wire,
+        _ => return None,
+    })
+}
+
+fn new_node(plot: &Plot, pos: BlockPos, block: Block, kind: NodeKind) -> Node {
+    let (powered, output_power, locked) = match block {
+        Block::RedstoneRepeater(repeater) => {
+            (repeater.powered, if repeater.powered { 15 } else { 0 }, repeater.locked)
+        }
+        Block::RedstoneComparator(comparator) => {
+            let output_strength = match plot.get_block_entity(pos) {
+                Some(BlockEntity::Comparator { output_strength }) => *output_strength,
+                _ => 0,
+            };
+            (comparator.powered, output_strength, false)
+        }
+        Block::RedstoneTorch(lit) | Block::RedstoneWallTorch(lit, _) => {
+            (lit, if lit { 15 } else { 0 }, false)
+        }
+        Block::RedstoneLamp(lit) => (lit, 0, false),
+        Block::Lever(lever) => (lever.powered, if lever.powered { 15 } else { 0 }, false),
+        Block::StoneButton(button) => (button.powered, if button.powered { 15 } else { 0 }, false),
+        Block::RedstoneBlock => (true, 15, false),
+        Block::RedstoneWire(wire) => (false, wire.power, false),
+        _ => (false, 0, false),
+    };
+    Node {
+        pos,
+        block,
+        kind,
+        inputs: Vec::new(),
+        updates: Vec::new(),
+        powered,
+        output_power,
+        locked,
+        pending_tick: false,
+        changed: false,
+    }
+}
+
+/// Finds the sources of every input by following the same rules the world simulation
+/// uses in `get_redstone_power`, except that it returns nodes instead of power levels.
+struct InputSearch<'a> {
+    plot: &'a Plot,
+    pos_map: &'a HashMap<BlockPos, NodeId>,
+    wire_cache: HashMap<BlockPos, Vec<(NodeId, u8)>>,
+}
+
+impl<'a> InputSearch<'a> {
+    fn push_node(&self, pos: BlockPos, weight: u8, sources: &mut Vec<(NodeId, u8)>) {
+        if let Some(&id) = self.pos_map.get(&pos) {
+            sources.push((id, weight));
+        }
+    }
+
+    fn weak_sources(
+        &mut self,
+        block: Block,
+        pos: BlockPos,
+        side: BlockFace,
+        dust_power: bool,
+        sources: &mut Vec<(NodeId, u8)>,
+    ) {
+        match block {
+            Block::RedstoneTorch(_)
+            | Block::RedstoneWallTorch(_, _)
+            | Block::RedstoneBlock
+            | Block::Lever(_)
+            | Block::StoneButton(_) => self.push_node(pos, 0, sources),
+            Block::RedstoneRepeater(repeater) if repeater.facing.block_face() == side => {
+                self.push_node(pos, 0, sources)
+            }
+            Block::RedstoneComparator(comparator) if comparator.facing.block_face() == side => {
+                self.push_node(pos, 0, sources)
+            }
+            Block::RedstoneWire(_) if dust_power => match side {
+                BlockFace::Top => sources.extend(self.wire_sources(pos)),
+                BlockFace::Bottom => {}
+                _ => {
+                    let direction = side.to_direction();
+                    if RedstoneWire::get_side(self.plot, pos, direction.rotate()).is_none()
+                        && RedstoneWire::get_side(self.plot, pos, direction.rotate_ccw()).is_none()
+                    {
+                        sources.extend(self.wire_sources(pos));
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn strong_sources(
+        &mut self,
+        block: Block,
+        pos: BlockPos,
+        side: BlockFace,
+        dust_power: bool,
+        sources: &mut Vec<(NodeId, u8)>,
+    ) {
+        match block {
+            Block::RedstoneTorch(_) | Block::RedstoneWallTorch(_, _) if side == BlockFace::Bottom => {
+                self.push_node(pos, 0, sources)
+            }
+            Block::Lever(lever) => {
+                let powers_side = match side {
+                    BlockFace::Top if lever.face == LeverFace::Floor => true,
+                    BlockFace::Bottom if lever.face == LeverFace::Ceiling => true,
+                    _ => lever.facing == side.to_direction(),
+                };
+                if powers_side {
+                    self.push_node(pos, 0, sources);
+                }
+            }
+            Block::StoneButton(button) => {
+                let powers_side = match side {
+                    BlockFace::Top if button.face == ButtonFace::Floor => true,
+                    BlockFace::Bottom if button.face == ButtonFace::Ceiling => true,
+                    _ => button.facing == side.to_direction(),
+                };
+                if powers_side {
+                    self.push_node(pos, 0, sources);
+                }
+            }
+            Block::RedstoneWire(_) | Block::RedstoneRepeater(_) | Block::RedstoneComparator(_) => {
+                self.weak_sources(block, pos, side, dust_power, sources)
+            }
+            _ => {}
+        }
+    }
+
+    fn power_sources(
+        &mut self,
+        block: Block,
+        pos: BlockPos,
+        side: BlockFace,
+        dust_power: bool,
+        sources: &mut Vec<(NodeId, u8)>,
+    ) {
+        if block.is_solid() {
+            for face in &BlockFace::values() {
+                let neighbor_pos = pos.offset(*face);
+                let neighbor = self.plot.get_block(neighbor_pos);
+                self.strong_sources(neighbor, neighbor_pos, *face, dust_power, sources);
+            }
+        } else {
+            self.weak_sources(block, pos, side, dust_power, sources);
+        }
+    }
+
+    /// Returns the wires that a wire at `pos` reads its power from.
+    fn connected_wires(&self, pos: BlockPos) -> Vec<BlockPos> {
+        let mut wires = Vec::new();
+        let mut push_wire = |pos: BlockPos| {
+            if let Block::RedstoneWire(_) = self.plot.get_block(pos) {
+                wires.push(pos);
+            }
+        };
+        let up_block = self.plot.get_block(pos.offset(BlockFace::Top));
+        for side in &BlockFace::values() {
+            let neighbor_pos = pos.offset(*side);
+            push_wire(neighbor_pos);
+            if side.is_horizontal() {
+                let neighbor = self.plot.get_block(neighbor_pos);
+                if !up_block.is_solid() && !neighbor.is_transparent() {
+                    push_wire(neighbor_pos.offset(BlockFace::Top));
+                }
+                if !neighbor.is_solid() {
+                    push_wire(neighbor_pos.offset(BlockFace::Bottom));
+                }
+            }
+        }
+        wires
+    }
+
+    /// Returns every node that powers the wire at `pos`, along with how much power
+    /// is lost getting there. This walks the whole wire network the same way the
+    /// power would spread through it.
+    fn wire_sources(&mut self, pos: BlockPos) -> Vec<(NodeId, u8)> {
+        if let Some(sources) = self.wire_cache.get(&pos) {
+            return sources.clone();
+        }
+
+        let mut best: HashMap<NodeId, u8> = HashMap::new();
+        let mut visited = HashSet::new();
+        visited.insert(pos);
+        let mut queue = VecDeque::new();
+        queue.push_back((pos, 0));
+        while let Some((wire_pos, distance)) = queue.pop_front() {
+            let mut direct_sources = Vec::new();
+            for side in &BlockFace::values() {
+                let neighbor_pos = wire_pos.offset(*side);
+                let neighbor = self.plot.get_block(neighbor_pos);
+                self.power_sources(neighbor, neighbor_pos, *side, false, &mut direct_sources);
+            }
+            for (id, _) in direct_sources {
+                let weight = best.entry(id).or_insert(distance);
+                *weight = (*weight).min(distance);
+            }
+
+            if distance + 1 >= MAX_WIRE_DISTANCE {
+                continue;
+            }
+            for next_pos in self.connected_wires(wire_pos) {
+                if visited.insert(next_pos) {
+                    queue.push_back((next_pos, distance + 1));
+                }
+            }
+        }
+
+        let sources: Vec<(NodeId, u8)> = best.into_iter().collect();
+        self.wire_cache.insert(pos, sources.clone());
+        sources
+    }
+
+    /// The same as `diode_get_input_strength`.
+    fn diode_input_sources(&mut self, pos: BlockPos, facing: BlockDirection) -> Vec<(NodeId, u8)> {
+        let input_pos = pos.offset(facing.block_face());
+        let input_block = self.plot.get_block(input_pos);
+        if let Block::RedstoneWire(_) = input_block {
+            self.wire_sources(input_pos)
+        } else {
+            let mut sources = Vec::new();
+            self.power_sources(input_block, input_pos, facing.block_face(), true, &mut sources);
+            sources
+        }
+    }
+
+    fn diode_side_sources(
+        &mut self,
+        pos: BlockPos,
+        facing: BlockDirection,
+        include_wire: bool,
+    ) -> Vec<(NodeId, u8)> {
+        let mut sources = Vec::new();
+        for side in &[facing.rotate(), facing.rotate_ccw()] {
+            let side_pos = pos.offset(side.block_face());
+            let side_block = self.plot.get_block(side_pos);
+            if side_block.is_diode() {
+                self.weak_sources(side_block, side_pos, side.block_face(), false, &mut sources);
+            } else if let Block::RedstoneWire(_) = side_block {
+                if include_wire {
+                    sources.extend(self.wire_sources(side_pos));
+                }
+            }
+        }
+        sources
+    }
+
+    fn inputs(&mut self, node: &Node) -> Vec<Link> {
+        let pos = node.pos;
+        let (default_sources, side_sources) = match node.block {
+            Block::RedstoneRepeater(repeater) => (
+                self.diode_input_sources(pos, repeater.facing),
+                self.diode_side_sources(pos, repeater.facing, false),
+            ),
+            Block::RedstoneComparator(comparator) => (
+                self.diode_input_sources(pos, comparator.facing),
+                self.diode_side_sources(pos, comparator.facing, true),
+            ),
+            Block::RedstoneTorch(_) => {
+                let bottom_pos = pos.offset(BlockFace::Bottom);
+                let bottom_block = self.plot.get_block(bottom_pos);
+                let mut sources = Vec::new();
+                self.power_sources(bottom_block, bottom_pos, BlockFace::Top, true, &mut sources);
+                (sources, Vec::new())
+            }
+            Block::RedstoneWallTorch(_, direction) => {
+                let wall_face = direction.opposite().block_face();
+                let wall_pos = pos.offset(wall_face);
+                let wall_block = self.plot.get_block(wall_pos);
+                let mut sources = Vec::new();
+                self.power_sources(wall_block, wall_pos, wall_face, true, &mut sources);
+                (sources, Vec::new())
+            }
+            Block::RedstoneLamp(_) => {
+                let mut sources = Vec::new();
+                for face in &BlockFace::values() {
+                    let neighbor_pos = pos.offset(*face);
+                    let neighbor = self.plot.get_block(neighbor_pos);
+                    self.power_sources(neighbor, neighbor_pos, *face, true, &mut sources);
+                }
+                (sources, Vec::new())
+            }
+            Block::RedstoneWire(_) => (self.wire_sources(pos), Vec::new()),
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        let mut links: Vec<Link> = Vec::new();
+        let all_sources = default_sources
+            .into_iter()
+            .map(|source| (LinkType::Default, source))
+            .chain(side_sources.into_iter().map(|source| (LinkType::Side, source)));
+        for (ty, (from, weight)) in all_sources {
+            // Only the strongest link between two nodes matters
+            if let Some(link) = links.iter_mut().find(|l| l.ty == ty && l.from == from) {
+                link.weight = link.weight.min(weight);
+            } else {
+                links.push(Link { ty, weight, from });
+            }
+        }
+        links
+    }
+}
+
+/// Returns true for components that have state the redpiler can't simulate yet.
+fn is_unsupported(block: Block) -> bool {
+    matches!(
+        block,
+        Block::Observer(_, _)
+            | Block::Piston(_)
+            | Block::PistonHead(_)
+            | Block::MovingPiston(_, _)
+            | Block::PressurePlate(_)
+            | Block::TripwireHook(_)
+            | Block::Hopper(_)
+            | Block::Dropper(_)
+    )
+}
+
+/// Builds the nodes for the plot. Also returns the number of components that
+/// were left out because they can't be compiled.
+pub fn compile(plot: &Plot) -> (Vec<Node>, usize) {
+    let mut nodes = Vec::new();
+    let mut unsupported = 0;
+    plot.for_each_block(|pos, block| {
+        if let Some(kind) = node_kind(plot, pos, block) {
+            nodes.push(new_node(plot, pos, block, kind));
+        } else if is_unsupported(block) {
+            unsupported += 1;
+        }
+    });
+
+    let pos_map: HashMap<BlockPos, NodeId> = nodes
+        .iter()
+        .enumerate()
+        .map(|(id, node)| (node.pos, id))
+        .collect();
+    let mut search = InputSearch {
+        plot,
+        pos_map: &pos_map,
+        wire_cache: HashMap::new(),
+    };
+    for node in nodes.iter_mut() {
+        node.inputs = search.inputs(node);
+    }
+
+    for id in 0..nodes.len() {
+        for i in 0..nodes[id].inputs.len() {
+            let from = nodes[id].inputs[i].from;
+            if !nodes[from].updates.contains(&id) {
+                nodes[from].updates.push(id);
+            }
+        }
+    }
+    (nodes, unsupported)
+}
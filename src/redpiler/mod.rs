@@ -0,0 +1,602 @@
+//! The redpiler compiles the redstone in a plot into a flat graph of nodes so
+//! that it can be simulated without going through the chunk storage. Every node
+//! knows exactly which other nodes it reads power from, which lets ticks skip
+//! all of the neighbor lookups and wire propagation the world simulation does.
+
+mod compile;
+
+use crate::blocks::{Block, BlockEntity, BlockPos, ComparatorMode};
+use crate::plot::{Plot, TickPriority};
+use std::collections::HashMap;
+use std::mem;
+
+/// Nothing in vanilla redstone schedules a tick further than 10 redstone ticks
+/// ahead, so 16 slots are plenty for the tick ring buffer.
+const TICK_SLOTS: usize = 16;
+
+pub type NodeId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LinkType {
+    Default,
+    Side,
+}
+
+/// A precomputed connection between two nodes. The power received over the link
+/// is the output power of `from` minus `weight`, which is how far the signal had
+/// to travel through redstone wire.
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    ty: LinkType,
+    weight: u8,
+    from: NodeId,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    Repeater {
+        delay: u8,
+        facing_diode: bool,
+    },
+    Comparator {
+        mode: ComparatorMode,
+        /// The comparator override of the container directly behind the comparator.
+        input_override: Option<u8>,
+        /// The comparator override of the container behind the block behind the comparator.
+        far_override: Option<u8>,
+        facing_diode: bool,
+    },
+    Torch,
+    Lamp,
+    Lever,
+    Button,
+    /// Redstone blocks, which are always powered.
+    Constant,
+    /// Redstone wire only needs to be kept around so it can be shown to players.
+    /// Other nodes are linked directly to the sources powering the wire.
+    Wire,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    pos: BlockPos,
+    block: Block,
+    kind: NodeKind,
+    inputs: Vec<Link>,
+    /// The nodes that have to be updated when the output of this node changes.
+    updates: Vec<NodeId>,
+    powered: bool,
+    output_power: u8,
+    locked: bool,
+    pending_tick: bool,
+    changed: bool,
+}
+
+impl Node {
+    /// Writes the simulated state back into the block so it can be placed in the world.
+    fn to_block(&self) -> Block {
+        match self.block {
+            Block::RedstoneRepeater(mut repeater) => {
+                repeater.powered = self.powered;
+                repeater.locked = self.locked;
+                Block::RedstoneRepeater(repeater)
+            }
+            Block::RedstoneComparator(mut comparator) => {
+                comparator.powered = self.powered;
+                Block::RedstoneComparator(comparator)
+            }
+            Block::RedstoneTorch(_) => Block::RedstoneTorch(self.powered),
+            Block::RedstoneWallTorch(_, facing) => Block::RedstoneWallTorch(self.powered, facing),
+            Block::RedstoneLamp(_) => Block::RedstoneLamp(self.powered),
+            Block::Lever(mut lever) => {
+                lever.powered = self.powered;
+                Block::Lever(lever)
+            }
+            Block::StoneButton(mut button) => {
+                button.powered = self.powered;
+                Block::StoneButton(button)
+            }
+            Block::RedstoneWire(mut wire) => {
+                wire.power = self.output_power;
+                Block::RedstoneWire(wire)
+            }
+            block => block,
+        }
+    }
+}
+
+/// A block which was changed by the compiled simulation and still has to be
+/// written back into the plot.
+pub struct BlockChange {
+    pub pos: BlockPos,
+    pub block: Block,
+    pub block_entity: Option<BlockEntity>,
+}
+
+struct TickScheduler {
+    queues: Vec<[Vec<NodeId>; 4]>,
+    pos: usize,
+}
+
+impl Default for TickScheduler {
+    fn default() -> TickScheduler {
+        TickScheduler {
+            queues: (0..TICK_SLOTS).map(|_| Default::default()).collect(),
+            pos: 0,
+        }
+    }
+}
+
+impl TickScheduler {
+    fn schedule_tick(&mut self, node: NodeId, delay: u32, priority: TickPriority) {
+        let delay = (delay as usize).clamp(1, TICK_SLOTS - 1);
+        let slot = (self.pos + delay) % TICK_SLOTS;
+        self.queues[slot][priority as usize].push(node);
+    }
+
+    fn next_slot(&mut self) -> [Vec<NodeId>; 4] {
+        self.pos = (self.pos + 1) % TICK_SLOTS;
+        mem::take(&mut self.queues[self.pos])
+    }
+}
+
+#[derive(Default)]
+pub struct Compiler {
+    is_active: bool,
+    nodes: Vec<Node>,
+    pos_map: HashMap<BlockPos, NodeId>,
+    scheduler: TickScheduler,
+    changed: Vec<NodeId>,
+    link_count: usize,
+    unsupported_count: usize,
+}
+
+impl Compiler {
+    /// Builds the node graph for all the redstone in the plot. Any ticks still pending for
+    /// compiled blocks have to be handed over with `take_pending_tick` afterwards.
+    pub fn compile(plot: &Plot) -> Compiler {
+        let (nodes, unsupported_count) = compile::compile(plot);
+        let mut pos_map = HashMap::new();
+        let mut link_count = 0;
+        for (id, node) in nodes.iter().enumerate() {
+            pos_map.insert(node.pos, id);
+            link_count += node.inputs.len();
+        }
+        Compiler {
+            is_active: true,
+            nodes,
+            pos_map,
+            scheduler: Default::default(),
+            changed: Vec::new(),
+            link_count,
+            unsupported_count,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn link_count(&self) -> usize {
+        self.link_count
+    }
+
+    /// The number of components in the plot which were left out of the graph
+    /// because the redpiler doesn't know how to simulate them.
+    pub fn unsupported_count(&self) -> usize {
+        self.unsupported_count
+    }
+
+    /// Moves a pending tick from the plot into the compiled simulation. Returns false if
+    /// there is no node at that position, in which case the tick has to stay in the plot.
+    pub fn take_pending_tick(&mut self, pos: BlockPos, delay: u32, priority: TickPriority) -> bool {
+        if let Some(&id) = self.pos_map.get(&pos) {
+            self.schedule_tick(id, delay, priority);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stops the compiled simulation. The caller is expected to have flushed all changes
+    /// back into the plot first. Returns the ticks that were still pending so they can be
+    /// scheduled in the plot again.
+    pub fn reset(&mut self) -> Vec<(BlockPos, u32, TickPriority)> {
+        let ticks = self.pending_ticks();
+        *self = Default::default();
+        ticks
+    }
+
+    /// Returns the pending ticks of the compiled simulation without stopping it.
+    /// This is used to save the plot while the redpiler is active.
+    pub fn pending_ticks(&self) -> Vec<(BlockPos, u32, TickPriority)> {
+        let mut ticks = Vec::new();
+        for delay in 1..TICK_SLOTS {
+            let slot = (self.scheduler.pos + delay) % TICK_SLOTS;
+            for (priority, queue) in TickPriority::values()
+                .iter()
+                .zip(self.scheduler.queues[slot].iter())
+            {
+                for id in queue {
+                    ticks.push((self.nodes[*id].pos, delay as u32, priority.clone()));
+                }
+            }
+        }
+        ticks
+    }
+
+    /// Returns all the blocks that changed since the last call.
+    pub fn take_changes(&mut self) -> Vec<BlockChange> {
+        let mut changes = Vec::with_capacity(self.changed.len());
+        for id in mem::take(&mut self.changed) {
+            let node = &mut self.nodes[id];
+            node.changed = false;
+            let block_entity = match node.kind {
+                NodeKind::Comparator { .. } => Some(BlockEntity::Comparator {
+                    output_strength: node.output_power,
+                }),
+                _ => None,
+            };
+            changes.push(BlockChange {
+                pos: node.pos,
+                block: node.to_block(),
+                block_entity,
+            });
+        }
+        changes
+    }
+
+    /// Handles a player using a lever or button. Returns false if there is no
+    /// lever or button at the position.
+    pub fn on_use_block(&mut self, pos: BlockPos) -> bool {
+        let id = match self.pos_map.get(&pos) {
+            Some(&id) => id,
+            None => return false,
+        };
+        match self.nodes[id].kind {
+            NodeKind::Lever => {
+                let powered = !self.nodes[id].powered;
+                self.set_node(id, powered, if powered { 15 } else { 0 });
+                true
+            }
+            NodeKind::Button => {
+                if !self.nodes[id].powered {
+                    self.set_node(id, true, 15);
+                    self.schedule_tick(id, 10, TickPriority::Normal);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let queues = self.scheduler.next_slot();
+        for queue in queues.iter() {
+            for &id in queue {
+                self.nodes[id].pending_tick = false;
+                self.tick_node(id);
+            }
+        }
+    }
+
+    fn schedule_tick(&mut self, id: NodeId, delay: u32, priority: TickPriority) {
+        self.nodes[id].pending_tick = true;
+        self.scheduler.schedule_tick(id, delay, priority);
+    }
+
+    fn mark_changed(&mut self, id: NodeId) {
+        if !self.nodes[id].changed {
+            self.nodes[id].changed = true;
+            self.changed.push(id);
+        }
+    }
+
+    /// Changes the state of a node and updates every node that depends on it.
+    fn set_node(&mut self, id: NodeId, powered: bool, output_power: u8) {
+        let node = &mut self.nodes[id];
+        let output_changed = node.output_power != output_power;
+        node.powered = powered;
+        node.output_power = output_power;
+        self.mark_changed(id);
+        if output_changed {
+            for i in 0..self.nodes[id].updates.len() {
+                let update = self.nodes[id].updates[i];
+                self.update_node(update);
+            }
+        }
+    }
+
+    /// Returns the highest power received through the default links and the side links.
+    fn get_inputs(&self, id: NodeId) -> (u8, u8) {
+        let mut default_power = 0;
+        let mut side_power = 0;
+        for link in &self.nodes[id].inputs {
+            let power = self.nodes[link.from]
+                .output_power
+                .saturating_sub(link.weight);
+            match link.ty {
+                LinkType::Default => default_power = default_power.max(power),
+                LinkType::Side => side_power = side_power.max(power),
+            }
+        }
+        (default_power, side_power)
+    }
+
+    fn comparator_input(&self, id: NodeId, input_power: u8) -> u8 {
+        match self.nodes[id].kind {
+            NodeKind::Comparator {
+                input_override: Some(input_override),
+                ..
+            } => input_override,
+            NodeKind::Comparator {
+                far_override: Some(far_override),
+                ..
+            } if input_power < 15 => far_override,
+            _ => input_power,
+        }
+    }
+
+    fn comparator_output(mode: ComparatorMode, input_power: u8, side_power: u8) -> u8 {
+        if mode == ComparatorMode::Subtract {
+            input_power.saturating_sub(side_power)
+        } else if input_power >= side_power {
+            input_power
+        } else {
+            0
+        }
+    }
+
+    fn comparator_should_be_powered(mode: ComparatorMode, input_power: u8, side_power: u8) -> bool {
+        if input_power == 0 {
+            false
+        } else if input_power > side_power {
+            true
+        } else {
+            side_power == input_power && mode == ComparatorMode::Compare
+        }
+    }
+
+    fn update_node(&mut self, id: NodeId) {
+        let node = &self.nodes[id];
+        let (input_power, side_power) = self.get_inputs(id);
+        match node.kind {
+            NodeKind::Repeater {
+                delay,
+                facing_diode,
+            } => {
+                let should_be_locked = side_power > 0;
+                if node.locked != should_be_locked {
+                    self.nodes[id].locked = should_be_locked;
+                    self.mark_changed(id);
+                }
+                let node = &self.nodes[id];
+                if !node.locked && !node.pending_tick {
+                    let should_be_powered = input_power > 0;
+                    if should_be_powered != node.powered {
+                        let priority = if facing_diode {
+                            TickPriority::Highest
+                        } else if !should_be_powered {
+                            TickPriority::Higher
+                        } else {
+                            TickPriority::High
+                        };
+                        self.schedule_tick(id, delay as u32, priority);
+                    }
+                }
+            }
+            NodeKind::Comparator {
+                mode, facing_diode, ..
+            } => {
+                if node.pending_tick {
+                    return;
+                }
+                let input_power = self.comparator_input(id, input_power);
+                let output_power = Compiler::comparator_output(mode, input_power, side_power);
+                let should_be_powered =
+                    Compiler::comparator_should_be_powered(mode, input_power, side_power);
+                if output_power != node.output_power || node.powered != should_be_powered {
+                    let priority = if facing_diode {
+                        TickPriority::High
+                    } else {
+                        TickPriority::Normal
+                    };
+                    self.schedule_tick(id, 1, priority);
+                }
+            }
+            NodeKind::Torch => {
+                let lit = node.powered;
+                if lit == (input_power > 0) && !node.pending_tick {
+                    self.schedule_tick(id, 1, TickPriority::Normal);
+                }
+            }
+            NodeKind::Lamp => {
+                let should_be_lit = input_power > 0;
+                if node.powered && !should_be_lit {
+                    self.schedule_tick(id, 2, TickPriority::Normal);
+                } else if !node.powered && should_be_lit {
+                    self.nodes[id].powered = true;
+                    self.mark_changed(id);
+                }
+            }
+            NodeKind::Wire => {
+                if node.output_power != input_power {
+                    self.nodes[id].output_power = input_power;
+                    self.mark_changed(id);
+                }
+            }
+            NodeKind::Lever | NodeKind::Button | NodeKind::Constant => {}
+        }
+    }
+
+    fn tick_node(&mut self, id: NodeId) {
+        let node = &self.nodes[id];
+        let (input_power, side_power) = self.get_inputs(id);
+        match node.kind {
+            NodeKind::Repeater { .. } => {
+                if node.locked {
+                    return;
+                }
+                let should_be_powered = input_power > 0;
+                if node.powered && !should_be_powered {
+                    self.set_node(id, false, 0);
+                } else if !node.powered {
+                    self.set_node(id, true, 15);
+                }
+            }
+            NodeKind::Comparator { mode, .. } => {
+                let input_power = self.comparator_input(id, input_power);
+                let output_power = Compiler::comparator_output(mode, input_power, side_power);
+                if output_power != node.output_power || mode == ComparatorMode::Compare {
+                    let should_be_powered =
+                        Compiler::comparator_should_be_powered(mode, input_power, side_power);
+                    self.set_node(id, should_be_powered, output_power);
+                }
+            }
+            NodeKind::Torch => {
+                let should_be_off = input_power > 0;
+                let lit = node.powered;
+                if lit && should_be_off {
+                    self.set_node(id, false, 0);
+                } else if !lit && !should_be_off {
+                    self.set_node(id, true, 15);
+                }
+            }
+            NodeKind::Lamp => {
+                if node.powered && input_power == 0 {
+                    self.nodes[id].powered = false;
+                    self.mark_changed(id);
+                }
+            }
+            NodeKind::Button => {
+                if node.powered {
+                    self.set_node(id, false, 0);
+                }
+            }
+            NodeKind::Lever | NodeKind::Constant | NodeKind::Wire => {}
+        }
+    }
+}
+
+#[test]
+fn tick_scheduler_test() {
+    let mut scheduler = TickScheduler::default();
+    scheduler.schedule_tick(0, 2, TickPriority::Normal);
+    scheduler.schedule_tick(1, 2, TickPriority::Highest);
+    scheduler.schedule_tick(2, 1, TickPriority::Normal);
+    assert_eq!(scheduler.next_slot()[3], vec![2]);
+    let queues = scheduler.next_slot();
+    assert_eq!(queues[0], vec![1]);
+    assert_eq!(queues[3], vec![0]);
+}
+
+/// A block in a test circuit, given by its name and properties
+#[cfg(test)]
+type CircuitBlock<'a> = (BlockPos, &'a str, &'a [(&'a str, &'a str)]);
+
+/// Builds a circuit in a new plot, flicks the lever at `lever` and returns the blocks
+/// at `outputs` after each of the following ticks. The circuit is simulated with the
+/// redpiler if `compile` is set. Placing blocks doesn't update them, so the circuit has
+/// to be given in a state where nothing would change.
+#[cfg(test)]
+fn simulate_circuit(
+    blocks: &[CircuitBlock],
+    lever: BlockPos,
+    outputs: &[BlockPos],
+    ticks: usize,
+    compile: bool,
+) -> Vec<Vec<Block>> {
+    let mut plot = Plot::load_headless(0, 0, None).unwrap();
+    for &(pos, name, properties) in blocks {
+        let mut block = Block::from_name(name).unwrap();
+        for (key, value) in properties {
+            block.set_property(key, value);
+        }
+        plot.set_block(pos, block);
+    }
+    if compile {
+        assert!(Compiler::compile(&plot).node_count() > 0);
+        plot.compile_redpiler();
+    }
+    plot.use_block(lever);
+    (0..ticks)
+        .map(|_| {
+            plot.tick();
+            plot.flush_redpiler();
+            outputs.iter().map(|&pos| plot.get_block(pos)).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn redpiler_repeater_test() {
+    let lever = BlockPos::new(5, 10, 5);
+    let lamp = BlockPos::new(8, 10, 5);
+    let blocks: &[CircuitBlock] = &[
+        (lever, "lever", &[("face", "floor")]),
+        (
+            BlockPos::new(6, 10, 5),
+            "repeater",
+            &[("facing", "west"), ("delay", "3")],
+        ),
+        (
+            BlockPos::new(7, 10, 5),
+            "repeater",
+            &[("facing", "west"), ("delay", "2")],
+        ),
+        (lamp, "redstone_lamp", &[]),
+    ];
+    let outputs = [BlockPos::new(6, 10, 5), BlockPos::new(7, 10, 5), lamp];
+    let interpreted = simulate_circuit(blocks, lever, &outputs, 8, false);
+    assert_eq!(
+        simulate_circuit(blocks, lever, &outputs, 8, true),
+        interpreted
+    );
+    // The lamp turns on after the 5 ticks of delay
+    let lit: Vec<bool> = interpreted
+        .iter()
+        .map(|blocks| blocks[2] == Block::RedstoneLamp(true))
+        .collect();
+    assert_eq!(lit, [false, false, false, false, true, true, true, true]);
+}
+
+#[test]
+fn redpiler_torch_chain_test() {
+    let lever = BlockPos::new(5, 10, 8);
+    let lamp = BlockPos::new(12, 10, 8);
+    let blocks: &[CircuitBlock] = &[
+        (lever, "lever", &[("face", "floor")]),
+        (BlockPos::new(6, 10, 8), "repeater", &[("facing", "west")]),
+        (BlockPos::new(7, 10, 8), "stone", &[]),
+        (
+            BlockPos::new(8, 10, 8),
+            "redstone_wall_torch",
+            &[("facing", "east")],
+        ),
+        (
+            BlockPos::new(9, 10, 8),
+            "repeater",
+            &[("facing", "west"), ("powered", "true")],
+        ),
+        (BlockPos::new(10, 10, 8), "stone", &[]),
+        (
+            BlockPos::new(11, 10, 8),
+            "redstone_wall_torch",
+            &[("facing", "east"), ("lit", "false")],
+        ),
+        (lamp, "redstone_lamp", &[]),
+    ];
+    let outputs = [BlockPos::new(8, 10, 8), BlockPos::new(11, 10, 8), lamp];
+    let interpreted = simulate_circuit(blocks, lever, &outputs, 8, false);
+    assert_eq!(
+        simulate_circuit(blocks, lever, &outputs, 8, true),
+        interpreted
+    );
+    // Two inverters in a row pass the signal on to the lamp
+    assert_eq!(interpreted[0][2], Block::RedstoneLamp(false));
+    assert_eq!(interpreted[7][2], Block::RedstoneLamp(true));
+}
@@ -1,27 +1,72 @@
+use crate::config::{CONFIG, CONFIG_PATH};
 use crate::network::packets::clientbound::{
-    C00DisconnectLogin, C00Response, C01Pong, C02LoginSuccess, C03SetCompression, C15WindowItems,
-    C19PluginMessageBrand, C26JoinGame, C34PlayerInfo, C34PlayerInfoAddPlayer,
-    C36PlayerPositionAndLook, C40HeldItemChange, C4FTimeUpdate, ClientBoundPacket,
+    C00DisconnectLogin, C00Response, C01EncryptionRequest, C01Pong, C02LoginSuccess,
+    C03SetCompression, C15WindowItems, C19PluginMessageBrand, C26JoinGame, C34PlayerInfo,
+    C34PlayerInfoAddPlayer, C36PlayerPositionAndLook, C40HeldItemChange, C4FTimeUpdate,
+    ClientBoundPacket,
 };
 use crate::network::packets::serverbound::{
-    S00Handshake, S00LoginStart, S00Ping, ServerBoundPacket,
+    S00Handshake, S00LoginStart, S00Ping, S01EncryptionResponse, ServerBoundPacket,
 };
 use crate::network::packets::{PacketDecoder, SlotData};
-use crate::network::{NetworkServer, NetworkState};
-//use crate::permissions::Permissions;
+use crate::network::session::{self, GameProfile, ProfileProperty};
+use crate::network::{NetworkClient, NetworkServer, NetworkState};
+use crate::permissions::{PERMISSIONS, PERMISSIONS_PATH};
 use crate::player::Player;
 use crate::plot::{self, commands::DECLARE_COMMANDS, Plot};
 use backtrace::Backtrace;
 use bus::{Bus, BusReader};
 use fern::colors::{Color, ColoredLevelConfig};
 use log::{debug, error, info, warn};
-use serde::{Deserialize, Serialize};
+use rsa::pkcs8::EncodePublicKey;
+use rsa::rand_core::OsRng;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
 use serde_json::json;
+use std::collections::HashMap;
 use std::fs;
-use std::fs::read_to_string;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
 use std::time::{Duration, Instant};
-use toml::Value;
+
+/// The key pair used to exchange the shared secret with clients in online mode
+struct RsaKeyPair {
+    private_key: RsaPrivateKey,
+    /// The public key in DER format, which is sent to clients and hashed into the
+    /// server id
+    public_key_der: Vec<u8>,
+}
+
+impl RsaKeyPair {
+    fn generate() -> RsaKeyPair {
+        let private_key =
+            RsaPrivateKey::new(&mut OsRng, 1024).expect("Unable to generate an RSA key pair");
+        let public_key_der = private_key
+            .to_public_key()
+            .to_public_key_der()
+            .unwrap()
+            .into_vec();
+        RsaKeyPair {
+            private_key,
+            public_key_der,
+        }
+    }
+}
+
+/// Crashed plots aren't restarted if they crash again this soon after the last crash,
+/// since they would most likely just keep crashing.
+const CRASH_RESTART_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Remembers that a plot crashed at `now`, returning whether it had already crashed
+/// during the cooldown.
+fn record_crash(
+    crashed_plots: &mut HashMap<(i32, i32), Instant>,
+    plot: (i32, i32),
+    now: Instant,
+) -> bool {
+    // Crashes from before the cooldown don't matter anymore
+    crashed_plots.retain(|_, last_crash| now.duration_since(*last_crash) < CRASH_RESTART_COOLDOWN);
+    crashed_plots.insert(plot, now).is_some()
+}
 
 /// Messages get passed between plot threads, the server thread, and the networking thread.
 /// These messages are used to communicate when a player joins, leaves, or moves into another plot,
@@ -34,6 +79,9 @@ pub enum Message {
     PlayerLeavePlot(Player),
     PlayerTeleportOther(Player, String),
     PlotUnload(i32, i32),
+    /// Sent instead of `PlotUnload` when a plot thread panics, along with the players
+    /// that were in the plot and whether the plot keeps ticking while nobody is in it.
+    PlotCrashed(i32, i32, Vec<Player>, bool),
     Shutdown,
 }
 
@@ -55,7 +103,7 @@ pub enum PrivMessage {
 pub struct PlayerJoinInfo {
     pub username: String,
     pub uuid: u128,
-    skin: Option<String>,
+    pub properties: Vec<ProfileProperty>,
 }
 
 #[derive(Debug, Clone)]
@@ -64,15 +112,7 @@ struct PlayerListEntry {
     plot_z: i32,
     username: String,
     uuid: u128,
-    skin: Option<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ServerConfig {
-    bind_address: String,
-    motd: String,
-    chat_format: String,
-    max_players: i64,
+    properties: Vec<ProfileProperty>,
 }
 
 struct PlotListEntry {
@@ -84,14 +124,19 @@ struct PlotListEntry {
 /// This represents a minecraft server
 pub struct MinecraftServer {
     network: NetworkServer,
-    config: ServerConfig,
     broadcaster: Bus<BroadcastMessage>,
     debug_plot_receiver: BusReader<BroadcastMessage>,
     receiver: Receiver<Message>,
     plot_sender: Sender<Message>,
-    //permissions: Arc<Mutex<Permissions>>,
     online_players: Vec<PlayerListEntry>,
     running_plots: Vec<PlotListEntry>,
+    /// When each plot that crashed last crashed
+    crashed_plots: HashMap<(i32, i32), Instant>,
+    /// The key pair used for encryption, only generated in online mode
+    rsa_key_pair: Option<RsaKeyPair>,
+    /// Session server responses are sent here from the threads making the requests
+    auth_sender: Sender<(u32, Result<Option<GameProfile>, String>)>,
+    auth_receiver: Receiver<(u32, Result<Option<GameProfile>, String>)>,
 }
 
 impl MinecraftServer {
@@ -137,60 +182,27 @@ impl MinecraftServer {
 
         plot::database::init();
 
-        // Load config
-        let default_config = ServerConfig {
-            bind_address: "0.0.0.0:25565".to_string(),
-            motd: "Minecraft High Performace Redstone Server".to_string(),
-            chat_format: "<{username}> {message}".to_string(),
-            max_players: 99999,
-        };
-        let config: ServerConfig =
-            toml::from_str(&read_to_string("Config.toml").unwrap_or_else(|_| {
-                let config_string = toml::to_string(&default_config).unwrap();
-                fs::write("Config.toml", &config_string);
-                config_string
-            }))
-            .unwrap_or_else(|_| {
-                let config_string = read_to_string("Config.toml").unwrap();
-                let config_map = config_string.parse::<Value>().unwrap();
-                let merged_config = ServerConfig {
-                    bind_address: config_map
-                        .get("bind_address")
-                        .map(toml::value::Value::as_str)
-                        .map(|pp| pp.unwrap_or(&default_config.bind_address))
-                        .unwrap_or(&default_config.bind_address)
-                        .to_string(),
-                    motd: config_map
-                        .get("motd")
-                        .map(toml::value::Value::as_str)
-                        .map(|pp| pp.unwrap_or(&default_config.motd))
-                        .unwrap_or(&default_config.motd)
-                        .to_string(),
-                    chat_format: config_map
-                        .get("chat_format")
-                        .map(toml::value::Value::as_str)
-                        .map(|pp| pp.unwrap_or(&default_config.chat_format))
-                        .unwrap_or(&default_config.chat_format)
-                        .to_string(),
-                    max_players: config_map
-                        .get("max_players")
-                        .map(toml::value::Value::as_integer)
-                        .map(|pp| pp.unwrap_or(default_config.max_players))
-                        .unwrap_or(default_config.max_players),
-                };
-                let config_string = toml::to_string(&merged_config).unwrap();
-                fs::write("Config.toml", &config_string);
-                merged_config
-            });
+        lazy_static::initialize(&CONFIG);
+        CONFIG.save_if_missing(CONFIG_PATH);
+        // Load the permission groups now so that mistakes in them show up right away
+        lazy_static::initialize(&PERMISSIONS);
+        PERMISSIONS.save_if_missing(PERMISSIONS_PATH);
+
+        let bind_addr = CONFIG.bind_address.clone();
 
-        let bind_addr = config.bind_address.clone();
+        let rsa_key_pair = if CONFIG.online_mode {
+            info!("Generating RSA key pair...");
+            Some(RsaKeyPair::generate())
+        } else {
+            None
+        };
 
-        //let permissions = Arc::new(Mutex::new(Permissions::new(&config)));
         // Create thread messaging structs
         let (plot_tx, server_rx) = mpsc::channel();
         let mut bus = Bus::new(100);
         let debug_plot_receiver = bus.add_rx();
         let ctrl_handler_sender = plot_tx.clone();
+        let (auth_sender, auth_receiver) = mpsc::channel();
 
         ctrlc::set_handler(move || {
             ctrl_handler_sender.send(Message::Shutdown).unwrap();
@@ -200,33 +212,22 @@ impl MinecraftServer {
         // Create server struct
         let mut server = MinecraftServer {
             network: NetworkServer::new(bind_addr),
-            config,
             broadcaster: bus,
             receiver: server_rx,
             plot_sender: plot_tx,
             debug_plot_receiver,
-            // permissions,
             online_players: Vec::new(),
             running_plots: Vec::new(),
+            crashed_plots: HashMap::new(),
+            rsa_key_pair,
+            auth_sender,
+            auth_receiver,
         };
 
-        // Load the spawn area plot on server start
-        // This plot should be always active
-        let (spawn_tx, spawn_rx) = mpsc::channel();
-        Plot::load_and_run(
-            0,
-            0,
-            server.broadcaster.add_rx(),
-            server.plot_sender.clone(),
-            spawn_rx,
-            true,
-            None,
-        );
-        server.running_plots.push(PlotListEntry {
-            plot_x: 0,
-            plot_z: 0,
-            priv_message_sender: spawn_tx,
-        });
+        // Load the plots which keep ticking while nobody is in them, such as spawn
+        for (plot_x, plot_z) in plot::database::get_keep_ticking_plots() {
+            server.load_plot(plot_x, plot_z, None);
+        }
 
         info!("Done! Start took {:?}", start_time.elapsed());
 
@@ -254,14 +255,61 @@ impl MinecraftServer {
         }
     }
 
+    fn handle_plot_crash(
+        &mut self,
+        plot_x: i32,
+        plot_z: i32,
+        players: Vec<Player>,
+        keep_ticking: bool,
+    ) {
+        self.handle_plot_unload(plot_x, plot_z);
+        let crashed_recently =
+            record_crash(&mut self.crashed_plots, (plot_x, plot_z), Instant::now());
+        let is_spawn = plot::plot_at(0, 0) == (plot_x, plot_z);
+        for mut player in players {
+            if is_spawn && crashed_recently {
+                // Sending them back would most likely crash the spawn plot again
+                player.save();
+                player.kick(
+                    json!({
+                        "text": "The spawn plot has crashed!",
+                        "color": "red"
+                    })
+                    .to_string(),
+                );
+                self.handle_message(Message::PlayerLeft(player.uuid));
+                continue;
+            }
+            player.send_error_message(
+                "The plot you were in has crashed, you have been teleported to the spawn plot.",
+            );
+            let (x, y, z) = plot::spawn_pos();
+            player.teleport(x, y, z);
+            self.send_player_to_plot(player, false);
+        }
+
+        let plot_loaded = self
+            .running_plots
+            .iter()
+            .any(|p| p.plot_x == plot_x && p.plot_z == plot_z);
+        if CONFIG.restart_crashed_plots && !crashed_recently && !plot_loaded && keep_ticking {
+            info!("Restarting plot {},{} from its last save", plot_x, plot_z);
+            self.load_plot(plot_x, plot_z, None);
+        }
+    }
+
     fn graceful_shutdown(&mut self) {
         info!("Commencing graceful shutdown...");
         self.broadcaster.broadcast(BroadcastMessage::Shutdown);
         // Wait for all plots to save and unload
         while !self.running_plots.is_empty() {
             while let Ok(message) = self.receiver.try_recv() {
-                if let Message::PlotUnload(plot_x, plot_z) = message {
-                    self.handle_plot_unload(plot_x, plot_z);
+                match message {
+                    Message::PlotUnload(plot_x, plot_z)
+                    | Message::PlotCrashed(plot_x, plot_z, _, _) => {
+                        self.handle_plot_unload(plot_x, plot_z)
+                    }
+                    _ => {}
                 }
                 std::thread::sleep(Duration::from_millis(2));
             }
@@ -270,8 +318,7 @@ impl MinecraftServer {
     }
 
     fn send_player_to_plot(&mut self, player: Player, new_entry: bool) {
-        let plot_x = (player.x as i32) >> 8;
-        let plot_z = (player.z as i32) >> 8;
+        let (plot_x, plot_z) = plot::plot_at(player.x as i32, player.z as i32);
 
         if new_entry {
             let player_list_entry = PlayerListEntry {
@@ -279,7 +326,7 @@ impl MinecraftServer {
                 plot_z,
                 username: player.username.clone(),
                 uuid: player.uuid,
-                skin: None,
+                properties: player.properties.clone(),
             };
             self.online_players.push(player_list_entry);
         } else {
@@ -291,21 +338,7 @@ impl MinecraftServer {
             .iter()
             .any(|p| p.plot_x == plot_x && p.plot_z == plot_z);
         if !plot_loaded {
-            let (priv_tx, priv_rx) = mpsc::channel();
-            Plot::load_and_run(
-                plot_x,
-                plot_z,
-                self.broadcaster.add_rx(),
-                self.plot_sender.clone(),
-                priv_rx,
-                false,
-                Some(player),
-            );
-            self.running_plots.push(PlotListEntry {
-                plot_x,
-                plot_z,
-                priv_message_sender: priv_tx,
-            });
+            self.load_plot(plot_x, plot_z, Some(player));
         } else {
             let plot_list_entry = self
                 .running_plots
@@ -318,6 +351,218 @@ impl MinecraftServer {
         }
     }
 
+    fn load_plot(&mut self, plot_x: i32, plot_z: i32, initial_player: Option<Player>) {
+        let (priv_tx, priv_rx) = mpsc::channel();
+        Plot::load_and_run(
+            plot_x,
+            plot_z,
+            self.broadcaster.add_rx(),
+            self.plot_sender.clone(),
+            priv_rx,
+            initial_player,
+        );
+        self.running_plots.push(PlotListEntry {
+            plot_x,
+            plot_z,
+            priv_message_sender: priv_tx,
+        });
+    }
+
+    fn disconnect_login(client: &mut NetworkClient, reason: &str) {
+        let disconnect = C00DisconnectLogin {
+            reason: json!({ "text": reason }).to_string(),
+        }
+        .encode();
+        client.send_packet(&disconnect);
+        client.close_connection();
+    }
+
+    fn handle_encryption_response(&mut self, client_idx: usize, response: S01EncryptionResponse) {
+        let rsa_key_pair = match &self.rsa_key_pair {
+            Some(rsa_key_pair) => rsa_key_pair,
+            None => return,
+        };
+        let client = &mut self.network.handshaking_clients[client_idx];
+        let private_key = &rsa_key_pair.private_key;
+        let verify_token = private_key.decrypt(Pkcs1v15Encrypt, &response.verify_token);
+        let shared_secret = private_key.decrypt(Pkcs1v15Encrypt, &response.shared_secret);
+        // Every failure gets the same message so clients can't tell why it failed
+        let shared_secret = match (client.verify_token.take(), verify_token, shared_secret) {
+            (Some(expected), Ok(verify_token), Ok(shared_secret))
+                if verify_token == expected && shared_secret.len() == 16 =>
+            {
+                let mut key = [0; 16];
+                key.copy_from_slice(&shared_secret);
+                key
+            }
+            _ => {
+                warn!("A player failed the encryption handshake");
+                MinecraftServer::disconnect_login(client, "Failed to verify encryption!");
+                return;
+            }
+        };
+        client.enable_encryption(&shared_secret);
+
+        let server_hash = session::server_hash(&shared_secret, &rsa_key_pair.public_key_der);
+        let username = client.username.clone().unwrap_or_default();
+        let client_id = client.id;
+        let auth_sender = self.auth_sender.clone();
+        // The request is made on another thread so that it doesn't hold up the server
+        thread::spawn(move || {
+            let result = session::has_joined(&CONFIG.session_server, &username, &server_hash);
+            let _ = auth_sender.send((client_id, result));
+        });
+    }
+
+    fn handle_auth_result(&mut self, client_id: u32, result: Result<Option<GameProfile>, String>) {
+        let client_idx = match self
+            .network
+            .handshaking_clients
+            .iter()
+            .position(|c| c.id == client_id)
+        {
+            Some(client_idx) => client_idx,
+            None => return,
+        };
+        let client = &mut self.network.handshaking_clients[client_idx];
+        match result {
+            Ok(Some(profile)) => {
+                if let Some(uuid) = profile.uuid() {
+                    client.username = Some(profile.name);
+                    self.complete_login(client_idx, uuid, profile.properties);
+                } else {
+                    warn!("The session server sent an invalid uuid: {}", profile.id);
+                    MinecraftServer::disconnect_login(client, "Failed to verify username!");
+                }
+            }
+            Ok(None) => {
+                MinecraftServer::disconnect_login(client, "Failed to verify username!");
+            }
+            Err(err) => {
+                error!("Unable to reach the session server: {}", err);
+                MinecraftServer::disconnect_login(
+                    client,
+                    "Authentication servers are down. Please try again later, sorry!",
+                );
+            }
+        }
+    }
+
+    /// Finishes logging in a client which has been authenticated and moves it to a plot.
+    fn complete_login(&mut self, client_idx: usize, uuid: u128, properties: Vec<ProfileProperty>) {
+        let clients = &mut self.network.handshaking_clients;
+        let set_compression = C03SetCompression { threshold: 256 }.encode();
+        clients[client_idx].send_packet(&set_compression);
+        clients[client_idx].compressed = true;
+        let username = clients[client_idx].username.clone().unwrap_or_default();
+
+        let login_success = C02LoginSuccess {
+            uuid,
+            username: username.clone(),
+        }
+        .encode();
+        clients[client_idx].send_packet(&login_success);
+
+        clients[client_idx].state = NetworkState::Play;
+        let mut client = clients.remove(client_idx);
+
+        let join_game = C26JoinGame {
+            entity_id: client.id as i32,
+            gamemode: 1,
+            dimention: 0,
+            hash_seed: 0,
+            max_players: 0,
+            level_type: "flat".to_string(),
+            view_distance: 8,
+            reduced_debug_info: false,
+            enable_respawn_screen: false,
+        }
+        .encode();
+        client.send_packet(&join_game);
+
+        let brand = C19PluginMessageBrand {
+            brand: "Minecraft High Performace Redstone".to_string(),
+        }
+        .encode();
+        client.send_packet(&brand);
+
+        plot::database::update_player_name(uuid, &username);
+        let mut player = Player::load_player(uuid, username, client);
+        player.properties = properties;
+
+        let player_pos_and_look = C36PlayerPositionAndLook {
+            x: player.x,
+            y: player.y,
+            z: player.z,
+            yaw: player.yaw,
+            pitch: player.pitch,
+            flags: 0,
+            teleport_id: 0,
+        }
+        .encode();
+        player.client.send_packet(&player_pos_and_look);
+
+        let mut add_player_list = Vec::new();
+        for player in &self.online_players {
+            add_player_list.push(C34PlayerInfoAddPlayer {
+                uuid: player.uuid,
+                name: player.username.clone(),
+                display_name: None,
+                gamemode: 1,
+                ping: 0,
+                properties: player.properties.iter().cloned().map(Into::into).collect(),
+            });
+        }
+        add_player_list.push(C34PlayerInfoAddPlayer {
+            uuid: player.uuid,
+            name: player.username.clone(),
+            display_name: None,
+            gamemode: 1,
+            ping: 0,
+            properties: player.properties.iter().cloned().map(Into::into).collect(),
+        });
+        let player_info = C34PlayerInfo::AddPlayer(add_player_list).encode();
+        player.client.send_packet(&player_info);
+
+        let slot_data: Vec<Option<SlotData>> = player
+            .inventory
+            .iter()
+            .map(|op| {
+                op.as_ref().map(|item| SlotData {
+                    item_count: item.count as i8,
+                    item_id: item.item_type.get_id() as i32,
+                    nbt: item.nbt.clone(),
+                })
+            })
+            .collect();
+        let window_items = C15WindowItems {
+            window_id: 0,
+            slot_data,
+        }
+        .encode();
+        player.client.send_packet(&window_items);
+
+        let held_item_change = C40HeldItemChange {
+            slot: player.selected_slot as i8,
+        }
+        .encode();
+        player.client.send_packet(&held_item_change);
+
+        player.client.send_packet(&DECLARE_COMMANDS);
+
+        let time_update = C4FTimeUpdate {
+            world_age: 0,
+            // Noon
+            time_of_day: -6000,
+        }
+        .encode();
+        player.client.send_packet(&time_update);
+
+        self.plot_sender
+            .send(Message::PlayerJoined(player))
+            .unwrap();
+    }
+
     fn handle_packet(&mut self, client: usize, packet: PacketDecoder) {
         let clients = &mut self.network.handshaking_clients;
         match clients[client].state {
@@ -355,12 +600,12 @@ impl MinecraftServer {
                                     "protocol": 578
                                 },
                                 "players": {
-                                    "max": self.config.max_players,
+                                    "max": CONFIG.max_players,
                                     "online": self.online_players.len(),
                                     "sample": []
                                 },
                                 "description": {
-                                    "text": self.config.motd
+                                    "text": CONFIG.motd
                                 }
                             })
                             .to_string(),
@@ -379,125 +624,31 @@ impl MinecraftServer {
                     _ => {}
                 }
             }
-            NetworkState::Login => {
-                if packet.packet_id == 0x00 {
+            NetworkState::Login => match packet.packet_id {
+                0x00 => {
                     let login_start = S00LoginStart::decode(packet).unwrap();
-                    clients[client].username = Some(login_start.name);
-                    let set_compression = C03SetCompression { threshold: 256 }.encode();
-                    clients[client].send_packet(&set_compression);
-                    clients[client].compressed = true;
-                    let username = if let Some(name) = &clients[client].username {
-                        name.clone()
+                    clients[client].username = Some(login_start.name.clone());
+                    if let Some(rsa_key_pair) = &self.rsa_key_pair {
+                        let verify_token: [u8; 4] = rand::random();
+                        clients[client].verify_token = Some(verify_token);
+                        let encryption_request = C01EncryptionRequest {
+                            server_id: String::new(),
+                            public_key: rsa_key_pair.public_key_der.clone(),
+                            verify_token: verify_token.to_vec(),
+                        }
+                        .encode();
+                        clients[client].send_packet(&encryption_request);
                     } else {
-                        Default::default()
-                    };
-                    let uuid = Player::generate_offline_uuid(&username);
-
-                    let login_success = C02LoginSuccess {
-                        uuid,
-                        username: username.clone(),
-                    }
-                    .encode();
-                    clients[client].send_packet(&login_success);
-
-                    clients[client].state = NetworkState::Play;
-                    let mut client = clients.remove(client);
-
-                    let join_game = C26JoinGame {
-                        entity_id: client.id as i32,
-                        gamemode: 1,
-                        dimention: 0,
-                        hash_seed: 0,
-                        max_players: 0,
-                        level_type: "flat".to_string(),
-                        view_distance: 8,
-                        reduced_debug_info: false,
-                        enable_respawn_screen: false,
-                    }
-                    .encode();
-                    client.send_packet(&join_game);
-
-                    let brand = C19PluginMessageBrand {
-                        brand: "Minecraft High Performace Redstone".to_string(),
-                    }
-                    .encode();
-                    client.send_packet(&brand);
-
-                    let mut player = Player::load_player(uuid, username.clone(), client);
-
-                    let player_pos_and_look = C36PlayerPositionAndLook {
-                        x: player.x,
-                        y: player.y,
-                        z: player.z,
-                        yaw: player.yaw,
-                        pitch: player.pitch,
-                        flags: 0,
-                        teleport_id: 0,
-                    }
-                    .encode();
-                    player.client.send_packet(&player_pos_and_look);
-
-                    let mut add_player_list = Vec::new();
-                    for player in &self.online_players {
-                        add_player_list.push(C34PlayerInfoAddPlayer {
-                            uuid: player.uuid,
-                            name: player.username.clone(),
-                            display_name: None,
-                            gamemode: 1,
-                            ping: 0,
-                            properties: Vec::new(),
-                        });
-                    }
-                    add_player_list.push(C34PlayerInfoAddPlayer {
-                        uuid: player.uuid,
-                        name: player.username.clone(),
-                        display_name: None,
-                        gamemode: 1,
-                        ping: 0,
-                        properties: Vec::new(),
-                    });
-                    let player_info = C34PlayerInfo::AddPlayer(add_player_list).encode();
-                    player.client.send_packet(&player_info);
-
-                    let slot_data: Vec<Option<SlotData>> = player
-                        .inventory
-                        .iter()
-                        .map(|op| {
-                            op.as_ref().map(|item| SlotData {
-                                item_count: item.count as i8,
-                                item_id: item.item_type.get_id() as i32,
-                                nbt: item.nbt.clone(),
-                            })
-                        })
-                        .collect();
-                    let window_items = C15WindowItems {
-                        window_id: 0,
-                        slot_data,
-                    }
-                    .encode();
-                    player.client.send_packet(&window_items);
-
-                    let held_item_change = C40HeldItemChange {
-                        slot: player.selected_slot as i8,
-                    }
-                    .encode();
-                    player.client.send_packet(&held_item_change);
-
-                    player.client.send_packet(&DECLARE_COMMANDS);
-
-                    let time_update = C4FTimeUpdate {
-                        world_age: 0,
-                        // Noon
-                        time_of_day: -6000,
+                        let uuid = Player::generate_offline_uuid(&login_start.name);
+                        self.complete_login(client, uuid, Vec::new());
                     }
-                    .encode();
-                    player.client.send_packet(&time_update);
-
-                    self.plot_sender
-                        .send(Message::PlayerJoined(player))
-                        .unwrap();
                 }
-            }
+                0x01 => {
+                    let encryption_response = S01EncryptionResponse::decode(packet).unwrap();
+                    self.handle_encryption_response(client, encryption_response);
+                }
+                _ => {}
+            },
             NetworkState::Play => {}
         }
     }
@@ -510,7 +661,7 @@ impl MinecraftServer {
                 let player_join_info = PlayerJoinInfo {
                     username: player.username.clone(),
                     uuid: player.uuid,
-                    skin: None,
+                    properties: player.properties.clone(),
                 };
                 self.broadcaster
                     .broadcast(BroadcastMessage::PlayerJoinedInfo(player_join_info));
@@ -525,10 +676,13 @@ impl MinecraftServer {
                     .broadcast(BroadcastMessage::PlayerLeft(uuid));
             }
             Message::PlotUnload(plot_x, plot_z) => self.handle_plot_unload(plot_x, plot_z),
+            Message::PlotCrashed(plot_x, plot_z, players, keep_ticking) => {
+                self.handle_plot_crash(plot_x, plot_z, players, keep_ticking)
+            }
             Message::ChatInfo(username, message) => {
                 self.broadcaster.broadcast(BroadcastMessage::Chat(
                     json!({
-                        "text": self.config.chat_format
+                        "text": CONFIG.chat_format
                             .replace("{username}", &username)
                             .replace("{message}", &message)
                     })
@@ -586,6 +740,9 @@ impl MinecraftServer {
             self.handle_message(message);
         }
         self.network.update();
+        while let Ok((client_id, result)) = self.auth_receiver.try_recv() {
+            self.handle_auth_result(client_id, result);
+        }
         for client in 0..self.network.handshaking_clients.len() {
             let packets: Vec<PacketDecoder> = self.network.handshaking_clients[client]
                 .packets
@@ -597,3 +754,67 @@ impl MinecraftServer {
         }
     }
 }
+
+#[test]
+fn plot_crash_test() {
+    let (plot_sender, receiver) = mpsc::channel();
+    let mut broadcaster = Bus::new(100);
+    let debug_plot_receiver = broadcaster.add_rx();
+    let (auth_sender, auth_receiver) = mpsc::channel();
+    let mut server = MinecraftServer {
+        network: NetworkServer::new("127.0.0.1:0".to_owned()),
+        broadcaster,
+        debug_plot_receiver,
+        receiver,
+        plot_sender,
+        online_players: Vec::new(),
+        running_plots: Vec::new(),
+        crashed_plots: HashMap::new(),
+        rsa_key_pair: None,
+        auth_sender,
+        auth_receiver,
+    };
+    // Stands in for the spawn plot
+    let (spawn_sender, spawn_receiver) = mpsc::channel();
+    let (spawn_x, spawn_z) = plot::plot_at(0, 0);
+    server.running_plots.push(PlotListEntry {
+        plot_x: spawn_x,
+        plot_z: spawn_z,
+        priv_message_sender: spawn_sender,
+    });
+    let is_loaded = |server: &MinecraftServer| {
+        server
+            .running_plots
+            .iter()
+            .any(|p| (p.plot_x, p.plot_z) == (1, 0))
+    };
+
+    // Plots that stop ticking without players aren't restarted
+    let (client, _remote) = NetworkClient::connect_local(0);
+    let player = Player::load_player(1, "crash_test".to_owned(), client);
+    server.handle_plot_crash(1, 0, vec![player], false);
+    match spawn_receiver.try_recv() {
+        Ok(PrivMessage::PlayerEnterPlot(player)) => {
+            assert_eq!((player.x, player.y, player.z), plot::spawn_pos())
+        }
+        _ => panic!("The player wasn't sent to the spawn plot"),
+    }
+    assert!(!is_loaded(&server));
+}
+
+#[test]
+fn crash_cooldown_test() {
+    let mut crashed_plots = HashMap::new();
+    let start = Instant::now();
+    assert!(!record_crash(&mut crashed_plots, (1, 0), start));
+    assert!(!record_crash(&mut crashed_plots, (2, 0), start));
+
+    // Crashing again during the cooldown doesn't restart it
+    let soon = start + Duration::from_secs(30);
+    assert!(record_crash(&mut crashed_plots, (1, 0), soon));
+
+    // Crashes from before the cooldown are forgotten
+    let later = soon + CRASH_RESTART_COOLDOWN + Duration::from_secs(1);
+    assert!(!record_crash(&mut crashed_plots, (1, 0), later));
+    assert_eq!(crashed_plots.len(), 1);
+}